@@ -0,0 +1,591 @@
+use html5ever::{
+    rcdom::{Handle, Node, NodeData},
+    tendril::StrTendril,
+    tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeSink},
+    Attribute,
+    ExpandedName,
+    QualName,
+};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    convert::TryFrom,
+    fmt,
+    num::NonZeroU32,
+    ops::{Index, IndexMut},
+    rc::Rc,
+};
+
+use crate::{sink::ParseError, sync::SyncSoup};
+
+/// Identifies a node of a `SyncSoup`
+///
+/// Ids are small & `Copy`, and stay valid for as long as the document does. Turn one back into
+/// a node with `SyncSoup::node`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(NonZeroU32);
+
+impl NodeId {
+    fn new(index: usize) -> NodeId {
+        let id = u32::try_from(index + 1).ok().and_then(NonZeroU32::new);
+        NodeId(id.expect("Too many nodes for one document"))
+    }
+
+    /// The position of the node in the arena, which is 0 for the document
+    pub fn index(self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+// What a node in a `Tree` holds, with owned strings instead of tendrils, which can't be shared
+// between threads
+pub(crate) enum Data {
+    Document,
+    Doctype {
+        name: String,
+        public_id: String,
+        system_id: String,
+    },
+    Text(String),
+    Comment(String),
+    ProcessingInstruction {
+        target: String,
+        contents: String,
+    },
+    Element {
+        name: QualName,
+        attrs: Vec<(QualName, String)>,
+        template_contents: Option<NodeId>,
+        mathml_annotation_xml_integration_point: bool,
+    },
+}
+
+// A node in a `Tree`, linked to its relatives by their ids
+pub(crate) struct Entry {
+    pub(crate) data: Data,
+    pub(crate) parent: Option<NodeId>,
+    pub(crate) first_child: Option<NodeId>,
+    pub(crate) last_child: Option<NodeId>,
+    pub(crate) previous_sibling: Option<NodeId>,
+    pub(crate) next_sibling: Option<NodeId>,
+}
+
+// All of the nodes of a document in one vector, with the document itself first
+pub(crate) struct Tree {
+    nodes: Vec<Entry>,
+}
+
+impl Index<NodeId> for Tree {
+    type Output = Entry;
+
+    fn index(&self, id: NodeId) -> &Entry {
+        &self.nodes[id.index()]
+    }
+}
+
+impl IndexMut<NodeId> for Tree {
+    fn index_mut(&mut self, id: NodeId) -> &mut Entry {
+        &mut self.nodes[id.index()]
+    }
+}
+
+impl Tree {
+    pub(crate) fn document() -> NodeId {
+        NodeId::new(0)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn push(&mut self, data: Data) -> NodeId {
+        self.nodes.push(Entry {
+            data,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+        });
+        NodeId::new(self.nodes.len() - 1)
+    }
+
+    // Adds `child`, which must not have a parent, as the last child of `parent`
+    fn append(&mut self, parent: NodeId, child: NodeId) {
+        let previous = self[parent].last_child;
+        match previous {
+            Some(previous) => self[previous].next_sibling = Some(child),
+            None => self[parent].first_child = Some(child),
+        }
+        self[parent].last_child = Some(child);
+        let entry = &mut self[child];
+        entry.parent = Some(parent);
+        entry.previous_sibling = previous;
+    }
+
+    // Adds `node`, which must not have a parent, right before `sibling`
+    fn insert_before(&mut self, sibling: NodeId, node: NodeId) {
+        let parent = self[sibling].parent.expect("Sibling has no parent");
+        let previous = self[sibling].previous_sibling;
+        match previous {
+            Some(previous) => self[previous].next_sibling = Some(node),
+            None => self[parent].first_child = Some(node),
+        }
+        self[sibling].previous_sibling = Some(node);
+        let entry = &mut self[node];
+        entry.parent = Some(parent);
+        entry.previous_sibling = previous;
+        entry.next_sibling = Some(sibling);
+    }
+
+    // Removes `node` from its parent, if it has one
+    fn detach(&mut self, node: NodeId) {
+        let parent = match self[node].parent.take() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let previous = self[node].previous_sibling.take();
+        let next = self[node].next_sibling.take();
+        match previous {
+            Some(previous) => self[previous].next_sibling = next,
+            None => self[parent].first_child = next,
+        }
+        match next {
+            Some(next) => self[next].previous_sibling = previous,
+            None => self[parent].last_child = previous,
+        }
+    }
+
+    // Adds `text` to the end of `node` if it's a text node, returning whether it was
+    fn append_to_text(&mut self, node: Option<NodeId>, text: &str) -> bool {
+        match node.map(|node| &mut self[node].data) {
+            Some(Data::Text(ref mut contents)) => {
+                contents.push_str(text);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Copies the tree under `root`, without recursing so deep trees are fine
+    pub(crate) fn from_handle(root: &Handle) -> Tree {
+        enum Link {
+            Root,
+            Child(NodeId),
+            Template(NodeId),
+        }
+
+        let mut tree = Tree {
+            nodes: vec![],
+        };
+        let mut stack = vec![(root.clone(), Link::Root)];
+        while let Some((node, link)) = stack.pop() {
+            let data = match node.data {
+                NodeData::Document => Data::Document,
+                NodeData::Doctype {
+                    ref name,
+                    ref public_id,
+                    ref system_id,
+                } => Data::Doctype {
+                    name: name.to_string(),
+                    public_id: public_id.to_string(),
+                    system_id: system_id.to_string(),
+                },
+                NodeData::Text {
+                    ref contents,
+                } => Data::Text(contents.borrow().to_string()),
+                NodeData::Comment {
+                    ref contents,
+                } => Data::Comment(contents.to_string()),
+                NodeData::ProcessingInstruction {
+                    ref target,
+                    ref contents,
+                } => Data::ProcessingInstruction {
+                    target: target.to_string(),
+                    contents: contents.to_string(),
+                },
+                NodeData::Element {
+                    ref name,
+                    ref attrs,
+                    mathml_annotation_xml_integration_point,
+                    ..
+                } => Data::Element {
+                    name: name.clone(),
+                    attrs: attrs
+                        .borrow()
+                        .iter()
+                        .map(|attr| (attr.name.clone(), attr.value.to_string()))
+                        .collect(),
+                    template_contents: None,
+                    mathml_annotation_xml_integration_point,
+                },
+            };
+            let id = tree.push(data);
+            match link {
+                Link::Root => {},
+                Link::Child(parent) => tree.append(parent, id),
+                Link::Template(template) => {
+                    if let Data::Element {
+                        ref mut template_contents, ..
+                    } = tree[template].data
+                    {
+                        *template_contents = Some(id);
+                    }
+                },
+            }
+            if let NodeData::Element {
+                template_contents: Some(ref contents),
+                ..
+            } = node.data
+            {
+                stack.push((contents.clone(), Link::Template(id)));
+            }
+            for child in node.children.borrow().iter().rev() {
+                stack.push((child.clone(), Link::Child(id)));
+            }
+        }
+        tree
+    }
+
+    // Builds an `RcDom` tree out of this one, returning the handle for the document
+    pub(crate) fn to_handle(&self) -> Handle {
+        let mut handles: Vec<Option<Handle>> = vec![None; self.nodes.len()];
+        for index in 0..self.nodes.len() {
+            self.create_handle(NodeId::new(index), &mut handles);
+        }
+        let handles = handles.into_iter().flatten().collect::<Vec<_>>();
+        for (entry, handle) in self.nodes.iter().zip(&handles) {
+            let mut child = entry.first_child;
+            while let Some(id) = child {
+                let child_handle = &handles[id.index()];
+                child_handle.parent.set(Some(Rc::downgrade(handle)));
+                handle.children.borrow_mut().push(child_handle.clone());
+                child = self[id].next_sibling;
+            }
+        }
+        handles[0].clone()
+    }
+
+    fn create_handle(&self, id: NodeId, handles: &mut [Option<Handle>]) {
+        if handles[id.index()].is_some() {
+            return;
+        }
+        let data = match self[id].data {
+            Data::Document => NodeData::Document,
+            Data::Doctype {
+                ref name,
+                ref public_id,
+                ref system_id,
+            } => NodeData::Doctype {
+                name: StrTendril::from_slice(name),
+                public_id: StrTendril::from_slice(public_id),
+                system_id: StrTendril::from_slice(system_id),
+            },
+            Data::Text(ref contents) => NodeData::Text {
+                contents: RefCell::new(StrTendril::from_slice(contents)),
+            },
+            Data::Comment(ref contents) => NodeData::Comment {
+                contents: StrTendril::from_slice(contents),
+            },
+            Data::ProcessingInstruction {
+                ref target,
+                ref contents,
+            } => NodeData::ProcessingInstruction {
+                target: StrTendril::from_slice(target),
+                contents: StrTendril::from_slice(contents),
+            },
+            Data::Element {
+                ref name,
+                ref attrs,
+                template_contents,
+                mathml_annotation_xml_integration_point,
+            } => {
+                let template_contents = template_contents.map(|contents| {
+                    self.create_handle(contents, handles);
+                    handles[contents.index()].clone().expect("Template contents weren't created")
+                });
+                NodeData::Element {
+                    name: name.clone(),
+                    attrs: RefCell::new(
+                        attrs
+                            .iter()
+                            .map(|(name, value)| Attribute {
+                                name: name.clone(),
+                                value: StrTendril::from_slice(value),
+                            })
+                            .collect(),
+                    ),
+                    template_contents,
+                    mathml_annotation_xml_integration_point,
+                }
+            },
+        };
+        handles[id.index()] = Some(Rc::new(Node {
+            parent: Cell::new(None),
+            children: RefCell::new(vec![]),
+            data,
+        }));
+    }
+}
+
+/// A `TreeSink` that parses straight into the tree of a `SyncSoup`
+///
+/// Where an `RcDom` allocates every node separately, behind an `Rc` & with a `Vec` of
+/// children, this keeps all of the nodes in one vector, linked to each other by `NodeId`s. That
+/// makes parsing lots of pages a lot lighter on memory & the allocator. `SyncSoup::new` uses
+/// it, or it can be given to html5ever directly to parse with other options.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate html5ever;
+/// # extern crate soup;
+/// # use soup::prelude::*;
+/// # use std::error::Error;
+/// use html5ever::{parse_document, tendril::TendrilSink, ParseOpts};
+/// use soup::{dom::Node, ArenaSink};
+///
+/// # fn main() -> Result<(), Box<Error>> {
+/// let soup = parse_document(ArenaSink::default(), ParseOpts::default())
+///     .from_utf8()
+///     .one(r#"<p id="x">Hello</p>"#.as_bytes());
+/// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+/// assert_eq!(soup.node(p.id()).and_then(|p| p.get("id")), Some("x".to_string()));
+/// #   Ok(())
+/// # }
+/// ```
+pub struct ArenaSink {
+    tree: Tree,
+    errors: Vec<ParseError>,
+    quirks_mode: QuirksMode,
+    line: u64,
+}
+
+impl Default for ArenaSink {
+    fn default() -> ArenaSink {
+        let mut tree = Tree {
+            nodes: vec![],
+        };
+        tree.push(Data::Document);
+        ArenaSink {
+            tree,
+            errors: vec![],
+            quirks_mode: QuirksMode::NoQuirks,
+            line: 1,
+        }
+    }
+}
+
+impl fmt::Debug for ArenaSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArenaSink")
+            .field("nodes", &self.tree.len())
+            .field("errors", &self.errors)
+            .field("quirks_mode", &self.quirks_mode)
+            .finish()
+    }
+}
+
+impl TreeSink for ArenaSink {
+    type Handle = NodeId;
+    type Output = SyncSoup;
+
+    fn finish(self) -> SyncSoup {
+        SyncSoup::from_tree(self.tree, self.quirks_mode, self.errors)
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        self.errors.push(ParseError::new(msg, Some(self.line)));
+    }
+
+    fn set_current_line(&mut self, line_number: u64) {
+        self.line = line_number;
+    }
+
+    fn get_document(&mut self) -> NodeId {
+        Tree::document()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a NodeId) -> ExpandedName<'a> {
+        match self.tree[*target].data {
+            Data::Element {
+                ref name, ..
+            } => name.expanded(),
+            _ => panic!("not an element!"),
+        }
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> NodeId {
+        let template_contents = if flags.template {
+            Some(self.tree.push(Data::Document))
+        } else {
+            None
+        };
+        self.tree.push(Data::Element {
+            name,
+            attrs: attrs.into_iter().map(|attr| (attr.name, attr.value.to_string())).collect(),
+            template_contents,
+            mathml_annotation_xml_integration_point: flags.mathml_annotation_xml_integration_point,
+        })
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> NodeId {
+        self.tree.push(Data::Comment(text.to_string()))
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> NodeId {
+        self.tree.push(Data::ProcessingInstruction {
+            target: target.to_string(),
+            contents: data.to_string(),
+        })
+    }
+
+    fn append(&mut self, parent: &NodeId, child: NodeOrText<NodeId>) {
+        let child = match child {
+            NodeOrText::AppendNode(node) => node,
+            NodeOrText::AppendText(text) => {
+                let last = self.tree[*parent].last_child;
+                if self.tree.append_to_text(last, &text) {
+                    return;
+                }
+                self.tree.push(Data::Text(text.to_string()))
+            },
+        };
+        self.tree.append(*parent, child);
+    }
+
+    fn append_based_on_parent_node(
+        &mut self,
+        element: &NodeId,
+        prev_element: &NodeId,
+        child: NodeOrText<NodeId>,
+    ) {
+        if self.tree[*element].parent.is_some() {
+            self.append_before_sibling(element, child);
+        } else {
+            self.append(prev_element, child);
+        }
+    }
+
+    fn append_doctype_to_document(
+        &mut self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        let doctype = self.tree.push(Data::Doctype {
+            name: name.to_string(),
+            public_id: public_id.to_string(),
+            system_id: system_id.to_string(),
+        });
+        self.tree.append(Tree::document(), doctype);
+    }
+
+    fn get_template_contents(&mut self, target: &NodeId) -> NodeId {
+        match self.tree[*target].data {
+            Data::Element {
+                template_contents: Some(contents),
+                ..
+            } => contents,
+            _ => panic!("not a template element!"),
+        }
+    }
+
+    fn same_node(&self, x: &NodeId, y: &NodeId) -> bool {
+        x == y
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks_mode = mode;
+    }
+
+    fn append_before_sibling(&mut self, sibling: &NodeId, new_node: NodeOrText<NodeId>) {
+        let node = match new_node {
+            NodeOrText::AppendNode(node) => {
+                self.tree.detach(node);
+                node
+            },
+            NodeOrText::AppendText(text) => {
+                let previous = self.tree[*sibling].previous_sibling;
+                if self.tree.append_to_text(previous, &text) {
+                    return;
+                }
+                self.tree.push(Data::Text(text.to_string()))
+            },
+        };
+        self.tree.insert_before(*sibling, node);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &NodeId, attrs: Vec<Attribute>) {
+        if let Data::Element {
+            attrs: ref mut existing, ..
+        } = self.tree[*target].data
+        {
+            for attr in attrs {
+                if !existing.iter().any(|(name, _)| *name == attr.name) {
+                    existing.push((attr.name, attr.value.to_string()));
+                }
+            }
+        } else {
+            panic!("not an element");
+        }
+    }
+
+    fn remove_from_parent(&mut self, target: &NodeId) {
+        self.tree.detach(*target);
+    }
+
+    fn reparent_children(&mut self, node: &NodeId, new_parent: &NodeId) {
+        while let Some(child) = self.tree[*node].first_child {
+            self.tree.detach(child);
+            self.tree.append(*new_parent, child);
+        }
+    }
+
+    fn is_mathml_annotation_xml_integration_point(&self, target: &NodeId) -> bool {
+        match self.tree[*target].data {
+            Data::Element {
+                mathml_annotation_xml_integration_point,
+                ..
+            } => mathml_annotation_xml_integration_point,
+            _ => panic!("not an element!"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dom::Node, prelude::*, SyncSoup};
+
+    #[test]
+    fn parses_like_rcdom() {
+        let pages = [
+            "<!DOCTYPE html><p>One <b>two <i>three</b> four</i> five</p>",
+            "<table><tr><td>cell</td></tr>fostered<b>bold</b></table>after",
+            "<template><p>in</p>side</template><p>text<!--c-->more</p>",
+            "<p>a<p>b<math><annotation-xml encoding=\"text/html\"><div>c</div></annotation-xml></math>",
+            "<html lang=en><body class=x><html lang=fr id=y><body id=z>",
+            "text only\n<pre>\n\nkept</pre>",
+        ];
+        for page in pages.iter() {
+            let sync = SyncSoup::new(page);
+            let soup = Soup::new(page);
+            assert_eq!(sync.to_soup().get_handle().display(), soup.get_handle().display());
+            assert_eq!(sync.errors(), soup.errors());
+            assert_eq!(sync.quirks_mode(), soup.quirks_mode());
+        }
+    }
+
+    #[test]
+    fn node_ids() {
+        let soup = SyncSoup::new("<p>One</p><p>Two</p>");
+        for node in soup.tag("p").find_all() {
+            let found = soup.node(node.id()).expect("Couldn't find node by id");
+            assert_eq!(found.text(), node.text());
+        }
+        let last = soup.string("Two").find().expect("Couldn't find text");
+        assert_eq!(soup.node(last.id()).map(|node| node.is_text()), Some(true));
+        let other = SyncSoup::new("<p>Short</p>");
+        assert!(other.node(last.id()).is_none());
+    }
+}
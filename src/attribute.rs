@@ -1,8 +1,22 @@
-use html5ever::rcdom::{Node, NodeData};
-use crate::pattern::Pattern;
+use html5ever::{
+    rcdom::{Handle, NodeData},
+    tendril::StrTendril,
+    Attribute,
+    LocalName,
+    Namespace,
+    Prefix,
+    QualName,
+};
+use crate::{dom, index, pattern::Pattern};
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
 
 fn is_multiple(tag_name: &str, attr_name: &str) -> bool {
-    match (tag_name.to_lowercase().as_str(), attr_name.to_lowercase().as_str()) {
+    matches!(
+        (tag_name.to_lowercase().as_str(), attr_name.to_lowercase().as_str()),
         (_, "class")                |
         (_, "accesskey")            |
         (_, "dropzone")             |
@@ -17,42 +31,227 @@ fn is_multiple(tag_name: &str, attr_name: &str) -> bool {
         ("area", "rel")             |
         ("icon", "sizes")           |
         ("iframe", "sandbox")       |
-        ("output", "for")           => true,
-        _ => false
-    }
+        ("output", "for")
+    )
+}
+
+// Matches `pattern` against a name, either on its own or together with its prefix, so that
+// `media:content` or `xlink:href` can be searched for the way they're written
+pub(crate) fn name_matches<P: Pattern>(pattern: &P, name: &QualName) -> bool {
+    pattern.matches(name.local.as_ref())
+        || name
+            .prefix
+            .as_ref()
+            .is_some_and(|prefix| pattern.matches(&format!("{}:{}", prefix, name.local)))
 }
 
 fn match_list_attr<V: Pattern>(needle: &V, haystack: &str) -> bool {
     for part in haystack.split(char::is_whitespace) {
         let part = part.trim();
-        if needle.matches(&part) {
+        if needle.matches(part) {
             return true;
         }
     }
     false
 }
 
-pub(crate) fn list_aware_match<K: Pattern, V: Pattern>(node: &Node, attr_name: &K, attr_value: &V) -> bool {
-    match node.data {
-        NodeData::Element { ref name, ref attrs, ..} => {
-            let attrs = attrs.borrow();
-            for attr in attrs.iter() {
-                let k = attr.name.local.as_ref();
-                let v = attr.value.as_ref();
-                if attr_name.matches(k) {
-                    if is_multiple(name.local.as_ref(), &k) {
-                        if match_list_attr(attr_value, &v) {
-                            return true;
-                        }
-                    } else {
-                        if attr_value.matches(v) {
-                            return true;
-                        }
-                    }
-                }
+pub(crate) fn list_aware_match<N, K, V>(node: &N, attr_name: &K, attr_value: &V) -> bool
+where
+    N: dom::Node,
+    K: Pattern,
+    V: Pattern,
+{
+    match_attrs(node, |name| name_matches(attr_name, name), attr_value)
+}
+
+// Like `list_aware_match`, but the attribute's namespace has to match `ns` & its local name has
+// to match `attr_name` without any prefix
+pub(crate) fn list_aware_match_ns<N, M, K, V>(node: &N, ns: &M, attr_name: &K, attr_value: &V) -> bool
+where
+    N: dom::Node,
+    M: Pattern,
+    K: Pattern,
+    V: Pattern,
+{
+    match_attrs(node, |name| ns.matches(&name.ns) && attr_name.matches(&name.local), attr_value)
+}
+
+fn match_attrs<N, F, V>(node: &N, name_matches: F, attr_value: &V) -> bool
+where
+    N: dom::Node,
+    F: Fn(&QualName) -> bool,
+    V: Pattern,
+{
+    let name = match node.element_name() {
+        Some(name) => name,
+        None => return false,
+    };
+    node.any_attribute(&mut |attr_name, v| {
+        if !name_matches(attr_name) {
+            return false;
+        }
+        if is_multiple(name.local.as_ref(), attr_name.local.as_ref()) {
+            match_list_attr(attr_value, v)
+        } else {
+            attr_value.matches(v)
+        }
+    })
+}
+
+// Whether the attribute is called `attr_name`. Like the HTML parser, which lowercases them, names
+// are compared ignoring case on HTML elements only. Prefixed attributes like `xlink:href` match
+// with or without their prefix
+pub(crate) fn has_name(element: &QualName, name: &QualName, attr_name: &str) -> bool {
+    let eq = |a: &str, b: &str| if is_html(element) { a.eq_ignore_ascii_case(b) } else { a == b };
+    let local: &str = &name.local;
+    match name.prefix {
+        Some(ref prefix) if attr_name.contains(':') => eq(attr_name, &format!("{}:{}", prefix, local)),
+        _ => eq(local, attr_name),
+    }
+}
+
+fn is_html(element: &QualName) -> bool {
+    &*element.ns == HTML_NAMESPACE
+}
+
+// The name a new attribute called `attr_name` gets on an element called `element`. Like the
+// HTML parser, names are lowercased on HTML elements, and the prefixes that are bound to a
+// namespace of their own are split off, so that `xlink:href` ends up in the XLink namespace
+fn new_name(element: &QualName, attr_name: &str) -> QualName {
+    let attr_name = if is_html(element) {
+        attr_name.to_ascii_lowercase()
+    } else {
+        attr_name.to_string()
+    };
+    if attr_name == "xmlns" {
+        return QualName::new(None, Namespace::from(XMLNS_NAMESPACE), LocalName::from("xmlns"));
+    }
+    if let Some(idx) = attr_name.find(':') {
+        let (prefix, local) = (&attr_name[..idx], &attr_name[idx + 1..]);
+        let ns = match prefix {
+            "xlink" => Some(XLINK_NAMESPACE),
+            "xml" => Some(XML_NAMESPACE),
+            "xmlns" => Some(XMLNS_NAMESPACE),
+            _ => None,
+        };
+        if let Some(ns) = ns.filter(|_| !local.is_empty()) {
+            return QualName::new(Some(Prefix::from(prefix)), Namespace::from(ns), LocalName::from(local));
+        }
+    }
+    QualName::new(None, Namespace::from(""), LocalName::from(attr_name))
+}
+
+// Whether the document's index has to be rebuilt when the attribute changes. The index goes
+// by local names, so `xml:id` counts as well
+fn is_indexed(attr_name: &str) -> bool {
+    let local = attr_name.rsplit(':').next().unwrap_or(attr_name);
+    local.eq_ignore_ascii_case("id") || local.eq_ignore_ascii_case("class")
+}
+
+pub(crate) fn set(node: &Handle, attr_name: &str, attr_value: &str) {
+    if let NodeData::Element { ref name, ref attrs, ..} = node.data {
+        if is_indexed(attr_name) {
+            index::changed();
+        }
+        let mut attrs = attrs.borrow_mut();
+        let value = StrTendril::from_slice(attr_value);
+        if let Some(attr) = attrs.iter_mut().find(|attr| has_name(name, &attr.name, attr_name)) {
+            attr.value = value;
+            return;
+        }
+        attrs.push(Attribute {
+            name: new_name(name, attr_name),
+            value,
+        });
+    }
+}
+
+pub(crate) fn remove(node: &Handle, attr_name: &str) -> Option<String> {
+    if let NodeData::Element { ref name, ref attrs, ..} = node.data {
+        if is_indexed(attr_name) {
+            index::changed();
+        }
+        let mut attrs = attrs.borrow_mut();
+        let idx = attrs.iter().position(|attr| has_name(name, &attr.name, attr_name))?;
+        return Some(attrs.remove(idx).value.to_string());
+    }
+    None
+}
+
+pub(crate) fn get<N: dom::Node>(node: &N, attr_name: &str) -> Option<String> {
+    let element = node.element_name()?;
+    let mut value = None;
+    node.any_attribute(&mut |name, v| {
+        if has_name(element, name, attr_name) {
+            value = Some(v.to_string());
+        }
+        value.is_some()
+    });
+    value
+}
+
+fn tag_name<N: dom::Node>(node: &N) -> Option<&str> {
+    node.element_name().map(|name| name.local.as_ref())
+}
+
+// Adds `value` to the attribute. For attributes that hold a list of values, `value` is added to
+// the list if it isn't already there, otherwise the attribute is set to `value`
+pub(crate) fn add_value(node: &Handle, attr_name: &str, value: &str) {
+    let tag = match tag_name(node) {
+        Some(tag) => tag,
+        None => return,
+    };
+    let current = get(node, attr_name);
+    match current {
+        Some(ref current) if is_multiple(tag, attr_name) => {
+            if !current.split_whitespace().any(|part| part == value) {
+                let mut parts = current.split_whitespace().collect::<Vec<_>>();
+                parts.push(value);
+                set(node, attr_name, &parts.join(" "));
             }
         },
-        _ => (),
+        _ => set(node, attr_name, value),
+    }
+}
+
+// Removes `value` from the attribute. For attributes that hold a list of values, `value` is
+// removed from the list, otherwise the attribute is removed if it is equal to `value`. The
+// attribute is removed entirely once its list is empty
+pub(crate) fn remove_value(node: &Handle, attr_name: &str, value: &str) {
+    let tag = match tag_name(node) {
+        Some(tag) => tag,
+        None => return,
+    };
+    let current = match get(node, attr_name) {
+        Some(current) => current,
+        None => return,
+    };
+    if is_multiple(tag, attr_name) {
+        let parts = current
+            .split_whitespace()
+            .filter(|&part| part != value)
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            remove(node, attr_name);
+        } else {
+            set(node, attr_name, &parts.join(" "));
+        }
+    } else if current == value {
+        remove(node, attr_name);
+    }
+}
+
+// Returns `true` if `value` is in the attribute, taking lists of values into account
+pub(crate) fn has_value<N: dom::Node>(node: &N, attr_name: &str, value: &str) -> bool {
+    let tag = match tag_name(node) {
+        Some(tag) => tag,
+        None => return false,
+    };
+    match get(node, attr_name) {
+        Some(ref current) if is_multiple(tag, attr_name) => {
+            current.split_whitespace().any(|part| part == value)
+        },
+        Some(current) => current == value,
+        None => false,
     }
-    false
 }
@@ -0,0 +1,728 @@
+//! A common interface to the document trees that `soup` can search
+//!
+//! `QueryBuilder`, CSS selectors & XPath work over any type that implements `Node`, which also
+//! provides the reading & navigation methods of `NodeExt`. It's implemented for the nodes of a
+//! `Soup`, `html5ever::rcdom::Handle`, as well as for the nodes of a `SyncSoup`, and can be
+//! implemented for the nodes of your own DOM or `TreeSink` to search it without converting it
+//! first.
+//!
+//! `NodeExt`, which is in the prelude, has the same methods for a `Handle`, so import `Node`
+//! where you work with other kinds of node, to keep the two apart.
+//!
+//! # Example
+//!
+//! ```rust
+//! # extern crate html5ever;
+//! # extern crate soup;
+//! use html5ever::{LocalName, Namespace, QualName};
+//! use soup::{dom::{Node, NodeKind}, QueryBuilderExt};
+//! use std::{borrow::Cow, rc::Rc, vec};
+//!
+//! // Elements with an `id`, each pointing to its parent
+//! struct Tree(Vec<(QualName, String, Option<usize>)>);
+//!
+//! #[derive(Clone)]
+//! struct Element(Rc<Tree>, usize);
+//!
+//! impl Element {
+//!     fn at(&self, idx: usize) -> Element {
+//!         Element(self.0.clone(), idx)
+//!     }
+//! }
+//!
+//! impl Node for Element {
+//!     type Children = vec::IntoIter<Element>;
+//!
+//!     fn node_kind(&self) -> NodeKind {
+//!         NodeKind::Element
+//!     }
+//!
+//!     fn element_name(&self) -> Option<&QualName> {
+//!         Some(&(self.0).0[self.1].0)
+//!     }
+//!
+//!     fn any_attribute(&self, f: &mut dyn FnMut(&QualName, &str) -> bool) -> bool {
+//!         let id = QualName::new(None, Namespace::from(""), LocalName::from("id"));
+//!         f(&id, &(self.0).0[self.1].1)
+//!     }
+//!
+//!     fn contents(&self) -> Option<Cow<'_, str>> {
+//!         None
+//!     }
+//!
+//!     fn child_nodes(&self) -> vec::IntoIter<Element> {
+//!         let children = (self.0).0.iter().enumerate()
+//!             .filter(|&(_, &(_, _, parent))| parent == Some(self.1))
+//!             .map(|(idx, _)| self.at(idx))
+//!             .collect::<Vec<_>>();
+//!         children.into_iter()
+//!     }
+//!
+//!     fn parent_node(&self) -> Option<Element> {
+//!         (self.0).0[self.1].2.map(|idx| self.at(idx))
+//!     }
+//!
+//!     fn same_node(&self, other: &Element) -> bool {
+//!         Rc::ptr_eq(&self.0, &other.0) && self.1 == other.1
+//!     }
+//! }
+//!
+//! impl QueryBuilderExt<Element> for Element {
+//!     fn get_handle(&self) -> Element {
+//!         self.clone()
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let html = |name: &str| QualName::new(None, Namespace::from("http://www.w3.org/1999/xhtml"), LocalName::from(name));
+//! let tree = Tree(vec![
+//!     (html("ul"), "list".to_string(), None),
+//!     (html("li"), "one".to_string(), Some(0)),
+//!     (html("li"), "two".to_string(), Some(0)),
+//! ]);
+//! let ul = Element(Rc::new(tree), 0);
+//! let two = ul.attr("id", "two").find().expect("Couldn't find id 'two'");
+//! assert_eq!(two.name(), "li");
+//! assert_eq!(two.previous_sibling().and_then(|li| li.get("id")), Some("one".to_string()));
+//! assert_eq!(two.parent().map(|ul| ul.name().to_string()), Some("ul".to_string()));
+//! assert_eq!(ul.tag("li").find_all().count(), 2);
+//! # }
+//! ```
+
+use html5ever::{
+    rcdom::{Handle, NodeData},
+    QualName,
+};
+use std::{borrow::Cow, collections::BTreeMap, rc::Rc};
+
+use crate::{
+    attribute,
+    node_ext::{NodeDocumentIter, NodeSiblingIter},
+    qb_ext::NodeChildIter,
+    text::{Strings, TextOptions},
+};
+
+/// The kinds of node that a document tree is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    /// The document itself, at the root of the tree
+    Document,
+    /// A doctype
+    Doctype,
+    /// Some text
+    Text,
+    /// A comment
+    Comment,
+    /// A processing instruction
+    ProcessingInstruction,
+    /// An element
+    Element,
+}
+
+/// An exact tag name, id or class to look up in the index of a document tree
+///
+/// See `Node::indexed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexKey<'a> {
+    /// Elements with this tag name, with or without its prefix
+    Tag(&'a str),
+    /// Elements with this id
+    Id(&'a str),
+    /// Elements with this class among their classes
+    Class(&'a str),
+}
+
+/// A node in a document tree, which can be searched with a `QueryBuilder`
+///
+/// Nodes are handles into the tree, so they should be cheap to clone.
+pub trait Node: Clone {
+    /// Iterator over the children of a node
+    type Children: Iterator<Item = Self>;
+
+    /// Returns what kind of node this is
+    fn node_kind(&self) -> NodeKind;
+
+    /// Returns the name of the node, if it is an element
+    fn element_name(&self) -> Option<&QualName>;
+
+    /// Calls `f` with the name & value of each of the node's attributes, stopping as soon as it
+    /// returns `true`
+    ///
+    /// Returns `true` if `f` did, and `false` if it didn't or the node isn't an element
+    fn any_attribute(&self, f: &mut dyn FnMut(&QualName, &str) -> bool) -> bool;
+
+    /// Returns the contents of a text or comment node, or the data of a processing instruction
+    fn contents(&self) -> Option<Cow<'_, str>>;
+
+    /// Returns an iterator over the children of the node
+    fn child_nodes(&self) -> Self::Children;
+
+    /// Returns the parent of the node, if it has one
+    fn parent_node(&self) -> Option<Self>;
+
+    /// Returns `true` if `self` & `other` are the same node
+    fn same_node(&self, other: &Self) -> bool;
+
+    /// Returns the sibling that comes right after the node, if there is one
+    fn next_sibling_node(&self) -> Option<Self> {
+        let mut siblings = self.parent_node()?.child_nodes();
+        siblings.position(|sibling| sibling.same_node(self))?;
+        siblings.next()
+    }
+
+    /// Returns the sibling that comes right before the node, if there is one
+    fn previous_sibling_node(&self) -> Option<Self> {
+        let mut previous = None;
+        for sibling in self.parent_node()?.child_nodes() {
+            if sibling.same_node(self) {
+                return previous;
+            }
+            previous = Some(sibling);
+        }
+        None
+    }
+
+    /// Returns the last child of the node, if it has any
+    fn last_child_node(&self) -> Option<Self> {
+        self.child_nodes().last()
+    }
+
+    /// Returns the target of a processing instruction
+    ///
+    /// The default returns `None`, so the node never matches an XPath
+    /// `processing-instruction('target')` test.
+    fn target(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns a number that tells the node apart from the other nodes of its tree, if the tree
+    /// has one
+    ///
+    /// XPath uses this to put node-sets into document order. The default returns `None`, which
+    /// still works, but means every node of the tree is compared with each node being sorted.
+    fn node_id(&self) -> Option<usize> {
+        None
+    }
+
+    /// Looks up the elements in & under the node that `key` points to, in document order, if
+    /// the tree keeps an index of them
+    ///
+    /// `QueryBuilder` tries this before walking the tree for queries that start with an exact
+    /// tag, id or class, and still checks each of the nodes against the whole query. The
+    /// default returns `None`, so the tree is always walked.
+    fn indexed(&self, _key: &IndexKey<'_>) -> Option<Vec<Self>> {
+        None
+    }
+
+    /// Returns `true` if node is of type Document
+    fn is_document(&self) -> bool {
+        self.node_kind() == NodeKind::Document
+    }
+
+    /// Returns `true` if node is of type Doctype
+    fn is_doctype(&self) -> bool {
+        self.node_kind() == NodeKind::Doctype
+    }
+
+    /// Returns `true` if node is of type Text
+    fn is_text(&self) -> bool {
+        self.node_kind() == NodeKind::Text
+    }
+
+    /// Returns `true` if node is of type Comment
+    fn is_comment(&self) -> bool {
+        self.node_kind() == NodeKind::Comment
+    }
+
+    /// Returns `true` if node is of type ProcessingInstruction
+    fn is_processing_instruction(&self) -> bool {
+        self.node_kind() == NodeKind::ProcessingInstruction
+    }
+
+    /// Returns `true` if node is of type Element
+    fn is_element(&self) -> bool {
+        self.node_kind() == NodeKind::Element
+    }
+
+    /// Retrieves the name of the node, or a special name like `"[text]"` if it isn't an element,
+    /// as `NodeExt::name` does
+    fn name(&self) -> &str {
+        name(self)
+    }
+
+    /// Retrieves the namespace URI of the node, if it is an element
+    fn namespace(&self) -> Option<&str> {
+        self.element_name().map(|name| name.ns.as_ref())
+    }
+
+    /// Looks for an attribute named `attr` and returns it's value as a string
+    fn get(&self, attr: &str) -> Option<String> {
+        attribute::get(self, attr)
+    }
+
+    /// Returns the node's attributes as a BTreeMap
+    fn attrs(&self) -> BTreeMap<String, String> {
+        let mut attrs = BTreeMap::new();
+        self.any_attribute(&mut |name, value| {
+            attrs.insert(name.local.to_string(), value.to_string());
+            false
+        });
+        attrs
+    }
+
+    /// Returns `true` if the element has the class `class`
+    fn has_class(&self, class: &str) -> bool {
+        attribute::has_value(self, "class", class)
+    }
+
+    /// Retrieves the text value of this element, as well as it's child elements
+    fn text(&self) -> String {
+        text(self)
+    }
+
+    /// Joins the strings under the node with `separator`, as `NodeExt::get_text` does
+    fn get_text(&self, separator: &str, strip: bool) -> String {
+        self.get_text_with(separator, &TextOptions::new().strip(strip))
+    }
+
+    /// Joins the strings under the node with `separator`, using `options`
+    fn get_text_with(&self, separator: &str, options: &TextOptions) -> String {
+        self.strings_with(options).collect::<Vec<_>>().join(separator)
+    }
+
+    /// Returns an iterator over the strings under the node, in document order
+    fn strings(&self) -> Strings<Self> {
+        self.strings_with(&TextOptions::new())
+    }
+
+    /// Returns an iterator over the strings under the node, with whitespace stripped from each
+    /// & blank strings left out
+    fn stripped_strings(&self) -> Strings<Self> {
+        self.strings_with(&TextOptions::new().strip(true))
+    }
+
+    /// Returns an iterator over the strings under the node, using `options`
+    fn strings_with(&self, options: &TextOptions) -> Strings<Self> {
+        Strings::new(self, options)
+    }
+
+    /// Lays out the text under the node the way a browser would display it, as
+    /// `NodeExt::rendered_text` does
+    fn rendered_text(&self, width: usize) -> String {
+        crate::text::render(self, width)
+    }
+
+    /// Navigates to the parent of the node, if there is one
+    fn parent(&self) -> Option<Self> {
+        self.parent_node()
+    }
+
+    /// Navigates to the next sibling of the node, if there is one
+    fn next_sibling(&self) -> Option<Self> {
+        self.next_sibling_node()
+    }
+
+    /// Navigates to the previous sibling of the node, if there is one
+    fn previous_sibling(&self) -> Option<Self> {
+        self.previous_sibling_node()
+    }
+
+    /// Navigates to the next sibling of the node that is an element, if there is one
+    fn next_element_sibling(&self) -> Option<Self> {
+        self.next_element_siblings().next()
+    }
+
+    /// Navigates to the previous sibling of the node that is an element, if there is one
+    fn previous_element_sibling(&self) -> Option<Self> {
+        self.previous_element_siblings().next()
+    }
+
+    /// Returns an iterator over the siblings that come after the node
+    fn next_siblings(&self) -> NodeSiblingIter<Self> {
+        NodeSiblingIter::new(self, true, false)
+    }
+
+    /// Returns an iterator over the siblings that come before the node, nearest first
+    fn previous_siblings(&self) -> NodeSiblingIter<Self> {
+        NodeSiblingIter::new(self, false, false)
+    }
+
+    /// Returns an iterator over the siblings that come after the node and are elements
+    fn next_element_siblings(&self) -> NodeSiblingIter<Self> {
+        NodeSiblingIter::new(self, true, true)
+    }
+
+    /// Returns an iterator over the siblings that come before the node and are elements,
+    /// nearest first
+    fn previous_element_siblings(&self) -> NodeSiblingIter<Self> {
+        NodeSiblingIter::new(self, false, true)
+    }
+
+    /// Navigates to the node that comes right after this one in document order
+    fn next_element(&self) -> Option<Self> {
+        following(self)
+    }
+
+    /// Navigates to the node that comes right before this one in document order
+    fn previous_element(&self) -> Option<Self> {
+        preceding(self)
+    }
+
+    /// Returns an iterator over all of the nodes that come after this one in document order
+    fn next_elements(&self) -> NodeDocumentIter<Self> {
+        NodeDocumentIter::new(DocumentOrder::after(self))
+    }
+
+    /// Returns an iterator over all of the nodes that come before this one in document order,
+    /// nearest first
+    fn previous_elements(&self) -> NodeDocumentIter<Self> {
+        NodeDocumentIter::new(DocumentOrder::before(self))
+    }
+}
+
+impl Node for Handle {
+    type Children = NodeChildIter;
+
+    fn node_kind(&self) -> NodeKind {
+        rc_kind(&self.data)
+    }
+
+    fn element_name(&self) -> Option<&QualName> {
+        rc_element_name(&self.data)
+    }
+
+    fn any_attribute(&self, f: &mut dyn FnMut(&QualName, &str) -> bool) -> bool {
+        match self.data {
+            NodeData::Element {
+                ref attrs, ..
+            } => attrs.borrow().iter().any(|attr| f(&attr.name, attr.value.as_ref())),
+            _ => false,
+        }
+    }
+
+    fn contents(&self) -> Option<Cow<'_, str>> {
+        match self.data {
+            NodeData::Text {
+                ref contents,
+            } => Some(Cow::Owned(contents.borrow().to_string())),
+            NodeData::Comment {
+                ref contents,
+            } => Some(Cow::Borrowed(contents)),
+            NodeData::ProcessingInstruction {
+                ref contents, ..
+            } => Some(Cow::Borrowed(contents)),
+            _ => None,
+        }
+    }
+
+    fn child_nodes(&self) -> NodeChildIter {
+        NodeChildIter::new(self.clone())
+    }
+
+    fn parent_node(&self) -> Option<Handle> {
+        let parent = self.parent.take(); // leaves node.parent as Cell(None)
+        let parent_node = parent.clone();
+        self.parent.set(parent); // puts original parent back
+        parent_node.and_then(|node| node.upgrade())
+    }
+
+    fn same_node(&self, other: &Handle) -> bool {
+        Rc::ptr_eq(self, other)
+    }
+
+    fn last_child_node(&self) -> Option<Handle> {
+        self.children.borrow().last().cloned()
+    }
+
+    fn target(&self) -> Option<&str> {
+        match self.data {
+            NodeData::ProcessingInstruction {
+                ref target, ..
+            } => Some(target),
+            _ => None,
+        }
+    }
+
+    fn node_id(&self) -> Option<usize> {
+        Some(Rc::as_ptr(self) as usize)
+    }
+}
+
+// The kind of an `html5ever::rcdom` node
+pub(crate) fn rc_kind(data: &NodeData) -> NodeKind {
+    match *data {
+        NodeData::Document => NodeKind::Document,
+        NodeData::Doctype {
+            ..
+        } => NodeKind::Doctype,
+        NodeData::Text {
+            ..
+        } => NodeKind::Text,
+        NodeData::Comment {
+            ..
+        } => NodeKind::Comment,
+        NodeData::ProcessingInstruction {
+            ..
+        } => NodeKind::ProcessingInstruction,
+        NodeData::Element {
+            ..
+        } => NodeKind::Element,
+    }
+}
+
+// The name of an `html5ever::rcdom` node, if it is an element
+pub(crate) fn rc_element_name(data: &NodeData) -> Option<&QualName> {
+    match *data {
+        NodeData::Element {
+            ref name, ..
+        } => Some(name),
+        _ => None,
+    }
+}
+
+// The name of an element, or one of the special names `NodeExt::name` documents for other
+// kinds of node
+pub(crate) fn name<N: Node>(node: &N) -> &str {
+    kind_name(node.node_kind(), node.element_name())
+}
+
+pub(crate) fn kind_name(kind: NodeKind, element_name: Option<&QualName>) -> &str {
+    match element_name {
+        Some(name) => name.local.as_ref(),
+        None => match kind {
+            NodeKind::Document => "[document]",
+            NodeKind::Doctype => "[doctype]",
+            NodeKind::Text => "[text]",
+            NodeKind::Comment => "[comment]",
+            NodeKind::ProcessingInstruction => "[processing-instruction]",
+            NodeKind::Element => "",
+        },
+    }
+}
+
+// All of the text in & under `node`
+pub(crate) fn text<N: Node>(node: &N) -> String {
+    let mut text = String::new();
+    push_text(node, &mut text);
+    let mut stack = vec![node.child_nodes()];
+    while let Some(children) = stack.last_mut() {
+        match children.next() {
+            Some(child) => {
+                push_text(&child, &mut text);
+                stack.push(child.child_nodes());
+            },
+            None => {
+                stack.pop();
+            },
+        }
+    }
+    text
+}
+
+fn push_text<N: Node>(node: &N, text: &mut String) {
+    if node.node_kind() == NodeKind::Text {
+        if let Some(contents) = node.contents() {
+            text.push_str(&contents);
+        }
+    }
+}
+
+// The node that comes right after `node` in document order
+pub(crate) fn following<N: Node>(node: &N) -> Option<N> {
+    if let Some(child) = node.child_nodes().next() {
+        return Some(child);
+    }
+    let mut current = node.clone();
+    loop {
+        if let Some(sibling) = current.next_sibling_node() {
+            return Some(sibling);
+        }
+        current = current.parent_node()?;
+    }
+}
+
+// The node that comes right before `node` in document order
+pub(crate) fn preceding<N: Node>(node: &N) -> Option<N> {
+    match node.previous_sibling_node() {
+        Some(mut last) => {
+            while let Some(child) = last.last_child_node() {
+                last = child;
+            }
+            Some(last)
+        },
+        None => node.parent_node(),
+    }
+}
+
+// Iterator over the nodes before or after a node, in document order
+//
+// Finding a sibling through the `Node` trait means looking through the parent's children, so
+// instead of stepping with `following` & `preceding` the walk keeps the children of each node
+// on the way down from the root, along with where it has got to among them. Each node's
+// children are only listed once, so walking past lots of siblings stays linear.
+pub(crate) struct DocumentOrder<N> {
+    next: Option<N>,
+    // The siblings at each level of the walk, outermost first, and the index of the node the
+    // walk is at among them
+    levels: Vec<(Vec<N>, usize)>,
+    forward: bool,
+}
+
+impl<N: Node> DocumentOrder<N> {
+    pub(crate) fn after(node: &N) -> DocumentOrder<N> {
+        DocumentOrder::new(node, true)
+    }
+
+    pub(crate) fn before(node: &N) -> DocumentOrder<N> {
+        DocumentOrder::new(node, false)
+    }
+
+    fn new(node: &N, forward: bool) -> DocumentOrder<N> {
+        let mut levels = vec![];
+        let mut current = node.clone();
+        while let Some(parent) = current.parent_node() {
+            let siblings = parent.child_nodes().collect::<Vec<_>>();
+            let idx = siblings.iter().position(|sibling| sibling.same_node(&current));
+            // A node that its parent doesn't list among its children is walked as if it were
+            // the root
+            let idx = match idx {
+                Some(idx) => idx,
+                None => break,
+            };
+            levels.push((siblings, idx));
+            current = parent;
+        }
+        levels.push((vec![current], 0));
+        levels.reverse();
+        let mut order = DocumentOrder {
+            next: None,
+            levels,
+            forward,
+        };
+        order.next = order.step();
+        order
+    }
+
+    pub(crate) fn peek(&self) -> Option<&N> {
+        self.next.as_ref()
+    }
+
+    // Moves the walk on to the next node, returning it
+    fn step(&mut self) -> Option<N> {
+        if self.forward {
+            let children = {
+                let (siblings, idx) = self.levels.last()?;
+                siblings[*idx].child_nodes().collect::<Vec<_>>()
+            };
+            if !children.is_empty() {
+                let first = children[0].clone();
+                self.levels.push((children, 0));
+                return Some(first);
+            }
+            loop {
+                let (siblings, idx) = self.levels.last_mut()?;
+                if *idx + 1 < siblings.len() {
+                    *idx += 1;
+                    return Some(siblings[*idx].clone());
+                }
+                self.levels.pop();
+            }
+        } else {
+            let mut last = {
+                let (siblings, idx) = self.levels.last_mut()?;
+                if *idx == 0 {
+                    self.levels.pop();
+                    let (siblings, idx) = self.levels.last()?;
+                    return Some(siblings[*idx].clone());
+                }
+                *idx -= 1;
+                siblings[*idx].clone()
+            };
+            loop {
+                let children = last.child_nodes().collect::<Vec<_>>();
+                if children.is_empty() {
+                    return Some(last);
+                }
+                let idx = children.len() - 1;
+                last = children[idx].clone();
+                self.levels.push((children, idx));
+            }
+        }
+    }
+}
+
+impl<N: Node> Iterator for DocumentOrder<N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        let current = self.next.take()?;
+        self.next = self.step();
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DocumentOrder, Node, NodeKind};
+    use crate::prelude::*;
+    use html5ever::{rcdom::Handle, QualName};
+    use std::{borrow::Cow, cell::Cell, rc::Rc, vec};
+
+    // A `Handle` that counts how many children are listed through it
+    #[derive(Clone)]
+    struct Counted(Handle, Rc<Cell<usize>>);
+
+    impl Node for Counted {
+        type Children = vec::IntoIter<Counted>;
+
+        fn node_kind(&self) -> NodeKind {
+            self.0.node_kind()
+        }
+
+        fn element_name(&self) -> Option<&QualName> {
+            self.0.element_name()
+        }
+
+        fn any_attribute(&self, f: &mut dyn FnMut(&QualName, &str) -> bool) -> bool {
+            self.0.any_attribute(f)
+        }
+
+        fn contents(&self) -> Option<Cow<'_, str>> {
+            self.0.contents()
+        }
+
+        fn child_nodes(&self) -> vec::IntoIter<Counted> {
+            let children = self.0.child_nodes().map(|child| Counted(child, self.1.clone())).collect::<Vec<_>>();
+            self.1.set(self.1.get() + children.len());
+            children.into_iter()
+        }
+
+        fn parent_node(&self) -> Option<Counted> {
+            self.0.parent_node().map(|parent| Counted(parent, self.1.clone()))
+        }
+
+        fn same_node(&self, other: &Counted) -> bool {
+            self.0.same_node(&other.0)
+        }
+    }
+
+    #[test]
+    fn wide_trees() {
+        let html = "<p>x</p>".repeat(5000);
+        let soup = Soup::new(&html);
+        let listed = Rc::new(Cell::new(0));
+        let first = Counted(soup.tag("p").find().expect("Couldn't find tag 'p'"), listed.clone());
+        assert_eq!(DocumentOrder::after(&first).filter(|node| super::name(&node.0) == "p").count(), 4999);
+        assert!(listed.get() < 3 * 10_000, "Listed {} children", listed.get());
+        listed.set(0);
+        let last = Counted(soup.tag("p").find_all().last().expect("Couldn't find tag 'p'"), listed.clone());
+        let before = DocumentOrder::before(&last).map(|node| super::name(&node.0).to_string()).collect::<Vec<_>>();
+        assert_eq!(before.len(), 2 * 4999 + 4);
+        assert_eq!(&before[before.len() - 4..], &["body", "head", "html", "[document]"]);
+        assert!(listed.get() < 3 * 10_000, "Listed {} children", listed.get());
+        let first = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        assert_eq!(first.tag("p").find_all_next().count(), 4999);
+        let last = soup.tag("p").find_all().last().expect("Couldn't find tag 'p'");
+        assert_eq!(last.tag("p").find_all_previous().count(), 4999);
+    }
+}
@@ -0,0 +1,246 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+// How many bytes the `<meta>` prescan looks at, the same limit browsers use
+const PRESCAN_LIMIT: usize = 1024;
+
+/// Works out which encoding `bytes` are in
+///
+/// Looks for a byte order mark first, then for a `<meta charset>` or
+/// `<meta http-equiv="Content-Type">` declaration near the start of the document, then at
+/// `hint`, which would usually be the charset from an HTTP `Content-Type` header. If none of
+/// those give a usable encoding, the bytes are assumed to be UTF-8 if they're valid UTF-8, and
+/// windows-1252 otherwise.
+pub(crate) fn sniff(bytes: &[u8], hint: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LIMIT)]) {
+        return encoding;
+    }
+    if let Some(encoding) = hint.and_then(|label| Encoding::for_label(label.trim().as_bytes())) {
+        return encoding;
+    }
+    if ::std::str::from_utf8(bytes).is_ok() {
+        UTF_8
+    } else {
+        WINDOWS_1252
+    }
+}
+
+/// Decodes `bytes` to a string, returning the encoding that was used as well
+pub(crate) fn decode(bytes: &[u8], hint: Option<&str>) -> (String, &'static Encoding) {
+    let encoding = sniff(bytes, hint);
+    let (text, _) = encoding.decode_with_bom_removal(bytes);
+    (text.into_owned(), encoding)
+}
+
+// Prescans the start of a document for a `<meta>` declaring its encoding, following the
+// algorithm in the HTML spec
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!--") {
+            pos += 4 + find(&rest[4..], b"-->").map(|idx| idx + 3)?;
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/')
+        {
+            pos += 6;
+            if let Some(encoding) = meta_encoding(bytes, &mut pos) {
+                return Some(encoding);
+            }
+        } else if rest.len() > 2
+            && (rest[0] == b'<' && rest[1].is_ascii_alphabetic()
+                || rest.starts_with(b"</") && rest[2].is_ascii_alphabetic())
+        {
+            pos += rest
+                .iter()
+                .position(|&b| is_space(b) || b == b'>')
+                .unwrap_or(rest.len());
+            while get_attribute(bytes, &mut pos).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += rest.iter().position(|&b| b == b'>').map(|idx| idx + 1)?;
+        } else {
+            pos += 1;
+        }
+    }
+    None
+}
+
+// Reads the attributes of a `<meta>` tag, returning the encoding it declares, if any
+fn meta_encoding(bytes: &[u8], pos: &mut usize) -> Option<&'static Encoding> {
+    let mut seen = vec![];
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(bytes, pos) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match &name[..] {
+            b"http-equiv" => {
+                got_pragma |= value.eq_ignore_ascii_case(b"content-type");
+            },
+            b"content" if charset.is_none() => {
+                if let Some(label) = charset_from_content(&value) {
+                    charset = Encoding::for_label(label);
+                    need_pragma = Some(true);
+                }
+            },
+            b"charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            },
+            _ => {},
+        }
+        seen.push(name);
+    }
+    let charset = match need_pragma {
+        Some(true) if !got_pragma => return None,
+        Some(_) => charset?,
+        None => return None,
+    };
+    Some(if charset == UTF_16BE || charset == UTF_16LE {
+        UTF_8
+    } else if charset == X_USER_DEFINED {
+        WINDOWS_1252
+    } else {
+        charset
+    })
+}
+
+// Reads the next attribute of a tag, lowercasing its name & value
+fn get_attribute(bytes: &[u8], pos: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while *pos < bytes.len() && (is_space(bytes[*pos]) || bytes[*pos] == b'/') {
+        *pos += 1;
+    }
+    if *pos >= bytes.len() || bytes[*pos] == b'>' {
+        return None;
+    }
+    let mut name = vec![];
+    let mut value = vec![];
+    loop {
+        let b = *bytes.get(*pos)?;
+        if b == b'=' && !name.is_empty() {
+            *pos += 1;
+            break;
+        } else if is_space(b) {
+            skip_spaces(bytes, pos);
+            if bytes.get(*pos) != Some(&b'=') {
+                return Some((name, value));
+            }
+            *pos += 1;
+            break;
+        } else if b == b'/' || b == b'>' {
+            return Some((name, value));
+        }
+        name.push(b.to_ascii_lowercase());
+        *pos += 1;
+    }
+    skip_spaces(bytes, pos);
+    let quote = *bytes.get(*pos)?;
+    if quote == b'"' || quote == b'\'' {
+        *pos += 1;
+        loop {
+            let b = *bytes.get(*pos)?;
+            *pos += 1;
+            if b == quote {
+                return Some((name, value));
+            }
+            value.push(b.to_ascii_lowercase());
+        }
+    }
+    if quote == b'>' {
+        return Some((name, value));
+    }
+    loop {
+        let b = *bytes.get(*pos)?;
+        if is_space(b) || b == b'>' {
+            return Some((name, value));
+        }
+        value.push(b.to_ascii_lowercase());
+        *pos += 1;
+    }
+}
+
+// Pulls the charset out of a `content` attribute like `text/html; charset=Shift_JIS`
+fn charset_from_content(content: &[u8]) -> Option<&[u8]> {
+    let mut pos = 0;
+    loop {
+        pos += find(&content[pos..], b"charset")? + 7;
+        let mut rest = &content[pos..];
+        while rest.first().is_some_and(|&b| is_space(b)) {
+            rest = &rest[1..];
+        }
+        if rest.first() != Some(&b'=') {
+            continue;
+        }
+        rest = &rest[1..];
+        while rest.first().is_some_and(|&b| is_space(b)) {
+            rest = &rest[1..];
+        }
+        return match rest.first() {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let end = rest[1..].iter().position(|&b| b == quote)?;
+                Some(&rest[1..=end])
+            },
+            Some(_) => {
+                let end = rest
+                    .iter()
+                    .position(|&b| is_space(b) || b == b';')
+                    .unwrap_or(rest.len());
+                Some(&rest[..end])
+            },
+            None => None,
+        };
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn skip_spaces(bytes: &[u8], pos: &mut usize) {
+    while *pos < bytes.len() && is_space(bytes[*pos]) {
+        *pos += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sniff;
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, UTF_8, WINDOWS_1252};
+
+    #[test]
+    fn sniffing_order() {
+        assert_eq!(sniff(b"\xFF\xFE<\0p\0>\0", Some("shift_jis")), UTF_16LE);
+        assert_eq!(sniff(b"\xEF\xBB\xBF<meta charset=shift_jis>", None), UTF_8);
+        assert_eq!(sniff(b"<meta charset=\"Shift_JIS\">", Some("utf-8")), SHIFT_JIS);
+        assert_eq!(sniff(b"<p>caf\xE9</p>", Some("Shift_JIS")), SHIFT_JIS);
+        assert_eq!(sniff(b"<p>caf\xE9</p>", None), WINDOWS_1252);
+        assert_eq!(sniff(b"<p>caf\xC3\xA9</p>", None), UTF_8);
+        assert_eq!(sniff(b"<p>caf\xE9</p>", Some("bogus")), WINDOWS_1252);
+    }
+
+    #[test]
+    fn prescan() {
+        let http_equiv = br#"<!DOCTYPE html><!-- <meta charset=utf-8> --><html><head>
+            <title x='<meta charset=utf-8>'>T</title>
+            <META content="text/html; charset='iso-8859-1'" HTTP-EQUIV=Content-Type>"#;
+        assert_eq!(sniff(http_equiv, None), WINDOWS_1252);
+        let no_pragma = b"<meta content=\"text/html; charset=shift_jis\"><p>caf\xE9</p>";
+        assert_eq!(sniff(no_pragma, None), WINDOWS_1252);
+        assert_eq!(sniff(b"<meta charset=utf-16le>", None), UTF_8);
+        assert_eq!(sniff(b"<meta charset=x-user-defined>", None), WINDOWS_1252);
+        let late = format!("{}<meta charset=shift_jis>", " ".repeat(1024));
+        assert_eq!(sniff(late.as_bytes(), None), UTF_8);
+    }
+}
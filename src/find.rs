@@ -1,11 +1,53 @@
-use html5ever::rcdom::{self, Handle, NodeData};
+use html5ever::rcdom::Handle;
 use std::{fmt, marker::PhantomData, rc::Rc};
 
 use crate::pattern::Pattern;
 use crate::attribute;
+use crate::dom::{self, DocumentOrder, IndexKey, Node, NodeKind};
+use crate::index::Lookup;
 
-pub trait Query {
-    fn matches(&self, node: &rcdom::Node) -> bool;
+/// A condition that nodes are tested against by a `QueryBuilder`
+///
+/// Implement this to write your own matchers, and add them to a query with
+/// `QueryBuilder::query`. `N` is the type of node being searched, which is the `Handle` of a
+/// `Soup` unless said otherwise.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate html5ever;
+/// # extern crate soup;
+/// # use soup::prelude::*;
+/// # use std::error::Error;
+/// use html5ever::rcdom::Handle;
+/// use soup::Query;
+///
+/// struct ManyChildren(usize);
+///
+/// impl Query for ManyChildren {
+///     fn matches(&self, node: &Handle) -> bool {
+///         node.children.borrow().iter().filter(|child| child.is_element()).count() > self.0
+///     }
+/// }
+///
+/// # fn main() -> Result<(), Box<Error>> {
+/// let soup = Soup::new(r#"<ul id="a"><li>1</li></ul><ul id="b"><li>1</li><li>2</li></ul>"#);
+/// let ul = soup.tag("ul").query(ManyChildren(1)).find().expect("Couldn't find ul");
+/// assert_eq!(ul.get("id"), Some("b".to_string()));
+/// #   Ok(())
+/// # }
+/// ```
+pub trait Query<N = Handle> {
+    /// Returns `true` if `node` satisfies this query
+    fn matches(&self, node: &N) -> bool;
+
+    /// Returns the exact tag name, id or class a node has to have to satisfy this query, if
+    /// there is one, so that the query can be answered from an index
+    ///
+    /// The default returns `None`
+    fn index_key(&self) -> Option<IndexKey<'_>> {
+        None
+    }
 }
 
 pub struct TagQuery<P> {
@@ -31,14 +73,38 @@ where
     }
 }
 
-impl<P: Pattern> Query for TagQuery<P> {
-    fn matches(&self, node: &rcdom::Node) -> bool {
-        match node.data {
-            NodeData::Element {
-                ref name, ..
-            } => self.inner.matches(name.local.as_ref()),
-            _ => false,
-        }
+impl<P: Pattern, N: Node> Query<N> for TagQuery<P> {
+    fn matches(&self, node: &N) -> bool {
+        node.element_name().is_some_and(|name| attribute::name_matches(&self.inner, name))
+    }
+
+    fn index_key(&self) -> Option<IndexKey<'_>> {
+        self.inner.exact().map(IndexKey::Tag)
+    }
+}
+
+pub struct TagNsQuery<N, P> {
+    ns: N,
+    inner: P,
+}
+
+impl<N, P> fmt::Debug for TagNsQuery<N, P>
+where
+    N: Pattern + fmt::Debug,
+    P: Pattern + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TagNsQuery")
+            .field("ns", &self.ns)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<M: Pattern, P: Pattern, N: Node> Query<N> for TagNsQuery<M, P> {
+    fn matches(&self, node: &N) -> bool {
+        node.element_name()
+            .is_some_and(|name| self.ns.matches(&name.ns) && self.inner.matches(&name.local))
     }
 }
 
@@ -73,23 +139,142 @@ where
     }
 }
 
-impl<K, V> Query for AttrQuery<K, V>
+impl<K, V, N> Query<N> for AttrQuery<K, V>
 where
     K: Pattern,
     V: Pattern,
+    N: Node,
 {
-    fn matches(&self, node: &rcdom::Node) -> bool {
-        attribute::list_aware_match(&node, &self.key, &self.value)
+    fn matches(&self, node: &N) -> bool {
+        attribute::list_aware_match(node, &self.key, &self.value)
     }
+
+    fn index_key(&self) -> Option<IndexKey<'_>> {
+        let value = self.value.exact()?;
+        match self.key.exact()? {
+            "id" => Some(IndexKey::Id(value)),
+            "class" if !value.is_empty() => Some(IndexKey::Class(value)),
+            _ => None,
+        }
+    }
+}
+
+pub struct AttrNsQuery<N, K, V> {
+    ns: N,
+    key: K,
+    value: V,
 }
 
-impl Query for () {
-    fn matches(&self, _: &rcdom::Node) -> bool {
+impl<N, K, V> fmt::Debug for AttrNsQuery<N, K, V>
+where
+    N: Pattern + fmt::Debug,
+    K: Pattern + fmt::Debug,
+    V: Pattern + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AttrNsQuery")
+            .field("ns", &self.ns)
+            .field("key", &self.key)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<M, K, V, N> Query<N> for AttrNsQuery<M, K, V>
+where
+    M: Pattern,
+    K: Pattern,
+    V: Pattern,
+    N: Node,
+{
+    fn matches(&self, node: &N) -> bool {
+        attribute::list_aware_match_ns(node, &self.ns, &self.key, &self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextSource {
+    // all descendant text of an element
+    Descendants,
+    // only the text nodes that are direct children of an element
+    Children,
+    // the contents of a text node
+    Node,
+}
+
+pub struct TextQuery<P> {
+    inner: P,
+    source: TextSource,
+}
+
+impl<P: Pattern> TextQuery<P> {
+    fn new(inner: P, source: TextSource) -> TextQuery<P> {
+        TextQuery {
+            inner,
+            source,
+        }
+    }
+}
+
+impl<P> fmt::Debug for TextQuery<P>
+where
+    P: Pattern + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextQuery")
+            .field("inner", &self.inner)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl<P: Pattern, N: Node> Query<N> for TextQuery<P> {
+    fn matches(&self, node: &N) -> bool {
+        match (self.source, node.node_kind()) {
+            (TextSource::Descendants, NodeKind::Element) => self.inner.matches(&dom::text(node)),
+            (TextSource::Children, NodeKind::Element) => {
+                let text = node
+                    .child_nodes()
+                    .filter(|child| child.node_kind() == NodeKind::Text)
+                    .filter_map(|child| child.contents().map(|contents| contents.into_owned()))
+                    .collect::<Vec<_>>()
+                    .join("");
+                self.inner.matches(&text)
+            },
+            (TextSource::Node, NodeKind::Text) => {
+                node.contents().is_some_and(|contents| self.inner.matches(&contents))
+            },
+            _ => false,
+        }
+    }
+}
+
+pub struct FilterQuery<F> {
+    filter: F,
+}
+
+impl<F> fmt::Debug for FilterQuery<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FilterQuery(«Fn»)")
+    }
+}
+
+impl<F, N> Query<N> for FilterQuery<F>
+where
+    F: Fn(&N) -> bool,
+{
+    fn matches(&self, node: &N) -> bool {
+        (self.filter)(node)
+    }
+}
+
+impl<N> Query<N> for () {
+    fn matches(&self, _: &N) -> bool {
         true
     }
 }
 
-pub struct QueryWrapper<'a, T: Query, U: Query> {
+pub struct QueryWrapper<'a, T, U> {
     inner: T,
     next: Option<U>,
     _l: PhantomData<&'a ()>,
@@ -97,8 +282,8 @@ pub struct QueryWrapper<'a, T: Query, U: Query> {
 
 impl<'a, T, U> fmt::Debug for QueryWrapper<'a, T, U>
 where
-    T: Query + fmt::Debug,
-    U: Query + fmt::Debug,
+    T: fmt::Debug,
+    U: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("QueryWrapper")
@@ -110,6 +295,13 @@ where
 
 pub(crate) type EmptyQueryWrapper<'a> = QueryWrapper<'a, (), ()>;
 
+pub(crate) type OrQueryBuilder<'a, T, U, V, W, N = Handle> = QueryBuilder<
+    'a,
+    OrQuery<QueryWrapper<'a, T, U>, QueryWrapper<'a, V, W>>,
+    EmptyQueryWrapper<'a>,
+    N,
+>;
+
 // base case for the QueryWrapper
 impl<'a> EmptyQueryWrapper<'a> {
     fn new() -> EmptyQueryWrapper<'a> {
@@ -127,9 +319,9 @@ impl<'a> EmptyQueryWrapper<'a> {
 // QueryWrapper out of those two pieces
 impl<'a, T, U, V> QueryWrapper<'a, T, QueryWrapper<'a, U, V>>
 where
-    T: Query + 'a,
-    U: Query + 'a,
-    V: Query + 'a,
+    T: 'a,
+    U: 'a,
+    V: 'a,
 {
     fn wrap(
         inner: T,
@@ -143,12 +335,12 @@ where
     }
 }
 
-impl<'a, T, U> Query for QueryWrapper<'a, T, U>
+impl<'a, T, U, N> Query<N> for QueryWrapper<'a, T, U>
 where
-    T: Query + 'a,
-    U: Query + 'a,
+    T: Query<N> + 'a,
+    U: Query<N> + 'a,
 {
-    fn matches(&self, node: &rcdom::Node) -> bool {
+    fn matches(&self, node: &N) -> bool {
         let inner_match = self.inner.matches(node);
         if let Some(ref next) = self.next {
             let next_match = next.matches(node);
@@ -157,6 +349,125 @@ where
             inner_match
         }
     }
+
+    // The earliest query in the chain with a key leads
+    fn index_key(&self) -> Option<IndexKey<'_>> {
+        self.next
+            .as_ref()
+            .and_then(|next| next.index_key())
+            .or_else(|| self.inner.index_key())
+    }
+}
+
+impl<'a, T: 'a, U: 'a> QueryWrapper<'a, T, U> {
+    // only the base of the chain is built without a `next`
+    fn is_empty(&self) -> bool {
+        self.next.is_none()
+    }
+}
+
+/// Matches a node against each query of a chain, succeeding if any of them match
+pub trait Alternatives<N = Handle> {
+    /// Returns `true` if any query in the chain matches `node`
+    fn any_matches(&self, node: &N) -> bool;
+}
+
+impl<N> Alternatives<N> for () {
+    fn any_matches(&self, _: &N) -> bool {
+        false
+    }
+}
+
+impl<'a, T, U, N> Alternatives<N> for QueryWrapper<'a, T, U>
+where
+    T: Query<N> + 'a,
+    U: Query<N> + Alternatives<N> + 'a,
+{
+    fn any_matches(&self, node: &N) -> bool {
+        match self.next {
+            Some(ref next) => self.inner.matches(node) || next.any_matches(node),
+            None => false,
+        }
+    }
+}
+
+pub struct OrQuery<L, R> {
+    left: Option<L>,
+    right: Option<R>,
+}
+
+impl<'a, T, U, V, W> OrQuery<QueryWrapper<'a, T, U>, QueryWrapper<'a, V, W>>
+where
+    T: 'a,
+    U: 'a,
+    V: 'a,
+    W: 'a,
+{
+    fn new(
+        left: QueryWrapper<'a, T, U>,
+        right: QueryWrapper<'a, V, W>,
+    ) -> OrQuery<QueryWrapper<'a, T, U>, QueryWrapper<'a, V, W>> {
+        OrQuery {
+            left: if left.is_empty() { None } else { Some(left) },
+            right: if right.is_empty() { None } else { Some(right) },
+        }
+    }
+}
+
+impl<L, R> fmt::Debug for OrQuery<L, R>
+where
+    L: fmt::Debug,
+    R: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrQuery")
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl<L: Query<N>, R: Query<N>, N> Query<N> for OrQuery<L, R> {
+    fn matches(&self, node: &N) -> bool {
+        self.left.as_ref().is_some_and(|left| left.matches(node))
+            || self.right.as_ref().is_some_and(|right| right.matches(node))
+    }
+}
+
+pub struct NotQuery<Q> {
+    inner: Q,
+}
+
+impl<Q: fmt::Debug> fmt::Debug for NotQuery<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotQuery")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<Q: Query<N>, N> Query<N> for NotQuery<Q> {
+    fn matches(&self, node: &N) -> bool {
+        !self.inner.matches(node)
+    }
+}
+
+pub struct AnyQuery<Q> {
+    inner: Q,
+}
+
+impl<Q: fmt::Debug> fmt::Debug for AnyQuery<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyQuery")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<Q: Alternatives<N>, N> Query<N> for AnyQuery<Q> {
+    fn matches(&self, node: &N) -> bool {
+        self.inner.any_matches(node)
+    }
 }
 
 /// Construct a query to apply to an HTML tree
@@ -174,34 +485,46 @@ where
 ///                 .find();            // executes the query, returns the first result
 /// #   Ok(())
 /// # }
-pub struct QueryBuilder<'a, T: Query + 'a = (), U: Query + 'a = ()> {
-    handle: Handle,
+/// ```
+///
+/// `N` is the type of node being searched, see the `dom` module
+pub struct QueryBuilder<'a, T: Query<N> + 'a = (), U: Query<N> + 'a = (), N: Node = Handle> {
+    handle: N,
     queries: QueryWrapper<'a, T, U>,
     limit: Option<usize>,
     recursive: bool,
+    // The index of the `Soup` the query was started on, if it keeps one
+    index: Option<Rc<dyn Lookup<N>>>,
 }
 
-impl<'a, T: Query + 'a, U: Query + 'a> fmt::Debug for QueryBuilder<'a, T, U> {
+impl<'a, T: Query<N> + 'a, U: Query<N> + 'a, N: Node> fmt::Debug for QueryBuilder<'a, T, U, N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "QueryBuilder(«Handle», «Queries»)")
     }
 }
 
-impl<'a> QueryBuilder<'a, (), ()> {
-    pub(crate) fn new(handle: Handle) -> QueryBuilder<'a, (), ()> {
+impl<'a, N: Node> QueryBuilder<'a, (), (), N> {
+    pub(crate) fn new(handle: N) -> QueryBuilder<'a, (), (), N> {
         QueryBuilder {
             handle,
             queries: QueryWrapper::new(),
             limit: None,
             recursive: true,
+            index: None,
         }
     }
+
+    pub(crate) fn with_index(mut self, index: Rc<dyn Lookup<N>>) -> QueryBuilder<'a, (), (), N> {
+        self.index = Some(index);
+        self
+    }
 }
 
-impl<'a, T, U> QueryBuilder<'a, T, U>
+impl<'a, T, U, N> QueryBuilder<'a, T, U, N>
 where
-    T: Query + 'a,
-    U: Query + 'a,
+    T: Query<N> + 'a,
+    U: Query<N> + 'a,
+    N: Node,
 {
     /// Adds a limit to the number of results that can be returned
     ///
@@ -221,21 +544,56 @@ where
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn limit(mut self, limit: usize) -> QueryBuilder<'a, T, U> {
+    pub fn limit(mut self, limit: usize) -> QueryBuilder<'a, T, U, N> {
         self.limit = Some(limit);
         self
     }
 
-    fn push_query<Q: Query + 'a>(self, query: Q) -> QueryBuilder<'a, Q, QueryWrapper<'a, T, U>> {
+    pub(crate) fn push_query<Q: Query<N> + 'a>(self, query: Q) -> QueryBuilder<'a, Q, QueryWrapper<'a, T, U>, N> {
         let queries = QueryWrapper::<'a, Q, QueryWrapper<'a, T, U>>::wrap(query, self.queries);
         QueryBuilder {
             handle: self.handle,
             queries,
             limit: self.limit,
             recursive: self.recursive,
+            index: self.index,
         }
     }
 
+    /// Adds a custom `Query` that results must match
+    ///
+    /// See the `Query` trait for an example
+    pub fn query<Q: Query<N> + 'a>(self, query: Q) -> QueryBuilder<'a, Q, QueryWrapper<'a, T, U>, N> {
+        self.push_query(query)
+    }
+
+    /// Adds a closure that results must satisfy
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<a href="/1">Prev</a><a href="/3">Next</a>"#);
+    /// let next = soup.tag("a")
+    ///                .filter(|a| a.text() == "Next")
+    ///                .find()
+    ///                .expect("Couldn't find link");
+    /// assert_eq!(next.get("href"), Some("/3".to_string()));
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn filter<F>(self, filter: F) -> QueryBuilder<'a, FilterQuery<F>, QueryWrapper<'a, T, U>, N>
+    where
+        F: Fn(&N) -> bool + 'a,
+    {
+        self.push_query(FilterQuery {
+            filter,
+        })
+    }
+
     /// Specifies a tag for which to search
     ///
     /// # Example
@@ -252,10 +610,40 @@ where
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn tag<P: Pattern>(self, tag: P) -> QueryBuilder<'a, TagQuery<P>, QueryWrapper<'a, T, U>> {
+    pub fn tag<P: Pattern>(self, tag: P) -> QueryBuilder<'a, TagQuery<P>, QueryWrapper<'a, T, U>, N> {
         self.push_query(TagQuery::new(tag))
     }
 
+    /// Specifies a namespace & local name for which to search
+    ///
+    /// `ns` is matched against the namespace URI of the element, like
+    /// `"http://www.w3.org/2000/svg"`, and `tag` against its local name, without any prefix.
+    /// This tells apart elements that share a name, like the `a` elements of HTML & SVG.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<a href="/html">HTML</a><svg><a href="/svg">SVG</a></svg>"#);
+    /// let a = soup.tag_ns("http://www.w3.org/2000/svg", "a").find().expect("Couldn't find svg 'a'");
+    /// assert_eq!(a.get("href"), Some("/svg".to_string()));
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn tag_ns<M, P>(self, ns: M, tag: P) -> QueryBuilder<'a, TagNsQuery<M, P>, QueryWrapper<'a, T, U>, N>
+    where
+        M: Pattern,
+        P: Pattern,
+    {
+        self.push_query(TagNsQuery {
+            ns,
+            inner: tag,
+        })
+    }
+
     /// Searches for a tag that has an attribute with the specified name
     ///
     /// # Example
@@ -271,7 +659,7 @@ where
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn attr_name<P>(self, name: P) -> QueryBuilder<'a, AttrQuery<P, bool>, QueryWrapper<'a, T, U>>
+    pub fn attr_name<P>(self, name: P) -> QueryBuilder<'a, AttrQuery<P, bool>, QueryWrapper<'a, T, U>, N>
     where
         P: Pattern
     {
@@ -293,7 +681,7 @@ where
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn attr_value<P>(self, value: P) -> QueryBuilder<'a, AttrQuery<bool, P>, QueryWrapper<'a, T, U>>
+    pub fn attr_value<P>(self, value: P) -> QueryBuilder<'a, AttrQuery<bool, P>, QueryWrapper<'a, T, U>, N>
     where
         P: Pattern
     {
@@ -320,7 +708,7 @@ where
         self,
         name: P,
         value: Q,
-    ) -> QueryBuilder<'a, AttrQuery<P, Q>, QueryWrapper<'a, T, U>>
+    ) -> QueryBuilder<'a, AttrQuery<P, Q>, QueryWrapper<'a, T, U>, N>
     where
         P: Pattern,
         Q: Pattern,
@@ -328,6 +716,46 @@ where
         self.push_query(AttrQuery::new(name, value))
     }
 
+    /// Specifies a namespaced attribute name/value pair for which to search
+    ///
+    /// `ns` is matched against the namespace URI of the attribute, and `name` against its local
+    /// name, without any prefix. Most attributes aren't in a namespace, which is written `""`,
+    /// but some like `xlink:href` are.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r##"<svg><use href="#a"/><use xlink:href="#b"/></svg>"##);
+    /// let uses = soup.attr_ns("http://www.w3.org/1999/xlink", "href", true)
+    ///                .find_all()
+    ///                .collect::<Vec<_>>();
+    /// assert_eq!(uses.len(), 1);
+    /// assert_eq!(uses[0].get("xlink:href"), Some("#b".to_string()));
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn attr_ns<M, P, Q>(
+        self,
+        ns: M,
+        name: P,
+        value: Q,
+    ) -> QueryBuilder<'a, AttrNsQuery<M, P, Q>, QueryWrapper<'a, T, U>, N>
+    where
+        M: Pattern,
+        P: Pattern,
+        Q: Pattern,
+    {
+        self.push_query(AttrNsQuery {
+            ns,
+            key: name,
+            value,
+        })
+    }
+
     /// Specifies a class name for which to search
     ///
     /// # Example
@@ -348,10 +776,201 @@ where
     pub fn class<P: Pattern>(
         self,
         value: P,
-    ) -> QueryBuilder<'a, AttrQuery<&'static str, P>, QueryWrapper<'a, T, U>> {
+    ) -> QueryBuilder<'a, AttrQuery<&'static str, P>, QueryWrapper<'a, T, U>, N> {
         self.attr("class", value)
     }
 
+    /// Matches nodes that match either the query built so far, or the query built by `group`
+    ///
+    /// `group` is given an empty `QueryBuilder` to add queries to, which are combined as usual.
+    /// Any `limit` or `recursive` setting made inside `group` is ignored. An empty query on
+    /// either side of the `or` matches nothing, so `or` can also be used to start a query.
+    /// Queries added after the `or` apply to both sides.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<h1>ONE</h1><p>TWO</p><h2 class="x">THREE</h2><h2>FOUR</h2>"#);
+    /// let headings = soup.tag("h1")
+    ///                    .or(|q| q.tag("h2").class("x"))
+    ///                    .find_all()
+    ///                    .map(|h| h.text())
+    ///                    .collect::<Vec<_>>();
+    /// assert_eq!(headings, vec!["ONE".to_string(), "THREE".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn or<F, V, W>(
+        self,
+        group: F,
+    ) -> OrQueryBuilder<'a, T, U, V, W, N>
+    where
+        F: FnOnce(QueryBuilder<'a, (), (), N>) -> QueryBuilder<'a, V, W, N>,
+        V: Query<N> + 'a,
+        W: Query<N> + 'a,
+    {
+        let group = group(QueryBuilder::new(self.handle.clone()));
+        let query = OrQuery::new(self.queries, group.queries);
+        QueryBuilder {
+            handle: self.handle,
+            queries: QueryWrapper::wrap(query, QueryWrapper::new()),
+            limit: self.limit,
+            recursive: self.recursive,
+            index: self.index,
+        }
+    }
+
+    /// Excludes nodes that match the query built by `group`
+    ///
+    /// `group` is given an empty `QueryBuilder` to add queries to, and nodes matching all of
+    /// them are excluded. Any `limit` or `recursive` setting made inside `group` is ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div class="ad">ONE</div><div class="ad" id="keep">TWO</div>"#);
+    /// let ads = soup.class("ad")
+    ///               .not(|q| q.attr("id", "keep"))
+    ///               .find_all()
+    ///               .map(|ad| ad.text())
+    ///               .collect::<Vec<_>>();
+    /// assert_eq!(ads, vec!["ONE".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn not<F, V, W>(
+        self,
+        group: F,
+    ) -> QueryBuilder<'a, NotQuery<QueryWrapper<'a, V, W>>, QueryWrapper<'a, T, U>, N>
+    where
+        F: FnOnce(QueryBuilder<'a, (), (), N>) -> QueryBuilder<'a, V, W, N>,
+        V: Query<N> + 'a,
+        W: Query<N> + 'a,
+    {
+        let group = group(QueryBuilder::new(self.handle.clone()));
+        self.push_query(NotQuery {
+            inner: group.queries,
+        })
+    }
+
+    /// Matches nodes that match any one of the queries added by `group`
+    ///
+    /// Unlike everywhere else, the queries added to the `QueryBuilder` given to `group` are
+    /// combined with "or" instead of "and". Any `limit` or `recursive` setting made inside
+    /// `group` is ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<h1>ONE</h1><p>TWO</p><h2>THREE</h2><h3>FOUR</h3>"#);
+    /// let headings = soup.any_of(|q| q.tag("h1").tag("h2"))
+    ///                    .find_all()
+    ///                    .map(|h| h.text())
+    ///                    .collect::<Vec<_>>();
+    /// assert_eq!(headings, vec!["ONE".to_string(), "THREE".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn any_of<F, V, W>(
+        self,
+        group: F,
+    ) -> QueryBuilder<'a, AnyQuery<QueryWrapper<'a, V, W>>, QueryWrapper<'a, T, U>, N>
+    where
+        F: FnOnce(QueryBuilder<'a, (), (), N>) -> QueryBuilder<'a, V, W, N>,
+        V: Query<N> + 'a,
+        W: Query<N> + Alternatives<N> + 'a,
+    {
+        let group = group(QueryBuilder::new(self.handle.clone()));
+        self.push_query(AnyQuery {
+            inner: group.queries,
+        })
+    }
+
+    /// Searches for an element whose text matches the specified pattern
+    ///
+    /// The text of an element is all of the text inside of it, as returned by `NodeExt::text`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<ul><li><a href="/1">Prev</a></li><li><a href="/3">Next</a></li></ul>"#);
+    /// let li = soup.tag("li").text("Next").find().expect("Couldn't find li with text 'Next'");
+    /// assert_eq!(li.display(), r#"<li><a href="/3">Next</a></li>"#);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn text<P: Pattern>(self, text: P) -> QueryBuilder<'a, TextQuery<P>, QueryWrapper<'a, T, U>, N> {
+        self.push_query(TextQuery::new(text, TextSource::Descendants))
+    }
+
+    /// Searches for an element whose own text matches the specified pattern
+    ///
+    /// The own text of an element is the text of its direct text children, so text inside of
+    /// child elements is not included
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div>Price: <b>$5</b></div>"#);
+    /// let results = soup.own_text("Price: ").find_all().collect::<Vec<_>>();
+    /// assert_eq!(results.len(), 1);
+    /// assert_eq!(results[0].name(), "div");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn own_text<P: Pattern>(
+        self,
+        text: P,
+    ) -> QueryBuilder<'a, TextQuery<P>, QueryWrapper<'a, T, U>, N> {
+        self.push_query(TextQuery::new(text, TextSource::Children))
+    }
+
+    /// Searches for text nodes whose contents match the specified pattern
+    ///
+    /// Unlike `text` and `own_text`, this matches the text nodes themselves rather than the
+    /// elements containing them, so it cannot be combined with `tag` or `attr`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p>Elsie, <a>Lacie</a> and <a>Tillie</a></p>"#);
+    /// let names = soup.string(true)
+    ///                 .find_all()
+    ///                 .filter(|text| text.parent().is_some_and(|parent| parent.name() == "a"))
+    ///                 .map(|text| text.text())
+    ///                 .collect::<Vec<_>>();
+    /// assert_eq!(names, vec!["Lacie".to_string(), "Tillie".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn string<P: Pattern>(self, text: P) -> QueryBuilder<'a, TextQuery<P>, QueryWrapper<'a, T, U>, N> {
+        self.push_query(TextQuery::new(text, TextSource::Node))
+    }
+
     /// Specifies whether the query should recurse all the way through the document, or
     /// stay localized to the queried tag and it's children
     pub fn recursive(mut self, recursive: bool) -> Self {
@@ -376,7 +995,10 @@ where
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn find(mut self) -> Option<Handle> {
+    pub fn find(mut self) -> Option<N>
+    where
+        N: 'a,
+    {
         self.limit = Some(1);
         self.into_iter().nth(0)
     }
@@ -401,92 +1023,213 @@ where
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn find_all(self) -> BoxNodeIter<'a> {
+    pub fn find_all(self) -> BoxNodeIter<'a, N>
+    where
+        N: 'a,
+    {
         self.into_iter()
     }
+
+    /// Executes the query against the nodes that come after the queried node in document
+    /// order, and returns either the first result, or `None`
+    ///
+    /// Unlike `find`, the search is not limited to the subtree of the queried node, and
+    /// `recursive` has no effect.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div><h2>Intro</h2></div><p>ONE</p><h2>Next</h2><p>TWO</p>"#);
+    /// let h2 = soup.tag("h2").find().expect("Couldn't find tag 'h2'");
+    /// let p = h2.tag("p").find_next().expect("Couldn't find tag 'p'");
+    /// assert_eq!(p.text(), "ONE".to_string());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn find_next(mut self) -> Option<N>
+    where
+        N: 'a,
+    {
+        self.limit = Some(1);
+        self.find_all_next().next()
+    }
+
+    /// Executes the query against the nodes that come after the queried node in document
+    /// order, and returns an iterator of the results
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p>ZERO</p><div><h2>Intro</h2></div><p>ONE</p><p>TWO</p>"#);
+    /// let h2 = soup.tag("h2").find().expect("Couldn't find tag 'h2'");
+    /// let after = h2.tag("p").find_all_next().map(|p| p.text()).collect::<Vec<_>>();
+    /// assert_eq!(after, vec!["ONE".to_string(), "TWO".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn find_all_next(self) -> BoxNodeIter<'a, N>
+    where
+        N: 'a,
+    {
+        let nodes = DocumentOrder::after(&self.handle);
+        self.filter_nodes(nodes)
+    }
+
+    /// Executes the query against the nodes that come before the queried node in document
+    /// order, nearest first, and returns either the first result, or `None`
+    ///
+    /// The nodes searched include the ancestors of the queried node
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<h2>ONE</h2><p>Text</p><h2>TWO</h2><p id="x">Text</p>"#);
+    /// let p = soup.attr("id", "x").find().expect("Couldn't find tag with id 'x'");
+    /// let h2 = p.tag("h2").find_previous().expect("Couldn't find tag 'h2'");
+    /// assert_eq!(h2.text(), "TWO".to_string());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn find_previous(mut self) -> Option<N>
+    where
+        N: 'a,
+    {
+        self.limit = Some(1);
+        self.find_all_previous().next()
+    }
+
+    /// Executes the query against the nodes that come before the queried node in document
+    /// order, nearest first, and returns an iterator of the results
+    ///
+    /// The nodes searched include the ancestors of the queried node
+    pub fn find_all_previous(self) -> BoxNodeIter<'a, N>
+    where
+        N: 'a,
+    {
+        let nodes = DocumentOrder::before(&self.handle);
+        self.filter_nodes(nodes)
+    }
+
+    fn filter_nodes<I>(self, nodes: I) -> BoxNodeIter<'a, N>
+    where
+        I: Iterator<Item = N> + 'a,
+        N: 'a,
+    {
+        let queries = self.queries;
+        let iter = nodes.filter(move |node| queries.matches(node));
+        if let Some(limit) = self.limit {
+            Box::new(iter.take(limit))
+        } else {
+            Box::new(iter)
+        }
+    }
 }
 
-struct NodeIterator<'a, T: Query + 'a, U: Query + 'a> {
-    handle: Handle,
-    queries: Rc<QueryWrapper<'a, T, U>>,
-    done: bool,
+// Lazy pre-order walk over the subtree rooted at `root`, yielding the nodes that match
+// `queries`. Each stack entry is an iterator over the children of a node that are still to be
+// visited
+struct NodeIterator<'a, T: Query<N> + 'a, U: Query<N> + 'a, N: Node> {
+    root: Option<N>,
+    stack: Vec<N::Children>,
+    queries: QueryWrapper<'a, T, U>,
+    max_depth: Option<usize>,
 }
 
-impl<'a, T: Query + 'a, U: Query + 'a> NodeIterator<'a, T, U> {
-    fn new(handle: Handle, queries: Rc<QueryWrapper<'a, T, U>>) -> NodeIterator<'a, T, U> {
+impl<'a, T: Query<N> + 'a, U: Query<N> + 'a, N: Node> NodeIterator<'a, T, U, N> {
+    fn new(
+        root: N,
+        queries: QueryWrapper<'a, T, U>,
+        max_depth: Option<usize>,
+    ) -> NodeIterator<'a, T, U, N> {
         NodeIterator {
-            handle,
+            root: Some(root),
+            stack: vec![],
             queries,
-            done: false,
+            max_depth,
+        }
+    }
+
+    fn next_node(&mut self) -> Option<N> {
+        if let Some(root) = self.root.take() {
+            return Some(root);
+        }
+        loop {
+            let child = self.stack.last_mut()?.next();
+            match child {
+                Some(child) => return Some(child),
+                None => {
+                    self.stack.pop();
+                },
+            }
         }
     }
 }
 
-impl<'a, T, U> Iterator for NodeIterator<'a, T, U>
+impl<'a, T, U, N> Iterator for NodeIterator<'a, T, U, N>
 where
-    T: Query + 'a,
-    U: Query + 'a,
+    T: Query<N> + 'a,
+    U: Query<N> + 'a,
+    N: Node,
 {
-    type Item = Option<Handle>;
+    type Item = N;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.done {
-            return None;
-        }
-        if Query::matches(&*self.queries, &self.handle) {
-            self.done = true;
-            Some(Some(self.handle.clone()))
-        } else {
-            self.done = true;
-            Some(None)
+        loop {
+            let node = self.next_node()?;
+            let depth = self.stack.len();
+            if self.max_depth.is_none_or(|max| depth < max) {
+                self.stack.push(node.child_nodes());
+            }
+            if self.queries.matches(&node) {
+                return Some(node);
+            }
         }
     }
-
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (0, Some(1))
-    }
 }
 
-type BoxOptionNodeIter<'a> = Box<dyn Iterator<Item = Option<Handle>> + 'a>;
-type BoxNodeIter<'a> = Box<dyn Iterator<Item = Handle> + 'a>;
+pub(crate) type BoxNodeIter<'a, N = Handle> = Box<dyn Iterator<Item = N> + 'a>;
 
-impl<'a, T: Query + 'a, U: Query + 'a> IntoIterator for QueryBuilder<'a, T, U> {
-    type IntoIter = BoxNodeIter<'a>;
-    type Item = Handle;
+impl<'a, T, U, N> IntoIterator for QueryBuilder<'a, T, U, N>
+where
+    T: Query<N> + 'a,
+    U: Query<N> + 'a,
+    N: Node + 'a,
+{
+    type IntoIter = BoxNodeIter<'a, N>;
+    type Item = N;
 
     fn into_iter(self) -> Self::IntoIter {
-        let queries = Rc::new(self.queries);
-        let recurse_levels = if self.recursive {
+        let max_depth = if self.recursive {
             None
         } else {
-            Some(1u8)
+            Some(1)
         };
-        let iter = build_iter(self.handle, queries, recurse_levels);
-        let iter: BoxNodeIter<'_> = Box::new(iter.flat_map(|node| node));
+        if self.recursive {
+            let indexed = self.queries.index_key().and_then(|key| match self.index {
+                Some(ref index) => index.lookup(&self.handle, &key),
+                None => self.handle.indexed(&key),
+            });
+            if let Some(nodes) = indexed {
+                return self.filter_nodes(nodes.into_iter());
+            }
+        }
+        let iter = NodeIterator::new(self.handle, self.queries, max_depth);
         if let Some(limit) = self.limit {
-            let iter: BoxNodeIter<'_> = Box::new(iter.take(limit));
-            iter
+            Box::new(iter.take(limit))
         } else {
-            iter
-        }
-    }
-}
-
-fn build_iter<'a, T: Query + 'a, U: Query + 'a>(
-    handle: Handle,
-    queries: Rc<QueryWrapper<'a, T, U>>,
-    levels: Option<u8>,
-) -> BoxOptionNodeIter<'a> {
-    let iter = NodeIterator::new(handle.clone(), queries.clone());
-    let iter: BoxOptionNodeIter<'_> = Box::new(iter);
-    if let Some(l) = levels {
-        if l == 0 {
-            return iter;
+            Box::new(iter)
         }
     }
-    handle.children.borrow().iter().fold(iter, |acc, child| {
-        let child_iter = build_iter(child.clone(), queries.clone(), levels.map(|l| l - 1));
-        let child_iter: BoxOptionNodeIter<'_> = Box::new(child_iter);
-        Box::new(acc.chain(child_iter))
-    })
 }
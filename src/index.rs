@@ -0,0 +1,235 @@
+use html5ever::rcdom::{Handle, Node, NodeData};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::dom::{self, IndexKey};
+
+// The elements of a document, by tag name, id & class, in document order
+//
+// Only `Weak`s are kept, so that nodes removed from the document aren't kept alive by the index
+#[derive(Default)]
+struct Index {
+    tags: HashMap<String, Vec<Weak<Node>>>,
+    ids: HashMap<String, Vec<Weak<Node>>>,
+    classes: HashMap<String, Vec<Weak<Node>>>,
+}
+
+impl Index {
+    // Walks the tree under `document`, without recursing so deep trees are fine
+    fn build(document: &Handle) -> Index {
+        let mut index = Index::default();
+        let mut stack = vec![document.clone()];
+        while let Some(node) = stack.pop() {
+            if let NodeData::Element {
+                ref name,
+                ref attrs,
+                ..
+            } = node.data
+            {
+                let weak = Rc::downgrade(&node);
+                add(&mut index.tags, &name.local, &weak);
+                if let Some(ref prefix) = name.prefix {
+                    add(&mut index.tags, &format!("{}:{}", prefix, name.local), &weak);
+                }
+                for attr in attrs.borrow().iter() {
+                    match attr.name.local.as_ref() {
+                        "id" => add(&mut index.ids, &attr.value, &weak),
+                        "class" => {
+                            for class in attr.value.split_whitespace() {
+                                add(&mut index.classes, class, &weak);
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+            }
+            stack.extend(node.children.borrow().iter().rev().cloned());
+        }
+        index
+    }
+
+    fn get(&self, key: &IndexKey<'_>) -> &[Weak<Node>] {
+        let (map, key) = match *key {
+            IndexKey::Tag(tag) => (&self.tags, tag),
+            IndexKey::Id(id) => (&self.ids, id),
+            IndexKey::Class(class) => (&self.classes, class),
+        };
+        map.get(key).map_or(&[], |nodes| nodes.as_slice())
+    }
+}
+
+fn add(map: &mut HashMap<String, Vec<Weak<Node>>>, key: &str, node: &Weak<Node>) {
+    let nodes = map.entry(key.to_string()).or_default();
+    // An element is only added once, even if it has the same class twice
+    if !nodes.last().is_some_and(|last| last.ptr_eq(node)) {
+        nodes.push(node.clone());
+    }
+}
+
+thread_local! {
+    // Counts the changes this crate has made to documents that could affect an index, so an
+    // index can tell that it's out of date the next time it's used
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+fn generation() -> u64 {
+    GENERATION.with(Cell::get)
+}
+
+// Marks every index as out of date, because some document is about to change
+pub(crate) fn changed() {
+    GENERATION.with(|generation| generation.set(generation.get() + 1))
+}
+
+// The index a `Soup` keeps of its document, if it's been asked to, along with the generation it
+// was built in
+#[derive(Default)]
+pub(crate) struct SoupIndex {
+    index: RefCell<Option<(u64, Index)>>,
+}
+
+impl SoupIndex {
+    // Indexes the document, replacing any index there already was
+    pub(crate) fn build(&self, document: &Handle) {
+        *self.index.borrow_mut() = Some((generation(), Index::build(document)));
+    }
+}
+
+// Looks up the elements a query starts with, so that `QueryBuilder` doesn't have to walk the
+// tree to find them
+pub(crate) trait Lookup<N> {
+    // The elements in & under `root` that `key` points to, in document order, or `None` if
+    // there's nothing to look them up in
+    fn lookup(&self, root: &N, key: &IndexKey<'_>) -> Option<Vec<N>>;
+}
+
+impl Lookup<Handle> for SoupIndex {
+    // Queries on a `Soup` start from its document, so that's the `root`. An index that's out of
+    // date is rebuilt first, & the elements are checked against the tree, so that ones that are
+    // no longer under the root are left out. `QueryBuilder` checks that the rest still match the
+    // whole query.
+    fn lookup(&self, root: &Handle, key: &IndexKey<'_>) -> Option<Vec<Handle>> {
+        let mut current = self.index.borrow_mut();
+        let (built, index) = current.as_mut()?;
+        if *built != generation() {
+            *built = generation();
+            *index = Index::build(root);
+        }
+        let nodes = index
+            .get(key)
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|candidate| is_inclusive_descendant(candidate, root))
+            .collect();
+        Some(nodes)
+    }
+}
+
+// Whether `node` is `ancestor` or one of its descendants
+fn is_inclusive_descendant(node: &Handle, ancestor: &Handle) -> bool {
+    let mut current = Some(node.clone());
+    while let Some(node) = current {
+        if Rc::ptr_eq(&node, ancestor) {
+            return true;
+        }
+        current = dom::Node::parent_node(&node);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use html5ever::rcdom::{Handle, NodeData};
+
+    const HTML: &str = r#"<div id="main"><p class="a b a">ONE</p><section><p id="x" class="b">TWO</p></section></div><p class="b">THREE</p>"#;
+
+    fn texts<I: Iterator<Item = Handle>>(nodes: I) -> Vec<String> {
+        nodes.map(|node| node.text()).collect()
+    }
+
+    #[test]
+    fn same_results_as_walking() {
+        let plain = Soup::new(HTML);
+        let indexed = Soup::builder().index(true).parse(HTML);
+        assert_eq!(texts(indexed.class("b").find_all()), texts(plain.class("b").find_all()));
+        assert_eq!(texts(indexed.class("b").find_all()), vec!["ONE", "TWO", "THREE"]);
+        assert_eq!(texts(indexed.tag("p").class("a").find_all()), vec!["ONE"]);
+        assert_eq!(texts(indexed.attr("id", "x").tag("p").find_all()), vec!["TWO"]);
+        assert_eq!(indexed.class("b").limit(2).find_all().count(), 2);
+        let main = indexed.attr("id", "main").find().expect("Couldn't find id 'main'");
+        assert_eq!(texts(main.class("b").find_all()), vec!["ONE", "TWO"]);
+        assert_eq!(texts(main.tag("div").find_all()), vec!["ONETWO"]);
+        assert_eq!(main.tag("p").recursive(false).find_all().count(), 1);
+        assert!(indexed.class("missing").find().is_none());
+    }
+
+    #[test]
+    fn changes_after_indexing() {
+        let soup = Soup::builder().index(true).parse(HTML);
+        let x = soup.attr("id", "x").find().expect("Couldn't find id 'x'");
+        x.remove_class("b");
+        x.extract();
+        assert_eq!(texts(soup.class("b").find_all()), vec!["ONE", "THREE"]);
+        assert!(soup.attr("id", "x").find().is_none());
+        let main = soup.attr("id", "main").find().expect("Couldn't find id 'main'");
+        main.append(x);
+        assert_eq!(texts(soup.attr("id", "x").find_all()), vec!["TWO"]);
+        let y = Handle::new_element("p");
+        y.set_attr("id", "y");
+        main.append(y);
+        assert!(soup.attr("id", "y").find().is_some());
+        let one = soup.class("a").find().expect("Couldn't find class 'a'");
+        one.add_class("new");
+        assert_eq!(texts(soup.class("new").find_all()), vec!["ONE"]);
+        one.set_attr("ID", "one");
+        assert!(soup.attr("id", "one").find().is_some());
+    }
+
+    #[test]
+    fn document_order_after_moves() {
+        let soup = Soup::builder().index(true).parse(HTML);
+        let three = soup.tag("p").find_all().last().expect("Couldn't find last 'p'");
+        let main = soup.attr("id", "main").find().expect("Couldn't find id 'main'");
+        three.extract();
+        main.insert_before(three.clone());
+        assert_eq!(texts(soup.class("b").find_all()), vec!["THREE", "ONE", "TWO"]);
+        main.prepend(three);
+        assert_eq!(texts(soup.class("b").find_all()), vec!["THREE", "ONE", "TWO"]);
+        assert_eq!(texts(main.tag("p").recursive(false).find_all()), vec!["THREE", "ONE"]);
+        let one = main.tag("p").find_all().nth(1).expect("Couldn't find 'p'");
+        one.decompose();
+        assert_eq!(texts(soup.class("b").find_all()), vec!["THREE", "TWO"]);
+    }
+
+    #[test]
+    fn direct_changes() {
+        let soup = Soup::builder().index(true).parse(HTML);
+        let x = soup.attr("id", "x").find().expect("Couldn't find id 'x'");
+        if let NodeData::Element { ref attrs, .. } = x.data {
+            attrs.borrow_mut()[0].value = "y".into();
+        }
+        assert!(soup.attr("id", "x").find().is_none());
+        // The index isn't told about changes made straight to the nodes, so it takes a rebuild
+        // to find `x` under its new id
+        assert!(soup.attr("id", "y").find().is_none());
+        soup.build_index();
+        assert_eq!(texts(soup.attr("id", "y").find_all()), vec!["TWO"]);
+        let main = soup.attr("id", "main").find().expect("Couldn't find id 'main'");
+        main.children.borrow_mut().clear();
+        soup.build_index();
+        assert_eq!(texts(soup.class("b").find_all()), vec!["THREE"]);
+    }
+
+    #[test]
+    fn prefixed_tags() {
+        let soup = Soup::builder()
+            .index(true)
+            .parse_xml(r#"<feed xmlns:media="http://search.yahoo.com/mrss/"><media:content url="a"/></feed>"#);
+        assert!(soup.tag("media:content").find().is_some());
+        assert!(soup.tag("content").find().is_some());
+    }
+}
@@ -297,37 +297,68 @@
     rust_2018_compatibility,
     rust_2018_idioms
 )]
+extern crate encoding_rs;
 extern crate html5ever;
 #[cfg(feature = "regex")]
 extern crate regex;
+extern crate xml5ever;
 
-use html5ever::{
-    parse_document,
-    rcdom::RcDom,
-    tendril::TendrilSink,
-};
+use encoding_rs::Encoding;
+use html5ever::rcdom::RcDom;
 use std::{
     fmt,
     io::{self, Read},
+    rc::Rc,
 };
 
+use crate::{index::SoupIndex, position::Positions};
+
 /// This module exports all the important types & traits to use `soup`
 /// effectively
 pub mod prelude {
-    pub use crate::{node_ext::NodeExt, qb_ext::QueryBuilderExt, Soup};
+    pub use crate::{node_ext::NodeExt, qb_ext::QueryBuilderExt, tree_ext::TreeExt, Soup};
 }
 
-pub use crate::{find::QueryBuilder, node_ext::NodeExt, qb_ext::QueryBuilderExt};
+pub use crate::{
+    arena::{ArenaSink, NodeId},
+    find::{Query, QueryBuilder},
+    node_ext::NodeExt,
+    options::SoupOptions,
+    position::SourcePosition,
+    sink::ParseError,
+    qb_ext::QueryBuilderExt,
+    sync::{SyncNode, SyncSoup},
+    tree_ext::TreeExt,
+};
+pub use html5ever::tree_builder::QuirksMode;
 
+mod arena;
 mod attribute;
+mod encoding;
 mod find;
+mod index;
 mod qb_ext;
 mod node_ext;
+mod options;
+mod position;
+mod sink;
+mod sync;
+mod tree_ext;
+mod xml;
+pub mod dom;
 pub mod pattern;
+pub mod selector;
+pub mod serialize;
+pub mod text;
+pub mod xpath;
 
 /// Parses HTML & provides methods to query & manipulate the document
 pub struct Soup {
     handle: RcDom,
+    original_encoding: Option<&'static Encoding>,
+    errors: Vec<ParseError>,
+    positions: Positions,
+    index: Rc<SoupIndex>,
 }
 
 impl Soup {
@@ -359,17 +390,181 @@ impl Soup {
     /// # }
     /// ```
     pub fn new(html: &str) -> Soup {
-        let dom = parse_document(RcDom::default(), Default::default())
-            .from_utf8()
-            .one(html.as_bytes());
-        Soup {
-            handle: dom,
-        }
+        SoupOptions::new().parse(html)
+    }
+
+    /// Create a new `Soup` instance from a string slice, failing with the first parse error if
+    /// the HTML has any
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// assert!(Soup::new_strict("<!DOCTYPE html><title>T</title><p>Valid</p>").is_ok());
+    /// let err = Soup::new_strict("<!DOCTYPE html><title>T</title>\n<p>Invalid</b>").unwrap_err();
+    /// assert_eq!(err.line(), Some(2));
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn new_strict(html: &str) -> Result<Soup, ParseError> {
+        SoupOptions::new().parse_strict(html)
+    }
+
+    /// Returns the default `SoupOptions`, for parsing a `Soup` with options other than the
+    /// defaults
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::builder().drop_comments(true).parse(r#"<p>Some <!-- hidden -->text</p>"#);
+    /// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+    /// assert_eq!(p.display(), "<p>Some text</p>");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn builder() -> SoupOptions {
+        SoupOptions::new()
+    }
+
+    /// Create a new `Soup` instance from a fragment of HTML, as if it were the contents of a
+    /// `body` element
+    ///
+    /// Unlike `Soup::new`, no `html`, `head` or `body` elements are added, so the fragment's
+    /// nodes are the top-level nodes of the soup.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::fragment(r#"<p>One</p><p>Two</p>"#);
+    /// assert!(soup.tag("body").find().is_none());
+    /// let paragraphs = soup.tag("p").recursive(false).find_all().collect::<Vec<_>>();
+    /// assert_eq!(paragraphs.len(), 2);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn fragment(html: &str) -> Soup {
+        Soup::fragment_in_context(html, "body")
+    }
+
+    /// Create a new `Soup` instance from a fragment of HTML, as if it were the contents of an
+    /// element named `context`
+    ///
+    /// The context decides how the fragment is parsed, so for example table cells are only
+    /// kept when the context is a table row.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::fragment_in_context(r#"<td>a</td><td>b</td>"#, "tr");
+    /// let cells = soup.tag("td").find_all().map(|td| td.text()).collect::<Vec<_>>();
+    /// assert_eq!(cells, vec!["a", "b"]);
+    /// assert!(Soup::fragment(r#"<td>a</td>"#).tag("td").find().is_none());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn fragment_in_context(html: &str, context: &str) -> Soup {
+        SoupOptions::new().fragment_in_context(html, context)
+    }
+
+    /// Create a new `Soup` instance from an XML document, like an RSS feed or a sitemap
+    ///
+    /// Unlike HTML, names are case-sensitive, empty elements can be written as `<tag/>`, and
+    /// prefixed names like `media:content` are put in the namespace their prefix is bound to.
+    /// Queries match names with or without their prefix. Malformed documents are still
+    /// parsed as well as they can be, with the problems recorded in `Soup::errors`. Entities
+    /// declared in the doctype are expanded, but external DTDs & entities are never loaded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::xml(r#"<?xml version="1.0"?>
+    /// <rss xmlns:media="http://search.yahoo.com/mrss/"><channel>
+    ///   <item><title>Post</title><media:content url="a.jpg"/><guid isPermaLink="false">1</guid></item>
+    /// </channel></rss>"#);
+    /// let content = soup.tag("media:content").find().expect("Couldn't find tag 'media:content'");
+    /// assert_eq!(content.get("url"), Some("a.jpg".to_string()));
+    /// assert_eq!(content.display(), r#"<media:content url="a.jpg"></media:content>"#);
+    /// let guid = soup.attr("isPermaLink", "false").find().expect("Couldn't find guid");
+    /// assert_eq!(guid.text(), "1");
+    /// assert!(soup.errors().is_empty());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn xml(xml: &str) -> Soup {
+        SoupOptions::new().parse_xml(xml)
+    }
+
+    /// Create a new `Soup` instance from raw bytes, working out their encoding
+    ///
+    /// The encoding is taken from a byte order mark if there is one, or else from a
+    /// `<meta charset>` or `<meta http-equiv="Content-Type">` declaration near the start of
+    /// the document. Without either, the bytes are decoded as UTF-8 if they're valid UTF-8, and
+    /// as windows-1252 otherwise. The encoding that was used is available from
+    /// `Soup::original_encoding`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::from_bytes(b"<meta charset=iso-8859-1><p>caf\xE9</p>");
+    /// assert_eq!(soup.original_encoding(), Some("windows-1252"));
+    /// assert_eq!(soup.tag("p").find().expect("Couldn't find tag 'p'").text(), "caf\u{e9}");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Soup {
+        SoupOptions::new().parse_bytes(bytes)
+    }
+
+    /// Create a new `Soup` instance from raw bytes, falling back to the encoding labelled
+    /// `hint` if there's no byte order mark or `<meta>` declaration
+    ///
+    /// The hint would usually be the `charset` parameter of an HTTP `Content-Type` header.
+    /// Unknown labels are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::from_bytes_with_hint(b"<p>\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd</p>", "Shift_JIS");
+    /// assert_eq!(soup.original_encoding(), Some("Shift_JIS"));
+    /// assert_eq!(soup.text(), "\u{3053}\u{3093}\u{306b}\u{3061}\u{306f}");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn from_bytes_with_hint(bytes: &[u8], hint: &str) -> Soup {
+        SoupOptions::new().encoding_hint(hint).parse_bytes(bytes)
     }
 
     /// Create a new `Soup` instance from something that implements `Read`
     ///
-    /// This is good for parsing the output of an HTTP response, for example.
+    /// This is good for parsing the output of an HTTP response, for example. The encoding is
+    /// worked out the same way as for `Soup::from_bytes`.
     ///
     /// ```rust,no_run
     /// # extern crate reqwest;
@@ -383,25 +578,159 @@ impl Soup {
     /// #   Ok(())
     /// # }
     /// ```
-    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Soup> {
-        let dom = parse_document(RcDom::default(), Default::default())
-            .from_utf8()
-            .read_from(&mut reader)?;
-        Ok(Soup {
-            handle: dom,
-        })
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Soup> {
+        SoupOptions::new().from_reader(reader)
+    }
+
+    /// Create a new `Soup` instance from something that implements `Read`, falling back to the
+    /// encoding labelled `hint` the same way as `Soup::from_bytes_with_hint`
+    ///
+    /// ```rust,no_run
+    /// # extern crate reqwest;
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// use reqwest::header::CONTENT_TYPE;
+    /// use soup::prelude::*;
+    ///
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let response = reqwest::get("https://docs.rs/soup")?;
+    /// let charset = response
+    ///     .headers()
+    ///     .get(CONTENT_TYPE)
+    ///     .and_then(|value| value.to_str().ok())
+    ///     .and_then(|value| value.split("charset=").nth(1))
+    ///     .unwrap_or("")
+    ///     .to_string();
+    /// let soup = Soup::from_reader_with_hint(response, &charset)?;
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn from_reader_with_hint<R: Read>(reader: R, hint: &str) -> io::Result<Soup> {
+        SoupOptions::new().encoding_hint(hint).from_reader(reader)
+    }
+
+    /// The name of the encoding the document was decoded from
+    ///
+    /// This is `None` when the `Soup` was created from a string, which needs no decoding.
+    /// Names are the canonical ones from the Encoding Standard, so for example a page declaring
+    /// `iso-8859-1` gives `"windows-1252"`.
+    pub fn original_encoding(&self) -> Option<&'static str> {
+        self.original_encoding.map(|encoding| encoding.name())
+    }
+
+    /// The errors the parser recovered from while parsing the document, in the order they were
+    /// found
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::builder().exact_errors(true).parse("<!DOCTYPE html>\n<p>Text</b>");
+    /// let errors = soup.errors();
+    /// assert_eq!(errors.len(), 1);
+    /// assert_eq!(errors[0].line(), Some(2));
+    /// assert_eq!(errors[0].message(), "Found special tag while closing generic tag");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// The quirks mode the document was parsed in
+    ///
+    /// Documents without a doctype, or with certain legacy doctypes, are parsed in quirks mode,
+    /// which changes a few parsing rules.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.handle.quirks_mode
+    }
+
+    /// Indexes the elements of the document by tag name, id & class, replacing any index it
+    /// already had
+    ///
+    /// Queries on the `Soup` that start with an exact tag, id or class, like
+    /// `soup.attr("id", "main")`, then look their candidates up in the index instead of walking
+    /// the whole tree, & check each of them against the tree & the rest of the query. Changing
+    /// a document with the methods of this crate, like moving elements or changing their ids &
+    /// classes, puts the index out of date, & the next query that needs it builds it again.
+    /// Use `SoupOptions::index` to build it while parsing.
+    ///
+    /// Changes made straight to the nodes, through the `attrs`, `children` or `parent` of an
+    /// `html5ever::rcdom::Node`, aren't noticed. Until `build_index` is called again after
+    /// them, queries can miss elements that now match, or find ones that were removed that
+    /// way.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<ul><li class="item">ONE</li><li>TWO</li></ul>"#);
+    /// soup.build_index();
+    /// let two = soup.tag("li").find_all().nth(1).expect("Couldn't find second 'li'");
+    /// assert_eq!(soup.class("item").find_all().count(), 1);
+    /// two.add_class("item");
+    /// assert_eq!(soup.class("item").find_all().count(), 2);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn build_index(&self) {
+        self.index.build(&self.handle.document);
     }
 
     /// Extracts all text from the HTML
     pub fn text(&self) -> String {
         self.handle.document.text()
     }
+
+    /// Joins the strings in the document with `separator`, like `NodeExt::get_text`
+    pub fn get_text(&self, separator: &str, strip: bool) -> String {
+        self.handle.document.get_text(separator, strip)
+    }
+
+    /// Lays out the text of the document the way a browser would display it, without wrapping
+    /// lines
+    ///
+    /// See `NodeExt::rendered_text` for how the text is laid out
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new("<title>Ignored</title><h1>Title</h1><p>Line<br>break</p><table><tr><td>A</td><td>B</td></tr></table>");
+    /// assert_eq!(soup.to_plain_text(), "Title\n\nLine\nbreak\n\nA\tB");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn to_plain_text(&self) -> String {
+        self.handle.document.rendered_text(0)
+    }
+
+    /// Serializes the document to indented, human-readable HTML
+    ///
+    /// See `NodeExt::prettify` for how the output is laid out
+    pub fn prettify(&self) -> String {
+        self.handle.document.prettify()
+    }
 }
 
 impl From<RcDom> for Soup {
-    fn from(rc: RcDom) -> Soup {
+    fn from(mut rc: RcDom) -> Soup {
+        let errors = rc.errors.drain(..).map(|err| ParseError::new(err, None)).collect();
         Soup {
-            handle: rc
+            handle: rc,
+            original_encoding: None,
+            errors,
+            positions: Positions::default(),
+            index: Rc::default(),
         }
     }
 }
@@ -416,7 +745,7 @@ impl fmt::Debug for Soup {
 mod tests {
     use super::*;
 
-    const TEST_HTML_STRING: &'static str = r#"
+    const TEST_HTML_STRING: &str = r#"
 <!doctype html>
 <html>
   <head>
@@ -446,4 +775,66 @@ mod tests {
             .collect::<Vec<_>>();
         assert_eq!(result, vec!["One".to_string(), "Two".to_string()]);
     }
+
+    struct CountingQuery<'c>(&'c ::std::cell::Cell<usize>);
+
+    impl<'c> Query for CountingQuery<'c> {
+        fn matches(&self, _: &html5ever::rcdom::Handle) -> bool {
+            self.0.set(self.0.get() + 1);
+            true
+        }
+    }
+
+    #[test]
+    fn find_is_lazy() {
+        let soup = Soup::new(TEST_HTML_STRING);
+        let visited = ::std::cell::Cell::new(0);
+        let result = QueryBuilder::new(soup.get_handle())
+            .push_query(CountingQuery(&visited))
+            .tag("p")
+            .find()
+            .expect("Couldn't find tag 'p'");
+        assert_eq!(result.text(), "One".to_string());
+        let total = QueryBuilder::new(soup.get_handle()).find_all().count();
+        assert!(visited.get() < total);
+    }
+
+    #[test]
+    fn non_recursive() {
+        let soup = Soup::new(TEST_HTML_STRING);
+        let body = soup.tag("body").find().expect("Couldn't find tag 'body'");
+        assert_eq!(body.tag("p").recursive(false).find_all().count(), 2);
+        assert_eq!(soup.tag("p").recursive(false).find_all().count(), 0);
+    }
+
+    #[test]
+    fn large_tree() {
+        let html = "<p>a</p>".repeat(100_000);
+        let soup = Soup::new(&html);
+        assert_eq!(soup.tag("p").find_all().count(), 100_000);
+    }
+
+    #[test]
+    fn fragment() {
+        let soup = Soup::fragment(r#"<li>One</li>Two<!--x-->"#);
+        let names = soup.children().map(|node| node.name().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["li", "[text]", "[comment]"]);
+        let li = soup.tag("li").recursive(false).find().expect("Couldn't find tag 'li'");
+        assert!(li.parent().expect("Couldn't find parent of 'li'").is_document());
+        let soup = Soup::fragment_in_context(r#"<tr><td>a</td></tr>"#, "table");
+        let tbody = soup.tag("tbody").recursive(false).find().expect("Couldn't find tag 'tbody'");
+        assert_eq!(tbody.display(), "<tbody><tr><td>a</td></tr></tbody>");
+        assert_eq!(soup.select("tbody > tr > td").expect("Invalid selector").count(), 1);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let soup = Soup::new("<!DOCTYPE html>\n<p>One\n\n<p>Two</b></p></p>");
+        let lines = soup.errors().iter().map(|err| err.line()).collect::<Vec<_>>();
+        assert_eq!(lines, vec![Some(4), Some(4)]);
+        assert!(Soup::new("<!DOCTYPE html><p>Fine</p>").errors().is_empty());
+        let err = Soup::new_strict("<p>No doctype</p>").expect_err("Expected a parse error");
+        assert_eq!(err.to_string(), "Unexpected token on line 1");
+        assert!(Soup::fragment("<b>Fine</b>").errors().is_empty());
+    }
 }
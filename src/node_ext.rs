@@ -1,63 +1,65 @@
 use html5ever::rcdom::{self, Handle, NodeData};
-use std::collections::BTreeMap;
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt,
+    ptr,
+    rc::Rc,
+    vec,
+};
+
+use crate::{
+    attribute,
+    dom::{self, DocumentOrder, Node, NodeKind},
+    position::SourcePosition,
+    serialize::{self, AttributeOrder, SerializeOptions},
+    text::{self, Strings, TextOptions},
+    Soup,
+};
 
 /// Adds some convenience methods to the `html5ever::rcdom::Node` type
+///
+/// The reading & navigation methods are also there for the nodes of other trees, like those of
+/// a `SyncSoup`, as the provided methods of `dom::Node`.
 pub trait NodeExt: Sized {
     /// Retrieves the node that these methods will work on
     fn get_node(&self) -> &rcdom::Node;
 
+    // A `Handle` for the node, for the methods that work through `dom::Node`. Not part of the
+    // API, it's only here so that a `Handle` doesn't have to go looking for itself
+    #[doc(hidden)]
+    fn handle(&self) -> Handle {
+        handle(self.get_node())
+    }
+
     /// Returns `true` if node is of type Document
     fn is_document(&self) -> bool {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Document { .. } => true,
-            _ => false,
-        }
+        dom::rc_kind(&self.get_node().data) == NodeKind::Document
     }
 
     /// Returns `true` if node is of type Doctype
     fn is_doctype(&self) -> bool {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Doctype { .. } => true,
-            _ => false,
-        }
+        dom::rc_kind(&self.get_node().data) == NodeKind::Doctype
     }
 
     /// Returns `true` if node is of type Text
     fn is_text(&self) -> bool {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Text { .. } => true,
-            _ => false,
-        }
+        dom::rc_kind(&self.get_node().data) == NodeKind::Text
     }
 
     /// Returns `true` if node is of type Comment
     fn is_comment(&self) -> bool {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Comment { .. } => true,
-            _ => false,
-        }
+        dom::rc_kind(&self.get_node().data) == NodeKind::Comment
     }
 
     /// Returns `true` if node is of type ProcessingInstruction
     fn is_processing_instruction(&self) -> bool {
-        let node = self.get_node();
-        match node.data {
-            NodeData::ProcessingInstruction { .. } => true,
-            _ => false,
-        }
+        dom::rc_kind(&self.get_node().data) == NodeKind::ProcessingInstruction
     }
 
     /// Returns `true` if node is of type Element
     fn is_element(&self) -> bool {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Element { .. } => true,
-            _ => false,
-        }
+        dom::rc_kind(&self.get_node().data) == NodeKind::Element
     }
 
     /// Retrieves the name of the node
@@ -72,30 +74,40 @@ pub trait NodeExt: Sized {
     /// * ProcessingInstruction -> "\[processing-instruction\]"
     fn name(&self) -> &str {
         let node = self.get_node();
-        match node.data {
-            NodeData::Document {
-                ..
-            } => "[document]",
-            NodeData::Doctype {
-                ..
-            } => "[doctype]",
-            NodeData::Text {
-                ..
-            } => "[text]",
-            NodeData::Comment {
-                ..
-            } => "[comment]",
-            NodeData::ProcessingInstruction {
-                ..
-            } => "[processing-instruction]",
-            NodeData::Element {
-                ref name, ..
-            } => name.local.as_ref(),
-        }
+        dom::kind_name(dom::rc_kind(&node.data), dom::rc_element_name(&node.data))
+    }
+
+    /// Retrieves the namespace URI of the node, if it is an element
+    ///
+    /// Elements parsed from HTML are in the HTML namespace, `"http://www.w3.org/1999/xhtml"`,
+    /// except for those inside `svg` or `math` elements, which are in the SVG & MathML
+    /// namespaces
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p><svg><circle r="1"/></svg></p>"#);
+    /// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+    /// assert_eq!(p.namespace(), Some("http://www.w3.org/1999/xhtml"));
+    /// let circle = soup.tag("circle").find().expect("Couldn't find tag 'circle'");
+    /// assert_eq!(circle.namespace(), Some("http://www.w3.org/2000/svg"));
+    /// assert_eq!(soup.get_handle().namespace(), None);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn namespace(&self) -> Option<&str> {
+        dom::rc_element_name(&self.get_node().data).map(|name| name.ns.as_ref())
     }
 
     /// Looks for an attribute named `attr` and returns it's value as a string
     ///
+    /// Names are matched ignoring case on HTML elements, & exactly on others, like those of XML
+    /// documents.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -110,92 +122,299 @@ pub trait NodeExt: Sized {
     /// # }
     /// ```
     fn get(&self, attr: &str) -> Option<String> {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Element {
-                ref attrs, ..
-            } => {
-                let attrs = attrs.borrow();
-                for it in attrs.iter() {
-                    let name = it.name.local.as_ref();
-                    if name.to_lowercase() == attr.to_lowercase() {
-                        return Some(it.value.to_string());
-                    }
-                }
-                None
-            },
-            _ => None,
-        }
+        attribute::get(&self.handle(), attr)
     }
 
     /// Returns the node's attributes as a BTreeMap
     fn attrs(&self) -> BTreeMap<String, String> {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Element {
-                ref attrs, ..
-            } => {
-                let attrs = attrs.borrow();
-                attrs
-                    .iter()
-                    .map(|attr| (attr.name.local.to_string(), attr.value.to_string()))
-                    .collect()
-            },
-            _ => BTreeMap::new(),
+        Node::attrs(&self.handle())
+    }
+
+    /// Sets the attribute `attr` to `value`, replacing any existing value
+    ///
+    /// Does nothing if the node is not an element. A new attribute is named the way the parser
+    /// would name it: in lowercase on HTML elements, and with `xlink:`, `xml:` & `xmlns:` names
+    /// put in their namespaces.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<a href="/old">Link</a>"#);
+    /// let a = soup.tag("a").find().expect("Couldn't find tag 'a'");
+    /// a.set_attr("href", "/new");
+    /// a.set_attr("rel", "nofollow");
+    /// assert_eq!(a.display(), r#"<a href="/new" rel="nofollow">Link</a>"#);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn set_attr(&self, attr: &str, value: &str) {
+        attribute::set(&self.handle(), attr, value);
+    }
+
+    /// Removes the attribute `attr`, returning its value if it was set
+    fn remove_attr(&self, attr: &str) -> Option<String> {
+        attribute::remove(&self.handle(), attr)
+    }
+
+    /// Returns `true` if the element has the class `class`
+    fn has_class(&self, class: &str) -> bool {
+        Node::has_class(&self.handle(), class)
+    }
+
+    /// Adds `class` to the element's classes, if it isn't there already
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div class="one two"></div>"#);
+    /// let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+    /// div.add_class("three");
+    /// div.remove_class("one");
+    /// assert_eq!(div.get("class"), Some("two three".to_string()));
+    /// assert!(soup.class("three").find().is_some());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn add_class(&self, class: &str) {
+        attribute::add_value(&self.handle(), "class", class);
+    }
+
+    /// Removes `class` from the element's classes
+    ///
+    /// The `class` attribute is removed altogether once there are no classes left
+    fn remove_class(&self, class: &str) {
+        attribute::remove_value(&self.handle(), "class", class);
+    }
+
+    /// Adds `class` to the element's classes if it is missing, or removes it if it is present
+    ///
+    /// Returns `true` if the element has the class afterwards
+    fn toggle_class(&self, class: &str) -> bool {
+        if self.has_class(class) {
+            self.remove_class(class);
+            false
+        } else {
+            self.add_class(class);
+            self.is_element()
         }
     }
 
     /// Retrieves the text value of this element, as well as it's child elements
     fn text(&self) -> String {
-        let node = self.get_node();
-        let mut result = vec![];
-        extract_text(node, &mut result);
-        result.join("")
+        Node::text(&self.handle())
+    }
+
+    /// Joins the strings under the node with `separator`, like BeautifulSoup's `get_text`
+    ///
+    /// Unlike `text`, the contents of `script`, `style` & `template` elements are left out.
+    /// With `strip`, whitespace is stripped from each string & blank strings are skipped. See
+    /// the `text` module for more.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new("<ul>\n  <li>ONE</li>\n  <li>TWO <script>x()</script></li>\n</ul>");
+    /// let ul = soup.tag("ul").find().expect("Couldn't find tag 'ul'");
+    /// assert_eq!(ul.get_text(", ", true), "ONE, TWO");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn get_text(&self, separator: &str, strip: bool) -> String {
+        self.get_text_with(separator, &TextOptions::new().strip(strip))
+    }
+
+    /// Joins the strings under the node with `separator`, using `options`
+    fn get_text_with(&self, separator: &str, options: &TextOptions) -> String {
+        self.strings_with(options).collect::<Vec<_>>().join(separator)
+    }
+
+    /// Returns an iterator over the strings under the node, in document order
+    ///
+    /// The contents of `script`, `style` & `template` elements are left out
+    fn strings(&self) -> Strings {
+        self.strings_with(&TextOptions::new())
+    }
+
+    /// Returns an iterator over the strings under the node, with whitespace stripped from each
+    /// & blank strings left out
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new("<dl>\n  <dt>Name</dt>\n  <dd> Soup </dd>\n</dl>");
+    /// let dl = soup.tag("dl").find().expect("Couldn't find tag 'dl'");
+    /// assert_eq!(dl.stripped_strings().collect::<Vec<_>>(), vec!["Name", "Soup"]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn stripped_strings(&self) -> Strings {
+        self.strings_with(&TextOptions::new().strip(true))
+    }
+
+    /// Returns an iterator over the strings under the node, using `options`
+    fn strings_with(&self, options: &TextOptions) -> Strings {
+        Strings::new(&self.handle(), options)
+    }
+
+    /// Lays out the text under the node the way a browser would display it
+    ///
+    /// Hidden elements like `script` are left out, block elements like `div`, `p` & `li` go on
+    /// lines of their own, with a blank line around paragraphs, `br` breaks the line, and table
+    /// rows go on lines of their own with their cells separated by tabs. Whitespace collapses,
+    /// except inside `pre`. Lines longer than `width` characters are wrapped between words,
+    /// unless `width` is 0.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new("<div><p>A short\n   paragraph</p><ul><li>One</li><li>Two</li></ul></div>");
+    /// let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+    /// assert_eq!(div.rendered_text(0), "A short paragraph\n\nOne\nTwo");
+    /// assert_eq!(div.rendered_text(10), "A short\nparagraph\n\nOne\nTwo");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn rendered_text(&self, width: usize) -> String {
+        text::render(&self.handle(), width)
     }
 
     /// Returns the node as an html tag
+    ///
+    /// This is the same as `outer_html`, except that attributes are sorted by name
     fn display(&self) -> String {
-        let node = self.get_node();
-        match node.data {
-            NodeData::Element {
-                ref name,
-                ref attrs,
-                ..
-            } => {
-                let c = node
-                    .children
-                    .borrow()
-                    .iter()
-                    .map(|child| child.display())
-                    .collect::<Vec<_>>()
-                    .join("");
-                let mut a = attrs
-                    .borrow()
-                    .iter()
-                    .map(|attr| format!(r#"{}="{}""#, attr.name.local, attr.value.as_ref()))
-                    .collect::<Vec<_>>();
-                a.sort();
-                let a = a.join(" ");
-                if a.is_empty() {
-                    format!("<{}>{}</{}>", name.local.as_ref(), c, name.local.as_ref())
-                } else {
-                    format!(
-                        "<{} {}>{}</{}>",
-                        name.local.as_ref(),
-                        a,
-                        c,
-                        name.local.as_ref()
-                    )
-                }
-            },
-            NodeData::Text {
-                ref contents, ..
-            } => contents.borrow().as_ref().to_string(),
-            NodeData::Comment {
-                ref contents, ..
-            } => format!("<!--{}-->", contents.as_ref()),
-            _ => "".to_string(),
-        }
+        let options = SerializeOptions::new().attribute_order(AttributeOrder::Sorted);
+        self.outer_html_with(&options)
+    }
+
+    /// Serializes the node, including the node itself, to HTML
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p id="x" class="y">Fish &amp; Chips<br></p>"#);
+    /// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+    /// assert_eq!(p.outer_html(), r#"<p id="x" class="y">Fish &amp; Chips<br></p>"#);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn outer_html(&self) -> String {
+        self.outer_html_with(&SerializeOptions::default())
+    }
+
+    /// Serializes the node, including the node itself, to HTML using `options`
+    fn outer_html_with(&self, options: &SerializeOptions) -> String {
+        serialize::outer_html(&self.handle(), options)
+    }
+
+    /// Serializes the children of the node to HTML
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p id="x">Fish &amp; <b>Chips</b></p>"#);
+    /// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+    /// assert_eq!(p.inner_html(), "Fish &amp; <b>Chips</b>");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn inner_html(&self) -> String {
+        self.inner_html_with(&SerializeOptions::default())
+    }
+
+    /// Serializes the children of the node to HTML using `options`
+    fn inner_html_with(&self, options: &SerializeOptions) -> String {
+        serialize::inner_html(&self.handle(), options)
+    }
+
+    /// Serializes the node to indented, human-readable HTML
+    ///
+    /// Block-level elements get lines of their own, indented by their depth in the tree, while
+    /// text and inline elements like `b` or `a` are kept together on one line, with runs of
+    /// whitespace collapsed. The contents of whitespace-sensitive elements like `pre`,
+    /// `textarea` and `script` are left untouched.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div><p>Some <b>bold</b> text</p><ul><li>ONE</li></ul></div>"#);
+    /// let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+    /// assert_eq!(div.prettify(), r#"<div>
+    ///   <p>Some <b>bold</b> text</p>
+    ///   <ul>
+    ///     <li>ONE</li>
+    ///   </ul>
+    /// </div>
+    /// "#);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn prettify(&self) -> String {
+        self.prettify_with(&SerializeOptions::default())
+    }
+
+    /// Serializes the node to indented, human-readable HTML using `options`
+    fn prettify_with(&self, options: &SerializeOptions) -> String {
+        serialize::prettify(&self.handle(), options)
+    }
+
+    /// Where the node starts in the source `soup` was parsed from
+    ///
+    /// Positions are only recorded when parsing with `SoupOptions::source_positions` turned
+    /// on, and only for elements & text nodes. Elements the parser made up, like a missing
+    /// `body`, and nodes created after parsing don't have one either. The positions are kept on
+    /// the `Soup` the node was parsed into, so they're dropped along with it, & a node from
+    /// some other document has no position in `soup`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let html = "<ul>\n  <li>ONE</li>\n  <li>TWO</li>\n</ul>";
+    /// let soup = Soup::builder().source_positions(true).parse(html);
+    /// let li = soup.tag("li").find_all().nth(1).expect("Couldn't find second 'li'");
+    /// let position = li.source_position(&soup).expect("No position recorded");
+    /// assert_eq!((position.line(), position.column()), (3, 3));
+    /// assert!(soup.tag("body").find().expect("Couldn't find tag 'body'").source_position(&soup).is_none());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn source_position(&self, soup: &Soup) -> Option<SourcePosition> {
+        soup.positions.get(self.get_node())
     }
 
     /// Navigates to the parent of the node, if there is one
@@ -219,32 +438,317 @@ pub trait NodeExt: Sized {
         let node = self.get_node();
         let parent = node.parent.take(); // leaves node.parent as Cell(None)
         let parent_node = parent.clone();
-        node.parent.set(parent); // puts original parent back?
+        node.parent.set(parent); // puts original parent back
         parent_node.and_then(|node| node.upgrade())
     }
+
+    /// Navigates to the next sibling of the node, if there is one
+    ///
+    /// The sibling can be any kind of node, including text. Use `next_element_sibling` to skip
+    /// to the next element.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<dl><dt>Name</dt> <dd>Soup</dd></dl>"#);
+    /// let dt = soup.tag("dt").find().expect("Couldn't find tag 'dt'");
+    /// let next = dt.next_sibling().expect("Couldn't find next sibling");
+    /// assert_eq!(next.text(), " ".to_string());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn next_sibling(&self) -> Option<Handle> {
+        self.handle().next_sibling_node()
+    }
+
+    /// Navigates to the previous sibling of the node, if there is one
+    ///
+    /// The sibling can be any kind of node, including text. Use `previous_element_sibling` to
+    /// skip to the previous element.
+    fn previous_sibling(&self) -> Option<Handle> {
+        self.handle().previous_sibling_node()
+    }
+
+    /// Navigates to the next sibling of the node that is an element, if there is one
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<dl><dt>Name</dt> <dd>Soup</dd></dl>"#);
+    /// let dt = soup.tag("dt").find().expect("Couldn't find tag 'dt'");
+    /// let dd = dt.next_element_sibling().expect("Couldn't find tag 'dd'");
+    /// assert_eq!(dd.text(), "Soup".to_string());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn next_element_sibling(&self) -> Option<Handle> {
+        self.next_element_siblings().next()
+    }
+
+    /// Navigates to the previous sibling of the node that is an element, if there is one
+    fn previous_element_sibling(&self) -> Option<Handle> {
+        self.previous_element_siblings().next()
+    }
+
+    /// Returns an iterator over the siblings that come after the node
+    fn next_siblings(&self) -> NodeSiblingIter {
+        NodeSiblingIter::new(&self.handle(), true, false)
+    }
+
+    /// Returns an iterator over the siblings that come before the node, nearest first
+    fn previous_siblings(&self) -> NodeSiblingIter {
+        NodeSiblingIter::new(&self.handle(), false, false)
+    }
+
+    /// Returns an iterator over the siblings that come after the node and are elements
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<ul><li>ONE</li> <li id="two">TWO</li> <li>THREE</li></ul>"#);
+    /// let two = soup.attr("id", "two").find().expect("Couldn't find tag with id 'two'");
+    /// let before = two.previous_element_siblings().map(|li| li.text()).collect::<Vec<_>>();
+    /// let after = two.next_element_siblings().map(|li| li.text()).collect::<Vec<_>>();
+    /// assert_eq!(before, vec!["ONE".to_string()]);
+    /// assert_eq!(after, vec!["THREE".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn next_element_siblings(&self) -> NodeSiblingIter {
+        NodeSiblingIter::new(&self.handle(), true, true)
+    }
+
+    /// Returns an iterator over the siblings that come before the node and are elements,
+    /// nearest first
+    fn previous_element_siblings(&self) -> NodeSiblingIter {
+        NodeSiblingIter::new(&self.handle(), false, true)
+    }
+
+    /// Navigates to the node that comes right after this one in document order
+    ///
+    /// This is the first child of the node if it has any, otherwise the next sibling of the node
+    /// or of its nearest ancestor that has one. Like in BeautifulSoup, the result can be any
+    /// kind of node, including text.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p><b>ONE</b></p><p>TWO</p>"#);
+    /// let b = soup.tag("b").find().expect("Couldn't find tag 'b'");
+    /// let text = b.next_element().expect("Couldn't find next element");
+    /// assert_eq!(text.text(), "ONE".to_string());
+    /// let p = text.next_element().expect("Couldn't find next element");
+    /// assert_eq!(p.display(), "<p>TWO</p>".to_string());
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn next_element(&self) -> Option<Handle> {
+        dom::following(&self.handle())
+    }
+
+    /// Navigates to the node that comes right before this one in document order
+    ///
+    /// This is the last descendant of the previous sibling, the previous sibling itself if it
+    /// has no children, or the parent if there is no previous sibling.
+    fn previous_element(&self) -> Option<Handle> {
+        dom::preceding(&self.handle())
+    }
+
+    /// Returns an iterator over all of the nodes that come after this one in document order
+    ///
+    /// The iterator starts with the node's own descendants, and continues to the end of the
+    /// document
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div><h1>Title</h1></div><p>ONE</p><p>TWO</p>"#);
+    /// let h1 = soup.tag("h1").find().expect("Couldn't find tag 'h1'");
+    /// let names = h1.next_elements().map(|node| node.name().to_string()).collect::<Vec<_>>();
+    /// assert_eq!(names, vec!["[text]", "p", "[text]", "p", "[text]"]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn next_elements(&self) -> NodeDocumentIter {
+        NodeDocumentIter::new(DocumentOrder::after(&self.handle()))
+    }
+
+    /// Returns an iterator over all of the nodes that come before this one in document order,
+    /// nearest first
+    ///
+    /// This includes the ancestors of the node, ending with the document itself
+    fn previous_elements(&self) -> NodeDocumentIter {
+        NodeDocumentIter::new(DocumentOrder::before(&self.handle()))
+    }
 }
 
-fn extract_text(node: &rcdom::Node, result: &mut Vec<String>) {
-    match node.data {
-        NodeData::Text {
-            ref contents, ..
-        } => result.push(contents.borrow().to_string()),
-        _ => (),
+/// Iterator over the nodes before or after a node, in document order
+pub struct NodeDocumentIter<N = Handle> {
+    inner: DocumentOrder<N>,
+}
+
+impl<N: Node> NodeDocumentIter<N> {
+    pub(crate) fn new(inner: DocumentOrder<N>) -> NodeDocumentIter<N> {
+        NodeDocumentIter {
+            inner,
+        }
+    }
+}
+
+impl<N: Node> Iterator for NodeDocumentIter<N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        self.inner.next()
+    }
+}
+
+impl<N: Node> fmt::Debug for NodeDocumentIter<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeDocumentIter")
+            .field("next", &self.inner.peek().map(dom::name))
+            .finish()
     }
-    let children = node.children.borrow();
-    for child in children.iter() {
-        extract_text(child, result);
+}
+
+/// Iterator over the siblings of a node, in one direction
+pub struct NodeSiblingIter<N = Handle> {
+    siblings: vec::IntoIter<N>,
+    elements_only: bool,
+}
+
+impl<N: Node> NodeSiblingIter<N> {
+    pub(crate) fn new(node: &N, forward: bool, elements_only: bool) -> NodeSiblingIter<N> {
+        let mut siblings = match node.parent_node() {
+            Some(parent) => parent.child_nodes().collect::<Vec<_>>(),
+            None => vec![],
+        };
+        match siblings.iter().position(|sibling| sibling.same_node(node)) {
+            Some(position) if forward => {
+                siblings.drain(..=position);
+            },
+            Some(position) => {
+                siblings.truncate(position);
+                siblings.reverse();
+            },
+            None => siblings.clear(),
+        }
+        NodeSiblingIter {
+            siblings: siblings.into_iter(),
+            elements_only,
+        }
+    }
+}
+
+impl<N: Node> Iterator for NodeSiblingIter<N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        let elements_only = self.elements_only;
+        self.siblings
+            .by_ref()
+            .find(|sibling| !elements_only || sibling.node_kind() == NodeKind::Element)
+    }
+}
+
+impl<N: Node> fmt::Debug for NodeSiblingIter<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeSiblingIter")
+            .field("siblings", &self.siblings.as_slice().iter().map(dom::name).collect::<Vec<_>>())
+            .field("elements_only", &self.elements_only)
+            .finish()
     }
 }
 
+// The `Handle` that `node` is kept in. A node without a parent can't be found from here, so
+// it gets a stand-in with the same data & children, which is enough to read it & what's under
+// it
+fn handle(node: &rcdom::Node) -> Handle {
+    if let Some(parent) = node.parent() {
+        let found = parent.children.borrow().iter().find(|child| ptr::eq(&***child, node)).cloned();
+        if let Some(found) = found {
+            return found;
+        }
+    }
+    let data = match node.data {
+        NodeData::Document => NodeData::Document,
+        NodeData::Doctype {
+            ref name,
+            ref public_id,
+            ref system_id,
+        } => NodeData::Doctype {
+            name: name.clone(),
+            public_id: public_id.clone(),
+            system_id: system_id.clone(),
+        },
+        NodeData::Text {
+            ref contents,
+        } => NodeData::Text {
+            contents: contents.clone(),
+        },
+        NodeData::Comment {
+            ref contents,
+        } => NodeData::Comment {
+            contents: contents.clone(),
+        },
+        NodeData::Element {
+            ref name,
+            ref attrs,
+            ref template_contents,
+            mathml_annotation_xml_integration_point,
+        } => NodeData::Element {
+            name: name.clone(),
+            attrs: attrs.clone(),
+            template_contents: template_contents.clone(),
+            mathml_annotation_xml_integration_point,
+        },
+        NodeData::ProcessingInstruction {
+            ref target,
+            ref contents,
+        } => NodeData::ProcessingInstruction {
+            target: target.clone(),
+            contents: contents.clone(),
+        },
+    };
+    Rc::new(rcdom::Node {
+        parent: Cell::new(None),
+        children: RefCell::new(node.children.borrow().clone()),
+        data,
+    })
+}
+
 impl NodeExt for Handle {
     #[inline(always)]
     fn get_node(&self) -> &rcdom::Node {
-        &*self
+        self
+    }
+
+    fn handle(&self) -> Handle {
+        self.clone()
     }
 }
 
-impl<'node> NodeExt for &'node rcdom::Node {
+impl NodeExt for &rcdom::Node {
     #[inline(always)]
     fn get_node(&self) -> &rcdom::Node {
         self
@@ -253,7 +757,7 @@ impl<'node> NodeExt for &'node rcdom::Node {
 
 #[cfg(test)]
 mod tests {
-    use crate::prelude::*;
+    use crate::{attribute, prelude::*};
     use std::collections::BTreeMap;
 
     #[test]
@@ -264,6 +768,27 @@ mod tests {
         assert_eq!(name, "b");
     }
 
+    #[test]
+    fn rcdom_node_refs() {
+        use html5ever::rcdom;
+        let soup = Soup::new(r#"<p id="a">One <b>two</b></p><p>Three</p>"#);
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        let node: &rcdom::Node = p.get_node();
+        assert_eq!(node.name(), "p");
+        assert_eq!(node.get("id"), Some("a".to_string()));
+        assert_eq!(node.text(), "One two");
+        assert_eq!(node.next_sibling().map(|next| next.text()), Some("Three".to_string()));
+        assert_eq!(node.parent().map(|body| body.name().to_string()), Some("body".to_string()));
+        node.set_attr("class", "x");
+        assert_eq!(p.display(), r#"<p class="x" id="a">One <b>two</b></p>"#);
+        let document = soup.get_handle();
+        let root: &rcdom::Node = &document;
+        assert!(root.is_document());
+        assert_eq!(root.text(), "One twoThree");
+        assert_eq!(root.next_elements().filter(|node| node.name() == "p").count(), 2);
+        assert!(root.next_sibling().is_none());
+    }
+
     #[test]
     fn get() {
         let soup = Soup::new(r#"<div class="one two"></div>"#);
@@ -312,4 +837,83 @@ mod tests {
         let b = div.tag("b").find().expect("Couldn't find tag 'b'");
         assert_eq!(b.display(), r#"<b>SOME TEXT <!-- and a comment --></b>"#);
     }
+
+    #[test]
+    fn mutate_attrs() {
+        let soup = Soup::new(r#"<div ID="x" class=" one  two "></div>"#);
+        let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+        div.set_attr("Id", "y");
+        assert_eq!(div.get("id"), Some("y".to_string()));
+        assert!(soup.attr("id", "y").find().is_some());
+        assert_eq!(div.remove_attr("id"), Some("y".to_string()));
+        assert_eq!(div.remove_attr("id"), None);
+        div.add_class("two");
+        assert_eq!(div.get("class"), Some(" one  two ".to_string()));
+        div.add_class("three");
+        assert_eq!(div.get("class"), Some("one two three".to_string()));
+        assert!(!div.toggle_class("one"));
+        assert!(div.toggle_class("four"));
+        assert_eq!(div.display(), r#"<div class="two three four"></div>"#);
+        div.remove_class("two");
+        div.remove_class("three");
+        div.remove_class("four");
+        assert_eq!(div.display(), "<div></div>");
+        let document = soup.get_handle();
+        document.set_attr("id", "nope");
+        assert!(!document.toggle_class("nope"));
+        assert_eq!(document.get("id"), None);
+    }
+
+    #[test]
+    fn new_attr_names() {
+        let soup = Soup::new(r##"<div></div><svg><use href="#a"/></svg>"##);
+        let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+        div.set_attr("DATA-Id", "1");
+        assert_eq!(div.display(), r#"<div data-id="1"></div>"#);
+        assert!(soup.attr("data-id", "1").find().is_some());
+        let svg_use = soup.tag("use").find().expect("Couldn't find tag 'use'");
+        svg_use.set_attr("xlink:href", "#b");
+        svg_use.set_attr("xml:lang", "en");
+        let xlink = soup.attr_ns("http://www.w3.org/1999/xlink", "href", "#b").find();
+        assert!(xlink.is_some_and(|node| node.name() == "use"));
+        assert!(soup.attr_ns("http://www.w3.org/XML/1998/namespace", "lang", "en").find().is_some());
+        assert_eq!(svg_use.get("xlink:href"), Some("#b".to_string()));
+        assert_eq!(svg_use.display(), r##"<use href="#a" xlink:href="#b" xml:lang="en"></use>"##);
+        let soup = Soup::xml("<feed/>");
+        let feed = soup.tag("feed").find().expect("Couldn't find tag 'feed'");
+        feed.set_attr("xmlns", "http://www.w3.org/2005/Atom");
+        feed.set_attr("Version", "1");
+        assert_eq!(feed.display(), r#"<feed Version="1" xmlns="http://www.w3.org/2005/Atom"></feed>"#);
+        assert!(soup.attr_ns("http://www.w3.org/2000/xmlns/", "xmlns", true).find().is_some());
+    }
+
+    #[test]
+    fn case_sensitive_xml_attrs() {
+        let soup = Soup::xml(r#"<r><e Id="upper" id="lower"/></r>"#);
+        let e = soup.tag("e").find().expect("Couldn't find tag 'e'");
+        assert_eq!(e.get("id"), Some("lower".to_string()));
+        assert_eq!(e.get("Id"), Some("upper".to_string()));
+        assert_eq!(e.get("ID"), None);
+        e.set_attr("id", "changed");
+        assert_eq!(e.display(), r#"<e Id="upper" id="changed"></e>"#);
+        assert_eq!(e.remove_attr("Id"), Some("upper".to_string()));
+        assert_eq!(e.display(), r#"<e id="changed"></e>"#);
+        let soup = Soup::new(r#"<p ID="a"></p>"#);
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        assert_eq!(p.get("Id"), Some("a".to_string()));
+    }
+
+    #[test]
+    fn mutate_non_list_attrs() {
+        let soup = Soup::new(r#"<a rel="nofollow" target="_blank">Link</a>"#);
+        let a = soup.tag("a").find().expect("Couldn't find tag 'a'");
+        attribute::add_value(&a, "rel", "noopener");
+        assert_eq!(a.get("rel"), Some("nofollow noopener".to_string()));
+        attribute::add_value(&a, "target", "_top");
+        assert_eq!(a.get("target"), Some("_top".to_string()));
+        attribute::remove_value(&a, "target", "_blank");
+        assert_eq!(a.get("target"), Some("_top".to_string()));
+        attribute::remove_value(&a, "target", "_top");
+        assert_eq!(a.get("target"), None);
+    }
 }
@@ -0,0 +1,358 @@
+use html5ever::{
+    parse_document,
+    parse_fragment,
+    rcdom::{Handle, NodeData},
+    tendril::TendrilSink,
+    interface::create_element,
+    tree_builder::{TreeBuilder, TreeBuilderOpts},
+    LocalName,
+    Namespace,
+    ParseOpts,
+    QualName,
+};
+use std::{
+    io::{self, Read},
+    rc::Rc,
+};
+
+use crate::{
+    encoding,
+    index::SoupIndex,
+    position,
+    sink::{ParseError, Sink},
+    xml,
+    Soup,
+};
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+/// Options for parsing a `Soup`
+///
+/// Get one from `Soup::builder`, change the options you need, then call one of the parsing
+/// methods. The defaults are the ones `Soup::new` & friends use.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate soup;
+/// # use soup::prelude::*;
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<Error>> {
+/// let html = "<div>\n  <!-- nav -->\n  <noscript><p>Enable JS</p></noscript>\n</div>";
+/// let soup = Soup::builder()
+///     .scripting(false)
+///     .drop_comments(true)
+///     .drop_whitespace(true)
+///     .parse(html);
+/// let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+/// assert_eq!(div.display(), "<div><noscript><p>Enable JS</p></noscript></div>");
+/// #   Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoupOptions {
+    scripting: bool,
+    iframe_srcdoc: bool,
+    drop_comments: bool,
+    drop_whitespace: bool,
+    exact_errors: bool,
+    source_positions: bool,
+    index: bool,
+    encoding_hint: Option<String>,
+}
+
+impl Default for SoupOptions {
+    fn default() -> SoupOptions {
+        SoupOptions {
+            scripting: true,
+            iframe_srcdoc: false,
+            drop_comments: false,
+            drop_whitespace: false,
+            exact_errors: false,
+            source_positions: false,
+            index: false,
+            encoding_hint: None,
+        }
+    }
+}
+
+impl SoupOptions {
+    /// Creates the default options
+    pub fn new() -> SoupOptions {
+        SoupOptions::default()
+    }
+
+    /// Sets whether the document is parsed as if scripting were enabled, which is the default
+    ///
+    /// With scripting enabled the contents of `noscript` elements are kept as text, like a
+    /// browser running scripts would see them. With scripting disabled they're parsed as markup.
+    pub fn scripting(mut self, scripting: bool) -> SoupOptions {
+        self.scripting = scripting;
+        self
+    }
+
+    /// Sets whether the document is parsed as an `iframe srcdoc` document
+    ///
+    /// These are never put in quirks mode because of a missing or legacy doctype, so this is a
+    /// way to parse documents without a doctype in no-quirks mode. See `Soup::quirks_mode`.
+    pub fn iframe_srcdoc(mut self, iframe_srcdoc: bool) -> SoupOptions {
+        self.iframe_srcdoc = iframe_srcdoc;
+        self
+    }
+
+    /// Sets whether comments are left out of the tree
+    pub fn drop_comments(mut self, drop_comments: bool) -> SoupOptions {
+        self.drop_comments = drop_comments;
+        self
+    }
+
+    /// Sets whether text nodes made up only of whitespace are left out of the tree
+    ///
+    /// Whitespace inside `pre`, `textarea` & `listing` elements is always kept, because it
+    /// changes how their contents are displayed.
+    pub fn drop_whitespace(mut self, drop_whitespace: bool) -> SoupOptions {
+        self.drop_whitespace = drop_whitespace;
+        self
+    }
+
+    /// Sets whether parse errors get detailed messages, which makes parsing a little slower
+    ///
+    /// By default most errors just say what kind of token was unexpected, like
+    /// `"Unexpected token"`. See `Soup::errors`.
+    pub fn exact_errors(mut self, exact_errors: bool) -> SoupOptions {
+        self.exact_errors = exact_errors;
+        self
+    }
+
+    /// Sets whether the position in the source of each element & text node is recorded, so it
+    /// can be looked up with `NodeExt::source_position`
+    ///
+    /// The input is fed to the parser a tag at a time to do this, which makes parsing slower,
+    /// so it's off by default.
+    pub fn source_positions(mut self, source_positions: bool) -> SoupOptions {
+        self.source_positions = source_positions;
+        self
+    }
+
+    /// Sets whether the elements are indexed by tag name, id & class once parsed, to speed up
+    /// queries for them, as with `Soup::build_index`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::builder().index(true).parse(r#"<p id="a" class="x">ONE</p><p class="x">TWO</p>"#);
+    /// assert_eq!(soup.attr("id", "a").find().map(|p| p.text()), Some("ONE".to_string()));
+    /// assert_eq!(soup.class("x").tag("p").find_all().count(), 2);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn index(mut self, index: bool) -> SoupOptions {
+        self.index = index;
+        self
+    }
+
+    /// Sets the encoding to fall back to when parsing bytes that have no byte order mark or
+    /// `<meta>` declaration, as in `Soup::from_bytes_with_hint`
+    pub fn encoding_hint(mut self, label: &str) -> SoupOptions {
+        self.encoding_hint = Some(label.to_string());
+        self
+    }
+
+    /// Parses a string slice into a `Soup`
+    pub fn parse(&self, html: &str) -> Soup {
+        let sink = if self.source_positions {
+            let opts = self.parse_opts();
+            let tree_builder = TreeBuilder::new(Sink::default(), opts.tree_builder);
+            position::parse(tree_builder, opts.tokenizer, html)
+        } else {
+            parse_document(Sink::default(), self.parse_opts())
+                .from_utf8()
+                .one(html.as_bytes())
+        };
+        self.finish(sink)
+    }
+
+    /// Parses a string slice into a `Soup`, failing with the first parse error if there are any
+    pub fn parse_strict(&self, html: &str) -> Result<Soup, ParseError> {
+        let soup = self.parse(html);
+        match soup.errors.first() {
+            Some(err) => Err(err.clone()),
+            None => Ok(soup),
+        }
+    }
+
+    /// Parses raw bytes into a `Soup`, working out their encoding like `Soup::from_bytes`
+    pub fn parse_bytes(&self, bytes: &[u8]) -> Soup {
+        let (html, encoding) = encoding::decode(bytes, self.encoding_hint.as_deref());
+        let mut soup = self.parse(&html);
+        soup.original_encoding = Some(encoding);
+        soup
+    }
+
+    /// Parses the contents of something that implements `Read` into a `Soup`
+    pub fn from_reader<R: Read>(&self, mut reader: R) -> io::Result<Soup> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Ok(self.parse_bytes(&bytes))
+    }
+
+    /// Parses an XML document into a `Soup`, like `Soup::xml`
+    ///
+    /// The `scripting` & `iframe_srcdoc` options only apply to HTML, so they're ignored.
+    pub fn parse_xml(&self, xml: &str) -> Soup {
+        self.finish(xml::parse(xml, self.source_positions))
+    }
+
+    /// Parses a fragment of HTML into a `Soup`, like `Soup::fragment`
+    pub fn fragment(&self, html: &str) -> Soup {
+        self.fragment_in_context(html, "body")
+    }
+
+    /// Parses a fragment of HTML into a `Soup`, like `Soup::fragment_in_context`
+    pub fn fragment_in_context(&self, html: &str, context: &str) -> Soup {
+        let context = QualName::new(None, Namespace::from(HTML_NAMESPACE), LocalName::from(context));
+        let sink = if self.source_positions {
+            let mut opts = self.parse_opts();
+            let mut sink = Sink::default();
+            let context = create_element(&mut sink, context, vec![]);
+            let tree_builder = TreeBuilder::new_for_fragment(sink, context, None, opts.tree_builder);
+            opts.tokenizer.initial_state = Some(tree_builder.tokenizer_state_for_context_elem());
+            position::parse(tree_builder, opts.tokenizer, html)
+        } else {
+            parse_fragment(Sink::default(), self.parse_opts(), context, vec![])
+                .from_utf8()
+                .one(html.as_bytes())
+        };
+        // The parser puts the fragment inside an `html` element, which we don't want
+        let document = sink.dom.document.clone();
+        let root = document.children.replace(vec![]);
+        for html in root {
+            for child in html.children.replace(vec![]) {
+                child.parent.set(Some(Rc::downgrade(&document)));
+                document.children.borrow_mut().push(child);
+            }
+        }
+        self.finish(sink)
+    }
+
+    fn parse_opts(&self) -> ParseOpts {
+        ParseOpts {
+            tree_builder: TreeBuilderOpts {
+                exact_errors: self.exact_errors,
+                scripting_enabled: self.scripting,
+                iframe_srcdoc: self.iframe_srcdoc,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn finish(&self, sink: Sink) -> Soup {
+        if self.drop_comments || self.drop_whitespace {
+            self.prune(&sink.dom.document);
+        }
+        let index = SoupIndex::default();
+        if self.index {
+            index.build(&sink.dom.document);
+        }
+        Soup {
+            handle: sink.dom,
+            original_encoding: None,
+            errors: sink.errors,
+            positions: sink.positions,
+            index: Rc::new(index),
+        }
+    }
+
+    // Removes the nodes the options say to drop, without recursing so deep trees are fine
+    fn prune(&self, document: &Handle) {
+        let mut stack = vec![(document.clone(), false)];
+        while let Some((node, preformatted)) = stack.pop() {
+            let preformatted = preformatted || is_preformatted(&node);
+            node.children.borrow_mut().retain(|child| {
+                let keep = !self.should_drop(child, preformatted);
+                if !keep {
+                    child.parent.set(None);
+                }
+                keep
+            });
+            for child in node.children.borrow().iter() {
+                stack.push((child.clone(), preformatted));
+            }
+            if let NodeData::Element {
+                template_contents: Some(ref contents),
+                ..
+            } = node.data
+            {
+                stack.push((contents.clone(), preformatted));
+            }
+        }
+    }
+
+    fn should_drop(&self, node: &Handle, preformatted: bool) -> bool {
+        match node.data {
+            NodeData::Comment {
+                ..
+            } => self.drop_comments,
+            NodeData::Text {
+                ref contents,
+            } => {
+                self.drop_whitespace
+                    && !preformatted
+                    && contents.borrow().chars().all(|c| c.is_ascii_whitespace())
+            },
+            _ => false,
+        }
+    }
+}
+
+fn is_preformatted(node: &Handle) -> bool {
+    match node.data {
+        NodeData::Element {
+            ref name, ..
+        } => {
+            &*name.ns == HTML_NAMESPACE && matches!(&*name.local, "pre" | "textarea" | "listing")
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{prelude::*, QuirksMode};
+
+    #[test]
+    fn drop_nodes() {
+        let html = "<ul>\n  <li>A <!--x--> B</li>\n  <li> </li>\n</ul><pre>\n<!--y--> <b>C</b> </pre>\
+                    <template>\n<!--z--></template>";
+        let soup = Soup::builder().drop_whitespace(true).parse(html);
+        let ul = soup.tag("ul").find().expect("Couldn't find tag 'ul'");
+        assert_eq!(ul.display(), "<ul><li>A <!--x--> B</li><li></li></ul>");
+        let pre = soup.tag("pre").find().expect("Couldn't find tag 'pre'");
+        assert_eq!(pre.display(), "<pre><!--y--> <b>C</b> </pre>");
+        let soup = Soup::builder().drop_comments(true).drop_whitespace(true).parse(html);
+        let ul = soup.tag("ul").find().expect("Couldn't find tag 'ul'");
+        assert_eq!(ul.display(), "<ul><li>A  B</li><li></li></ul>");
+        let pre = soup.tag("pre").find().expect("Couldn't find tag 'pre'");
+        assert_eq!(pre.display(), "<pre> <b>C</b> </pre>");
+        let template = soup.tag("template").find().expect("Couldn't find tag 'template'");
+        assert_eq!(template.display(), "<template></template>");
+    }
+
+    #[test]
+    fn scripting_and_quirks() {
+        let html = "<noscript><p>No JS</p></noscript>";
+        let soup = Soup::builder().parse(html);
+        assert!(soup.tag("p").find().is_none());
+        let soup = Soup::builder().scripting(false).parse(html);
+        assert!(soup.tag("p").find().is_some());
+        assert_eq!(Soup::new(html).quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(Soup::new("<!DOCTYPE html>").quirks_mode(), QuirksMode::NoQuirks);
+        let soup = Soup::builder().iframe_srcdoc(true).parse(html);
+        assert_eq!(soup.quirks_mode(), QuirksMode::NoQuirks);
+    }
+}
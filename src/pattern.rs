@@ -29,6 +29,14 @@ use regex::Regex;
 pub trait Pattern {
     /// Matches the `Pattern` with the value `haystack`
     fn matches(&self, haystack: &str) -> bool;
+
+    /// Returns the one string this `Pattern` matches, if it only matches a single exact value
+    ///
+    /// Queries with exact patterns can be answered from the index of a `Soup` parsed with
+    /// `SoupOptions::index`. The default returns `None`.
+    fn exact(&self) -> Option<&str> {
+        None
+    }
 }
 
 impl Pattern for bool {
@@ -37,16 +45,24 @@ impl Pattern for bool {
     }
 }
 
-impl<'a> Pattern for &'a str {
+impl Pattern for &str {
     fn matches(&self, haystack: &str) -> bool {
         *self == haystack
     }
+
+    fn exact(&self) -> Option<&str> {
+        Some(self)
+    }
 }
 
 impl Pattern for String {
     fn matches(&self, haystack: &str) -> bool {
         self == haystack
     }
+
+    fn exact(&self) -> Option<&str> {
+        Some(self)
+    }
 }
 
 #[cfg(feature = "regex")]
@@ -0,0 +1,273 @@
+use html5ever::{
+    rcdom::{Handle, Node},
+    tendril::StrTendril,
+    tokenizer::{
+        BufferQueue,
+        CharacterTokens,
+        ParseError,
+        StartTag,
+        TagToken,
+        Token,
+        TokenSink,
+        TokenSinkResult,
+        Tokenizer,
+        TokenizerOpts,
+        TokenizerResult,
+    },
+    tree_builder::TreeBuilder,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    rc::{Rc, Weak},
+};
+
+use crate::sink::{Pending, Sink};
+
+/// Where a node starts in the source it was parsed from
+///
+/// Lines & columns both start at 1, and columns count characters rather than bytes. The lines
+/// of HTML are the ones the tokenizer counts, the same as those of `ParseError::line`. See
+/// `NodeExt::source_position`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourcePosition {
+    line: u64,
+    column: u64,
+}
+
+impl SourcePosition {
+    pub(crate) fn new(line: u64, column: u64) -> SourcePosition {
+        SourcePosition {
+            line,
+            column,
+        }
+    }
+
+    pub(crate) fn start() -> SourcePosition {
+        SourcePosition::new(1, 1)
+    }
+
+    /// The line the node starts on
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// The column the node starts at
+    pub fn column(&self) -> u64 {
+        self.column
+    }
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Where the nodes of a parsed document start, kept by the `Soup` the document was parsed into
+//
+// Nodes are keyed by their address. Holding a `Weak` to each node keeps its allocation around,
+// so the address can't be reused by another node for as long as the positions are.
+#[derive(Default)]
+pub(crate) struct Positions {
+    nodes: HashMap<usize, (Weak<Node>, SourcePosition)>,
+}
+
+impl Positions {
+    pub(crate) fn record(&mut self, node: &Handle, position: SourcePosition) {
+        self.nodes.insert(key(node), (Rc::downgrade(node), position));
+    }
+
+    pub(crate) fn get(&self, node: &Node) -> Option<SourcePosition> {
+        self.nodes.get(&key(node)).map(|&(_, position)| position)
+    }
+}
+
+fn key(node: &Node) -> usize {
+    let ptr: *const Node = node;
+    ptr as usize
+}
+
+// Splits the source into the pieces it's fed to the tokenizer in: each tag as far as the first
+// `>`, & the text between tags. Tokens mostly end where a piece does, so the start of the piece
+// after one is where the next token starts.
+pub(crate) fn chunks(source: &str) -> impl Iterator<Item = &str> + '_ {
+    let mut rest = source;
+    ::std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .bytes()
+            .enumerate()
+            .find(|&(idx, byte)| byte == b'>' || (byte == b'<' && idx > 0))
+            .map_or(rest.len(), |(idx, byte)| if byte == b'>' { idx + 1 } else { idx });
+        let (chunk, after) = rest.split_at(end);
+        rest = after;
+        Some(chunk)
+    })
+}
+
+// Works out the columns of offsets into the source, which have to be asked about in order.
+// Only the characters since the last offset asked about get counted, so finding the column at
+// the start of each token is linear in the length of the source.
+pub(crate) struct Columns<'a> {
+    source: &'a str,
+    offset: usize,
+    column: u64,
+}
+
+impl<'a> Columns<'a> {
+    pub(crate) fn new(source: &'a str) -> Columns<'a> {
+        Columns {
+            source,
+            offset: 0,
+            column: 1,
+        }
+    }
+
+    pub(crate) fn at(&mut self, offset: usize) -> u64 {
+        let skipped = &self.source[self.offset..offset];
+        self.column = match skipped.rfind(['\n', '\r']) {
+            Some(idx) => skipped[idx + 1..].chars().count() as u64 + 1,
+            None => self.column + skipped.chars().count() as u64,
+        };
+        self.offset = offset;
+        self.column
+    }
+}
+
+// Sits between the tokenizer & the tree builder, telling the sink where each token started
+struct Tracker {
+    inner: TreeBuilder<Handle, Sink>,
+    // Where the token being read started
+    start: SourcePosition,
+    // Whether any tokens have been emitted since `start` was set
+    emitted: bool,
+    // The line the tokenizer was on when it emitted the last token, which the tree builder
+    // also passes on to the sink's `set_current_line`, so positions & parse errors agree on
+    // which line is which
+    line: u64,
+}
+
+impl TokenSink for Tracker {
+    type Handle = Handle;
+
+    fn process_token(&mut self, token: Token, line_number: u64) -> TokenSinkResult<Handle> {
+        // Errors are reported in the middle of tokens, so they don't count as the end of one
+        if let ParseError(_) = token {
+            return self.inner.process_token(token, line_number);
+        }
+        self.emitted = true;
+        self.line = line_number;
+        self.inner.sink.pending = match token {
+            TagToken(ref tag) if tag.kind == StartTag => Some(Pending::Element(tag.name.clone(), self.start)),
+            CharacterTokens(_) => Some(Pending::Text(self.start)),
+            _ => None,
+        };
+        let result = self.inner.process_token(token, line_number);
+        self.inner.sink.pending = None;
+        result
+    }
+
+    fn end(&mut self) {
+        self.inner.end()
+    }
+
+    fn adjusted_current_node_present_but_not_in_html_namespace(&self) -> bool {
+        self.inner.adjusted_current_node_present_but_not_in_html_namespace()
+    }
+}
+
+// Runs the tokenizer over `html` a tag or a run of text at a time. A token starts at the start
+// of the first piece fed after the last token was emitted, on the line the tokenizer had
+// reached by then.
+pub(crate) fn parse(tree_builder: TreeBuilder<Handle, Sink>, opts: TokenizerOpts, html: &str) -> Sink {
+    let tracker = Tracker {
+        inner: tree_builder,
+        start: SourcePosition::start(),
+        emitted: false,
+        line: 1,
+    };
+    let mut tokenizer = Tokenizer::new(tracker, opts);
+    let mut input = BufferQueue::new();
+    let mut columns = Columns::new(html);
+    let mut offset = 0;
+    for chunk in chunks(html) {
+        if tokenizer.sink.emitted {
+            let line = tokenizer.sink.line;
+            tokenizer.sink.start = SourcePosition::new(line, columns.at(offset));
+            tokenizer.sink.emitted = false;
+        }
+        offset += chunk.len();
+        input.push_back(StrTendril::from_slice(chunk));
+        while let TokenizerResult::Script(_) = tokenizer.feed(&mut input) {}
+    }
+    tokenizer.end();
+    tokenizer.sink.inner.sink
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn position(soup: &Soup, node: &html5ever::rcdom::Handle) -> Option<(u64, u64)> {
+        node.source_position(soup).map(|position| (position.line(), position.column()))
+    }
+
+    #[test]
+    fn positions() {
+        let html = "<!DOCTYPE html>\r\n<p class=\"a\"\r\n   id=x>caf\u{e9} &amp; <b>bold</b></p>\n<table><tr><td>1</td></tr></table>\n<script>if (a < b) {}</script>";
+        let soup = Soup::builder().source_positions(true).parse(html);
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        assert_eq!(position(&soup, &p), Some((2, 1)));
+        let text = p.children().next().expect("Couldn't find text");
+        assert_eq!(position(&soup, &text), Some((3, 9)));
+        let b = soup.tag("b").find().expect("Couldn't find tag 'b'");
+        assert_eq!(position(&soup, &b), Some((3, 20)));
+        let td = soup.tag("td").find().expect("Couldn't find tag 'td'");
+        assert_eq!(position(&soup, &td), Some((4, 12)));
+        let script = soup.tag("script").find().expect("Couldn't find tag 'script'");
+        assert_eq!(position(&soup, &script), Some((5, 1)));
+        for name in &["html", "head", "body", "tbody"] {
+            let node = soup.tag(*name).find().expect("Couldn't find tag");
+            assert_eq!(position(&soup, &node), None);
+        }
+        let plain = Soup::new(html);
+        assert_eq!(soup.get_handle().display(), plain.get_handle().display());
+        assert_eq!(position(&plain, &plain.tag("p").find().expect("Couldn't find tag 'p'")), None);
+        assert_eq!(position(&plain, &p), None);
+    }
+
+    #[test]
+    fn tags_split_by_markup() {
+        let html = "<p title=\"a<b\">x &amp; \n<i>y</i><!-- < > --><em\nid=z>w</em></p>";
+        let soup = Soup::builder().source_positions(true).parse(html);
+        let positions = ["p", "i", "em"]
+            .iter()
+            .map(|name| position(&soup, &soup.tag(*name).find().expect("Couldn't find tag")))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![Some((1, 1)), Some((2, 1)), Some((2, 21))]);
+        let text = soup.tag("em").find().and_then(|em| em.children().next()).expect("Couldn't find text");
+        assert_eq!(position(&soup, &text), Some((3, 6)));
+    }
+
+    #[test]
+    fn fragment_positions() {
+        let soup = Soup::builder()
+            .source_positions(true)
+            .fragment_in_context("<td>a</td>\n<td>b</td>", "tr");
+        let positions = soup.tag("td").find_all().map(|td| position(&soup, &td)).collect::<Vec<_>>();
+        assert_eq!(positions, vec![Some((1, 1)), Some((2, 1))]);
+    }
+
+    #[test]
+    fn extracted_nodes() {
+        let soup = Soup::builder().source_positions(true).parse("<p>a</p><p>b</p>");
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        p.extract();
+        assert_eq!(position(&soup, &p), Some((1, 1)));
+        let other = Soup::builder().source_positions(true).parse("<p>a</p>");
+        assert_eq!(position(&other, &p), None);
+    }
+}
@@ -1,22 +1,51 @@
-use std::fmt;
+use std::{fmt, rc::Rc};
 use html5ever::rcdom::Handle;
 
 use crate::{
+    dom::Node,
+    index::Lookup,
     Soup,
-    find::{AttrQuery, QueryBuilder, QueryWrapper, TagQuery},
+    find::{
+        Alternatives,
+        AnyQuery,
+        AttrNsQuery,
+        AttrQuery,
+        BoxNodeIter,
+        EmptyQueryWrapper,
+        FilterQuery,
+        NotQuery,
+        OrQueryBuilder,
+        Query,
+        QueryBuilder,
+        QueryWrapper,
+        TagNsQuery,
+        TagQuery,
+        TextQuery,
+    },
     pattern::Pattern,
+    selector::{ParseError, ScopedSelector, Selector},
+    xpath::{self, XPath},
 };
 
 /// Adds the QueryBuilder constructor methods to the implementing type
-pub trait QueryBuilderExt {
+///
+/// `N` is the type of node being searched. The `children` & `parents` iterators are only
+/// available for the `Handle`s of a `Soup`.
+pub trait QueryBuilderExt<N: Node = Handle> {
     /// Retrieves the Handle that these methods will work on
-    fn get_handle(&self) -> Handle;
+    fn get_handle(&self) -> N;
+
+    // The builder that queries start out as. Not part of the API, it's only here so that a
+    // `Soup` can give its queries its index
+    #[doc(hidden)]
+    fn query_builder<'a>(&self) -> QueryBuilder<'a, (), (), N> {
+        QueryBuilder::new(self.get_handle())
+    }
 
     // QueryBuilder constructor methods
     /// Starts building a Query, with limit `limit`
-    fn limit<'a>(&self, limit: usize) -> QueryBuilder<'a, (), ()> {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+    fn limit<'a>(&self, limit: usize) -> QueryBuilder<'a, (), (), N> {
+        let qb = self.query_builder();
         qb.limit(limit)
     }
 
@@ -24,29 +53,40 @@ pub trait QueryBuilderExt {
     fn tag<'a, P: Pattern>(
         &self,
         tag: P,
-    ) -> QueryBuilder<'a, TagQuery<P>, QueryWrapper<'a, (), ()>> {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+    ) -> QueryBuilder<'a, TagQuery<P>, QueryWrapper<'a, (), ()>, N> {
+        let qb = self.query_builder();
         qb.tag(tag)
     }
 
+    /// Starts building a Query, with tag `tag` in the namespace `ns`
+    fn tag_ns<'a, M, P>(
+        &self,
+        ns: M,
+        tag: P,
+    ) -> QueryBuilder<'a, TagNsQuery<M, P>, QueryWrapper<'a, (), ()>, N>
+    where
+        M: Pattern,
+        P: Pattern,
+    {
+        let qb = self.query_builder();
+        qb.tag_ns(ns, tag)
+    }
+
     /// Starts building a Query, with attr name `name`
-    fn attr_name<'a, P>(&self, name: P) -> QueryBuilder<'a, AttrQuery<P, bool>, QueryWrapper<'a, (), ()>>
+    fn attr_name<'a, P>(&self, name: P) -> QueryBuilder<'a, AttrQuery<P, bool>, QueryWrapper<'a, (), ()>, N>
     where
         P: Pattern
     {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+        let qb = self.query_builder();
         qb.attr_name(name)
     }
 
     /// Starts building a Query, with attr value `value`
-    fn attr_value<'a, P>(&self, value: P) -> QueryBuilder<'a, AttrQuery<bool, P>, QueryWrapper<'a, (), ()>>
+    fn attr_value<'a, P>(&self, value: P) -> QueryBuilder<'a, AttrQuery<bool, P>, QueryWrapper<'a, (), ()>, N>
     where
         P: Pattern
     {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+        let qb = self.query_builder();
         qb.attr_value(value)
     }
 
@@ -55,33 +95,196 @@ pub trait QueryBuilderExt {
         &self,
         name: P,
         value: Q,
-    ) -> QueryBuilder<'a, AttrQuery<P, Q>, QueryWrapper<'a, (), ()>>
+    ) -> QueryBuilder<'a, AttrQuery<P, Q>, QueryWrapper<'a, (), ()>, N>
     where
         P: Pattern,
         Q: Pattern,
     {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+        let qb = self.query_builder();
         qb.attr(name, value)
     }
 
+    /// Starts building a Query, with attr `name` in the namespace `ns`
+    fn attr_ns<'a, M, P, Q>(
+        &self,
+        ns: M,
+        name: P,
+        value: Q,
+    ) -> QueryBuilder<'a, AttrNsQuery<M, P, Q>, QueryWrapper<'a, (), ()>, N>
+    where
+        M: Pattern,
+        P: Pattern,
+        Q: Pattern,
+    {
+        let qb = self.query_builder();
+        qb.attr_ns(ns, name, value)
+    }
+
     /// Starts building a Query, with class `class`
     fn class<'a, P: Pattern>(
         &self,
         value: P,
-    ) -> QueryBuilder<'a, AttrQuery<&'static str, P>, QueryWrapper<'a, (), ()>> {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+    ) -> QueryBuilder<'a, AttrQuery<&'static str, P>, QueryWrapper<'a, (), ()>, N> {
+        let qb = self.query_builder();
         qb.class(value)
     }
 
+    /// Starts building a Query, with the custom query `query`
+    fn query<'a, Q: Query<N> + 'a>(&self, query: Q) -> QueryBuilder<'a, Q, QueryWrapper<'a, (), ()>, N> {
+        let qb = self.query_builder();
+        qb.query(query)
+    }
+
+    /// Starts building a Query, with the filter closure `filter`
+    fn filter<'a, F>(&self, filter: F) -> QueryBuilder<'a, FilterQuery<F>, QueryWrapper<'a, (), ()>, N>
+    where
+        F: Fn(&N) -> bool + 'a,
+    {
+        let qb = self.query_builder();
+        qb.filter(filter)
+    }
+
+    /// Starts building a Query, with own text `text`
+    ///
+    /// There is no `text` constructor, since it would clash with `NodeExt::text`; start with
+    /// `tag(true).text(..)` instead
+    fn own_text<'a, P: Pattern>(
+        &self,
+        text: P,
+    ) -> QueryBuilder<'a, TextQuery<P>, QueryWrapper<'a, (), ()>, N> {
+        let qb = self.query_builder();
+        qb.own_text(text)
+    }
+
+    /// Starts building a Query for text nodes, with contents `text`
+    fn string<'a, P: Pattern>(&self, text: P) -> QueryBuilder<'a, TextQuery<P>, QueryWrapper<'a, (), ()>, N> {
+        let qb = self.query_builder();
+        qb.string(text)
+    }
+
     /// Starts building a Query, with recursion set to `recursive`
-    fn recursive<'a>(&self, recursive: bool) -> QueryBuilder<'a, (), ()> {
-        let handle = self.get_handle();
-        let qb = QueryBuilder::new(handle);
+    fn recursive<'a>(&self, recursive: bool) -> QueryBuilder<'a, (), (), N> {
+        let qb = self.query_builder();
         qb.recursive(recursive)
     }
 
+    /// Starts building a Query, matching nodes that match the query built by `group`
+    ///
+    /// See `QueryBuilder::or`
+    fn or<'a, F, V, W>(
+        &self,
+        group: F,
+    ) -> OrQueryBuilder<'a, (), (), V, W, N>
+    where
+        F: FnOnce(QueryBuilder<'a, (), (), N>) -> QueryBuilder<'a, V, W, N>,
+        V: Query<N> + 'a,
+        W: Query<N> + 'a,
+    {
+        let qb = self.query_builder();
+        qb.or(group)
+    }
+
+    /// Starts building a Query, excluding nodes that match the query built by `group`
+    ///
+    /// See `QueryBuilder::not`
+    fn not<'a, F, V, W>(
+        &self,
+        group: F,
+    ) -> QueryBuilder<'a, NotQuery<QueryWrapper<'a, V, W>>, EmptyQueryWrapper<'a>, N>
+    where
+        F: FnOnce(QueryBuilder<'a, (), (), N>) -> QueryBuilder<'a, V, W, N>,
+        V: Query<N> + 'a,
+        W: Query<N> + 'a,
+    {
+        let qb = self.query_builder();
+        qb.not(group)
+    }
+
+    /// Starts building a Query, matching nodes that match any of the queries added by `group`
+    ///
+    /// See `QueryBuilder::any_of`
+    fn any_of<'a, F, V, W>(
+        &self,
+        group: F,
+    ) -> QueryBuilder<'a, AnyQuery<QueryWrapper<'a, V, W>>, EmptyQueryWrapper<'a>, N>
+    where
+        F: FnOnce(QueryBuilder<'a, (), (), N>) -> QueryBuilder<'a, V, W, N>,
+        V: Query<N> + 'a,
+        W: Query<N> + Alternatives<N> + 'a,
+    {
+        let qb = self.query_builder();
+        qb.any_of(group)
+    }
+
+    /// Finds all descendants of this node that match the CSS selector `selector`
+    ///
+    /// See the `selector` module for the supported syntax. The node the search starts from is
+    /// never part of the results, but it can be referenced with `:scope`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// use soup::prelude::*;
+    ///
+    /// # fn main() -> Result<(), Box<std::error::Error>> {
+    /// let soup = Soup::new(r#"<ul><li>ONE</li><li class="two">TWO</li><li>THREE</li></ul>"#);
+    /// let li_text = soup.select("ul > li:not(.two)")?
+    ///                   .map(|node| node.text())
+    ///                   .collect::<Vec<_>>();
+    /// assert_eq!(li_text, vec!["ONE".to_string(), "THREE".to_string()]);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn select<'a>(&self, selector: &str) -> Result<BoxNodeIter<'a, N>, ParseError>
+    where
+        N: 'a,
+    {
+        let handle = self.get_handle();
+        let selector = Selector::parse(selector)?;
+        let query = ScopedSelector::new(selector, handle.clone());
+        Ok(QueryBuilder::new(handle).push_query(query).find_all())
+    }
+
+    /// Finds the first descendant of this node that matches the CSS selector `selector`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// use soup::prelude::*;
+    ///
+    /// # fn main() -> Result<(), Box<std::error::Error>> {
+    /// let soup = Soup::new(r#"<div><p id="one">ONE</p><p id="two">TWO</p></div>"#);
+    /// let p = soup.select_one("div > p + p")?.expect("Couldn't find p");
+    /// assert_eq!(p.get("id"), Some("two".to_string()));
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn select_one(&self, selector: &str) -> Result<Option<N>, ParseError> {
+        Ok(self.select(selector)?.next())
+    }
+
+    /// Evaluates the XPath 1.0 expression `expr`, with this node as the context node
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// use soup::prelude::*;
+    ///
+    /// # fn main() -> Result<(), Box<std::error::Error>> {
+    /// let soup = Soup::new(r#"<ul><li>ONE</li><li class="x">TWO</li></ul>"#);
+    /// let li = soup.xpath("//li[@class='x']")?.into_handles();
+    /// assert_eq!(li[0].text(), "TWO".to_string());
+    /// assert_eq!(soup.xpath("count(//li)")?.number(), 2.0);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn xpath(&self, expr: &str) -> Result<xpath::Value<N>, xpath::Error> {
+        XPath::parse(expr)?.evaluate(&self.get_handle())
+    }
+
     /// Returns an iterator over the node's children
     ///
     /// # Example
@@ -101,8 +304,11 @@ pub trait QueryBuilderExt {
     /// #   Ok(())
     /// # }
     /// ```
-    fn children(&self) -> NodeChildIter {
-        let handle = self.get_handle();
+    fn children(&self) -> NodeChildIter
+    where
+        Self: QueryBuilderExt<Handle>,
+    {
+        let handle = QueryBuilderExt::<Handle>::get_handle(self);
         NodeChildIter::new(handle.clone())
     }
 
@@ -122,8 +328,11 @@ pub trait QueryBuilderExt {
     /// #   Ok(())
     /// # }
     /// ```
-    fn parents(&self) -> NodeParentIter {
-        NodeParentIter::new(self.get_handle().clone())
+    fn parents(&self) -> NodeParentIter
+    where
+        Self: QueryBuilderExt<Handle>,
+    {
+        NodeParentIter::new(QueryBuilderExt::<Handle>::get_handle(self))
     }
 }
 
@@ -136,7 +345,7 @@ impl fmt::Debug for NodeParentIter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use crate::node_ext::NodeExt;
         f.debug_struct("NodeParentIter")
-            .field("inner", &format!("{}", self.inner.display()))
+            .field("inner", &self.inner.display())
             .finish()
     }
 }
@@ -151,8 +360,7 @@ impl Iterator for NodeParentIter {
     type Item = Handle;
 
     fn next(&mut self) -> Option<Self::Item> {
-        use crate::node_ext::NodeExt;
-        if let Some(ref parent) = self.inner.parent() {
+        if let Some(ref parent) = self.inner.parent_node() {
             self.inner = parent.clone();
             Some(parent.clone())
         } else {
@@ -213,4 +421,9 @@ impl QueryBuilderExt for Soup {
     fn get_handle(&self) -> Handle {
         self.handle.document.clone()
     }
+
+    fn query_builder<'a>(&self) -> QueryBuilder<'a, (), (), Handle> {
+        let index: Rc<dyn Lookup<Handle>> = self.index.clone();
+        QueryBuilder::new(self.get_handle()).with_index(index)
+    }
 }
@@ -0,0 +1,1256 @@
+//! CSS selector parsing & matching
+//!
+//! Supports CSS Selectors Level 3, plus the commonly used parts of Level 4
+//! (`:is()`, `:where()`, `:has()`, selector lists in `:not()`, `:nth-child(An+B of S)`
+//! and case-sensitivity flags on attribute selectors).
+//!
+//! Most of the time you won't need to use this module directly, you can call
+//! `select` or `select_one` on anything that implements `QueryBuilderExt`:
+//!
+//! ```rust
+//! # extern crate soup;
+//! # use soup::prelude::*;
+//! # use std::error::Error;
+//! # fn main() -> Result<(), Box<Error>> {
+//! let soup = Soup::new(r#"<div class="story"><a href="http://example.com">One</a><a href="/two">Two</a></div>"#);
+//! let links = soup.select("div.story > a[href^=http]")?
+//!                 .map(|a| a.text())
+//!                 .collect::<Vec<_>>();
+//! assert_eq!(links, vec!["One".to_string()]);
+//! #   Ok(())
+//! # }
+//! ```
+//!
+//! # Supported syntax
+//!
+//! * Type, universal, `#id` and `.class` selectors
+//! * Attribute selectors: `[attr]`, `[attr=value]`, `[attr~=value]`, `[attr|=value]`,
+//!   `[attr^=value]`, `[attr$=value]`, `[attr*=value]`, each optionally followed by an `i` or `s`
+//!   flag
+//! * Combinators: descendant (` `), child (`>`), next-sibling (`+`) and subsequent-sibling (`~`)
+//! * Structural pseudo-classes: `:root`, `:empty`, `:first-child`, `:last-child`, `:only-child`,
+//!   `:first-of-type`, `:last-of-type`, `:only-of-type`, `:nth-child()`, `:nth-last-child()`,
+//!   `:nth-of-type()`, `:nth-last-of-type()`
+//! * Logical pseudo-classes: `:not()`, `:is()`, `:matches()`, `:where()`, `:has()`
+//! * `:scope`, `:lang()`, `:link`, `:any-link`, `:checked`, `:disabled`, `:enabled`,
+//!   `:required`, `:optional`
+//! * `:contains("text")`, which matches elements whose text contains the given string
+//!
+//! User action pseudo-classes like `:hover` or `:focus` are accepted, but never match anything,
+//! since there is no user to interact with the document. Pseudo-elements are not supported.
+use html5ever::rcdom::Handle;
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::{
+    attribute,
+    dom::{self, Node, NodeKind},
+    find::Query,
+};
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+/// An error encountered while parsing a selector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    message: String,
+    position: usize,
+}
+
+impl ParseError {
+    fn new<S: Into<String>>(message: S, position: usize) -> ParseError {
+        ParseError {
+            message: message.into(),
+            position,
+        }
+    }
+
+    /// The character offset in the selector at which the error occurred
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for ParseError {}
+
+/// A parsed CSS selector list
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate soup;
+/// # use soup::prelude::*;
+/// # use std::error::Error;
+/// use soup::selector::Selector;
+///
+/// # fn main() -> Result<(), Box<Error>> {
+/// let soup = Soup::new(r#"<ul><li>ONE</li><li class="two">TWO</li></ul>"#);
+/// let selector = Selector::parse("ul > li:nth-child(2)")?;
+/// let li = soup.tag("li").find_all().find(|li| selector.matches(li)).expect("Couldn't find li");
+/// assert_eq!(li.get("class"), Some("two".to_string()));
+/// #   Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Selector {
+    selectors: Vec<Complex>,
+}
+
+impl Selector {
+    /// Parses a selector list, like `div.story > a, p[title]`
+    pub fn parse(selector: &str) -> Result<Selector, ParseError> {
+        let mut parser = Parser::new(selector);
+        let selectors = parser.parse_selector_list()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            return Err(ParseError::new(
+                format!("Unexpected character '{}'", c),
+                parser.pos,
+            ));
+        }
+        Ok(Selector {
+            selectors,
+        })
+    }
+
+    /// Returns `true` if the node is an element that matches any of the selectors in this list
+    pub fn matches<N: Node>(&self, node: &N) -> bool {
+        let ctx = Context {
+            scope: None,
+        };
+        matches_any(node, &self.selectors, &ctx)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Selector, ParseError> {
+        Selector::parse(s)
+    }
+}
+
+/// A selector that is matched relative to a scoping element, as done by
+/// `QueryBuilderExt::select`
+///
+/// The scoping element itself never matches, and `:scope` refers to it
+pub(crate) struct ScopedSelector<N = Handle> {
+    selector: Selector,
+    scope: N,
+}
+
+impl<N: Node> ScopedSelector<N> {
+    pub(crate) fn new(selector: Selector, scope: N) -> ScopedSelector<N> {
+        ScopedSelector {
+            selector,
+            scope,
+        }
+    }
+}
+
+impl<N> fmt::Debug for ScopedSelector<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedSelector")
+            .field("selector", &self.selector)
+            .finish()
+    }
+}
+
+impl<N: Node> Query<N> for ScopedSelector<N> {
+    fn matches(&self, node: &N) -> bool {
+        if node.same_node(&self.scope) {
+            return false;
+        }
+        let ctx = Context {
+            scope: Some(&self.scope),
+        };
+        matches_any(node, &self.selector.selectors, &ctx)
+    }
+}
+
+impl<N: Node> Query<N> for Selector {
+    fn matches(&self, node: &N) -> bool {
+        let ctx = Context {
+            scope: None,
+        };
+        matches_any(node, &self.selectors, &ctx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+#[derive(Debug, Clone)]
+struct Complex {
+    // compounds[i] and compounds[i + 1] are joined by combinators[i]
+    compounds: Vec<Compound>,
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Debug, Clone)]
+struct Relative {
+    // read left to right, each compound is related to the previous match
+    // (or the anchor element) by its combinator
+    steps: Vec<(Combinator, Compound)>,
+}
+
+#[derive(Debug, Clone)]
+struct Compound {
+    simple: Vec<Simple>,
+}
+
+#[derive(Debug, Clone)]
+enum Simple {
+    Universal,
+    Type {
+        name: String,
+        lower: String,
+    },
+    Id(String),
+    Class(String),
+    Attribute(AttrSelector),
+    Pseudo(Pseudo),
+}
+
+#[derive(Debug, Clone)]
+struct AttrSelector {
+    name: String,
+    operation: Option<(AttrOperator, String)>,
+    case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttrOperator {
+    Equals,
+    Includes,
+    DashMatch,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Nth {
+    a: i64,
+    b: i64,
+}
+
+impl Nth {
+    // Worked out in `i128`, so that coefficients near the limits of `i64` can't overflow
+    fn matches(self, index: i64) -> bool {
+        let (a, b, index) = (i128::from(self.a), i128::from(self.b), i128::from(index));
+        if a == 0 {
+            index == b
+        } else {
+            let diff = index - b;
+            diff % a == 0 && diff / a >= 0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Pseudo {
+    Root,
+    Empty,
+    Scope,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    NthChild(Nth, Option<Vec<Complex>>),
+    NthLastChild(Nth, Option<Vec<Complex>>),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    Not(Vec<Complex>),
+    Is(Vec<Complex>),
+    Has(Vec<Relative>),
+    Contains(Vec<String>),
+    Lang(Vec<String>),
+    Link,
+    Checked,
+    Disabled,
+    Enabled,
+    Required,
+    Optional,
+    Never,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Parser {
+        Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> ParseError {
+        ParseError::new(message, self.pos)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += 1;
+                Ok(())
+            },
+            Some(found) => Err(self.error(format!("Expected '{}', found '{}'", c, found))),
+            None => Err(self.error(format!("Expected '{}', found end of input", c))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.pos > start
+    }
+
+    fn parse_selector_list(&mut self) -> Result<Vec<Complex>, ParseError> {
+        let mut list = vec![];
+        loop {
+            self.skip_whitespace();
+            list.push(self.parse_complex()?);
+            self.skip_whitespace();
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            } else {
+                return Ok(list);
+            }
+        }
+    }
+
+    fn parse_relative_list(&mut self) -> Result<Vec<Relative>, ParseError> {
+        let mut list = vec![];
+        loop {
+            self.skip_whitespace();
+            let combinator = self.parse_combinator().unwrap_or(Combinator::Descendant);
+            self.skip_whitespace();
+            let complex = self.parse_complex()?;
+            let mut steps = vec![];
+            let mut combinators = Some(combinator)
+                .into_iter()
+                .chain(complex.combinators);
+            for compound in complex.compounds {
+                let combinator = combinators.next().unwrap_or(Combinator::Descendant);
+                steps.push((combinator, compound));
+            }
+            list.push(Relative {
+                steps,
+            });
+            self.skip_whitespace();
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            } else {
+                return Ok(list);
+            }
+        }
+    }
+
+    fn parse_combinator(&mut self) -> Option<Combinator> {
+        let combinator = match self.peek() {
+            Some('>') => Combinator::Child,
+            Some('+') => Combinator::NextSibling,
+            Some('~') => Combinator::SubsequentSibling,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(combinator)
+    }
+
+    fn parse_complex(&mut self) -> Result<Complex, ParseError> {
+        let mut compounds = vec![self.parse_compound()?];
+        let mut combinators = vec![];
+        loop {
+            let had_whitespace = self.skip_whitespace();
+            let combinator = match self.parse_combinator() {
+                Some(combinator) => {
+                    self.skip_whitespace();
+                    combinator
+                },
+                None => match self.peek() {
+                    Some(',') | Some(')') | None => break,
+                    Some(_) if had_whitespace => Combinator::Descendant,
+                    Some(c) => return Err(self.error(format!("Unexpected character '{}'", c))),
+                },
+            };
+            combinators.push(combinator);
+            compounds.push(self.parse_compound()?);
+        }
+        Ok(Complex {
+            compounds,
+            combinators,
+        })
+    }
+
+    fn parse_compound(&mut self) -> Result<Compound, ParseError> {
+        let mut simple = vec![];
+        match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                simple.push(Simple::Universal);
+            },
+            Some(_) if self.starts_ident() => {
+                let name = self.parse_ident()?;
+                let lower = name.to_ascii_lowercase();
+                simple.push(Simple::Type {
+                    name,
+                    lower,
+                });
+            },
+            _ => (),
+        }
+        if self.peek() == Some('|') {
+            return Err(self.error("Namespace prefixes are not supported"));
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.pos += 1;
+                    let name = self.parse_name()?;
+                    simple.push(Simple::Id(name));
+                },
+                Some('.') => {
+                    self.pos += 1;
+                    let name = self.parse_ident()?;
+                    simple.push(Simple::Class(name));
+                },
+                Some('[') => {
+                    self.pos += 1;
+                    simple.push(Simple::Attribute(self.parse_attribute()?));
+                },
+                Some(':') => {
+                    self.pos += 1;
+                    if self.peek() == Some(':') {
+                        return Err(self.error("Pseudo-elements are not supported"));
+                    }
+                    simple.push(Simple::Pseudo(self.parse_pseudo()?));
+                },
+                _ => break,
+            }
+        }
+        if simple.is_empty() {
+            return match self.peek() {
+                Some(c) => Err(self.error(format!("Expected selector, found '{}'", c))),
+                None => Err(self.error("Expected selector, found end of input")),
+            };
+        }
+        Ok(Compound {
+            simple,
+        })
+    }
+
+    fn parse_attribute(&mut self) -> Result<AttrSelector, ParseError> {
+        self.skip_whitespace();
+        let name = self.parse_ident()?;
+        self.skip_whitespace();
+        let operator = match (self.peek(), self.peek_at(1)) {
+            (Some(']'), _) => {
+                self.pos += 1;
+                return Ok(AttrSelector {
+                    name,
+                    operation: None,
+                    case_insensitive: false,
+                });
+            },
+            (Some('='), _) => AttrOperator::Equals,
+            (Some('~'), Some('=')) => AttrOperator::Includes,
+            (Some('|'), Some('=')) => AttrOperator::DashMatch,
+            (Some('^'), Some('=')) => AttrOperator::Prefix,
+            (Some('$'), Some('=')) => AttrOperator::Suffix,
+            (Some('*'), Some('=')) => AttrOperator::Substring,
+            _ => return Err(self.error("Expected attribute operator")),
+        };
+        self.pos += if operator == AttrOperator::Equals {
+            1
+        } else {
+            2
+        };
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(quote @ '"') | Some(quote @ '\'') => {
+                self.pos += 1;
+                self.parse_string(quote)?
+            },
+            _ => self.parse_ident()?,
+        };
+        self.skip_whitespace();
+        let case_insensitive = match self.peek() {
+            Some('i') | Some('I') => {
+                self.pos += 1;
+                true
+            },
+            Some('s') | Some('S') => {
+                self.pos += 1;
+                false
+            },
+            _ => false,
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok(AttrSelector {
+            name,
+            operation: Some((operator, value)),
+            case_insensitive,
+        })
+    }
+
+    fn parse_pseudo(&mut self) -> Result<Pseudo, ParseError> {
+        let start = self.pos;
+        let name = self.parse_ident()?.to_ascii_lowercase();
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            self.skip_whitespace();
+            let pseudo = match name.as_str() {
+                "not" => Pseudo::Not(self.parse_selector_list()?),
+                "is" | "matches" | "any" | "where" => Pseudo::Is(self.parse_selector_list()?),
+                "has" => Pseudo::Has(self.parse_relative_list()?),
+                "nth-child" => {
+                    let nth = self.parse_nth()?;
+                    Pseudo::NthChild(nth, self.parse_nth_of()?)
+                },
+                "nth-last-child" => {
+                    let nth = self.parse_nth()?;
+                    Pseudo::NthLastChild(nth, self.parse_nth_of()?)
+                },
+                "nth-of-type" => Pseudo::NthOfType(self.parse_nth()?),
+                "nth-last-of-type" => Pseudo::NthLastOfType(self.parse_nth()?),
+                "contains" | "-soup-contains" => Pseudo::Contains(self.parse_value_list()?),
+                "lang" => Pseudo::Lang(self.parse_value_list()?),
+                _ => {
+                    return Err(ParseError::new(
+                        format!("Unsupported pseudo-class ':{}()'", name),
+                        start,
+                    ))
+                },
+            };
+            self.skip_whitespace();
+            self.expect(')')?;
+            return Ok(pseudo);
+        }
+        let pseudo = match name.as_str() {
+            "root" => Pseudo::Root,
+            "empty" => Pseudo::Empty,
+            "scope" => Pseudo::Scope,
+            "first-child" => Pseudo::FirstChild,
+            "last-child" => Pseudo::LastChild,
+            "only-child" => Pseudo::OnlyChild,
+            "first-of-type" => Pseudo::FirstOfType,
+            "last-of-type" => Pseudo::LastOfType,
+            "only-of-type" => Pseudo::OnlyOfType,
+            "link" | "any-link" => Pseudo::Link,
+            "checked" => Pseudo::Checked,
+            "disabled" => Pseudo::Disabled,
+            "enabled" => Pseudo::Enabled,
+            "required" => Pseudo::Required,
+            "optional" => Pseudo::Optional,
+            "visited" | "hover" | "active" | "focus" | "focus-within" | "focus-visible"
+            | "target" | "target-within" => Pseudo::Never,
+            _ => {
+                return Err(ParseError::new(
+                    format!("Unsupported pseudo-class ':{}'", name),
+                    start,
+                ))
+            },
+        };
+        Ok(pseudo)
+    }
+
+    fn parse_nth_of(&mut self) -> Result<Option<Vec<Complex>>, ParseError> {
+        self.skip_whitespace();
+        let is_of = match (self.peek(), self.peek_at(1), self.peek_at(2)) {
+            (Some('o'), Some('f'), Some(c)) => c.is_whitespace(),
+            _ => false,
+        };
+        if is_of {
+            self.pos += 2;
+            Ok(Some(self.parse_selector_list()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_nth(&mut self) -> Result<Nth, ParseError> {
+        self.skip_whitespace();
+        if self.starts_ident() && !self.starts_with_ci("n") && !self.starts_with_ci("-n") {
+            let start = self.pos;
+            let ident = self.parse_ident()?.to_ascii_lowercase();
+            return match ident.as_str() {
+                "odd" => Ok(Nth {
+                    a: 2,
+                    b: 1,
+                }),
+                "even" => Ok(Nth {
+                    a: 2,
+                    b: 0,
+                }),
+                _ => Err(ParseError::new(
+                    format!("Invalid An+B expression '{}'", ident),
+                    start,
+                )),
+            };
+        }
+        let sign = self.parse_sign();
+        let digits = self.parse_digits()?;
+        if let Some('n') | Some('N') = self.peek() {
+            self.pos += 1;
+            let a = sign * digits.unwrap_or(1);
+            self.skip_whitespace();
+            let b = match self.peek() {
+                Some('+') | Some('-') => {
+                    let sign = self.parse_sign();
+                    self.skip_whitespace();
+                    match self.parse_digits()? {
+                        Some(digits) => sign * digits,
+                        None => return Err(self.error("Expected number in An+B expression")),
+                    }
+                },
+                _ => 0,
+            };
+            Ok(Nth {
+                a,
+                b,
+            })
+        } else {
+            match digits {
+                Some(digits) => Ok(Nth {
+                    a: 0,
+                    b: sign * digits,
+                }),
+                None => Err(self.error("Invalid An+B expression")),
+            }
+        }
+    }
+
+    fn parse_sign(&mut self) -> i64 {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                -1
+            },
+            Some('+') => {
+                self.pos += 1;
+                1
+            },
+            _ => 1,
+        }
+    }
+
+    // `None` if there are no digits, & an error if there are too many to fit an `i64`
+    fn parse_digits(&mut self) -> Result<Option<i64>, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.pos == start {
+            return Ok(None);
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map(Some)
+            .map_err(|_| ParseError::new("Number is too large in An+B expression", start))
+    }
+
+    fn parse_value_list(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut values = vec![];
+        loop {
+            self.skip_whitespace();
+            let value = match self.peek() {
+                Some(quote @ '"') | Some(quote @ '\'') => {
+                    self.pos += 1;
+                    self.parse_string(quote)?
+                },
+                _ => self.parse_ident()?,
+            };
+            values.push(value);
+            self.skip_whitespace();
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            } else {
+                return Ok(values);
+            }
+        }
+    }
+
+    fn starts_with_ci(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| {
+            self.peek_at(i)
+                .is_some_and(|found| found.eq_ignore_ascii_case(&c))
+        })
+    }
+
+    fn is_name_start(c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+    }
+
+    fn is_name_char(c: char) -> bool {
+        Parser::is_name_start(c) || c.is_ascii_digit() || c == '-'
+    }
+
+    fn starts_ident(&self) -> bool {
+        let (first, second) = match self.peek() {
+            Some('-') => (self.peek_at(1), self.peek_at(2)),
+            _ => (self.peek(), self.peek_at(1)),
+        };
+        match first {
+            Some('-') if self.peek() == Some('-') => true,
+            Some('\\') => second.is_some_and(|c| c != '\n'),
+            Some(c) => Parser::is_name_start(c),
+            None => false,
+        }
+    }
+
+    fn parse_ident(&mut self) -> Result<String, ParseError> {
+        if !self.starts_ident() {
+            return match self.peek() {
+                Some(c) => Err(self.error(format!("Expected identifier, found '{}'", c))),
+                None => Err(self.error("Expected identifier, found end of input")),
+            };
+        }
+        self.parse_name()
+    }
+
+    fn parse_name(&mut self) -> Result<String, ParseError> {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.pos += 1;
+                name.push(self.parse_escape()?);
+            } else if Parser::is_name_char(c) {
+                self.pos += 1;
+                name.push(c);
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error("Expected name"));
+        }
+        Ok(name)
+    }
+
+    // the leading backslash has already been consumed
+    fn parse_escape(&mut self) -> Result<char, ParseError> {
+        let start = self.pos;
+        while self.pos - start < 6 && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        if self.pos > start {
+            let hex = self.chars[start..self.pos].iter().collect::<String>();
+            if let Some(c) = self.peek() {
+                if c.is_whitespace() {
+                    self.pos += 1;
+                }
+            }
+            let code = u32::from_str_radix(&hex, 16).unwrap_or(0xFFFD);
+            return Ok(match ::std::char::from_u32(code) {
+                Some('\0') | None => '\u{FFFD}',
+                Some(c) => c,
+            });
+        }
+        match self.peek() {
+            Some('\n') | None => Err(self.error("Invalid escape")),
+            Some(c) => {
+                self.pos += 1;
+                Ok(c)
+            },
+        }
+    }
+
+    // the opening quote has already been consumed
+    fn parse_string(&mut self, quote: char) -> Result<String, ParseError> {
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(value);
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    if self.peek() == Some('\n') {
+                        self.pos += 1;
+                    } else if self.peek().is_some() {
+                        value.push(self.parse_escape()?);
+                    }
+                },
+                Some(c) => {
+                    self.pos += 1;
+                    value.push(c);
+                },
+            }
+        }
+    }
+}
+
+struct Context<'s, N> {
+    scope: Option<&'s N>,
+}
+
+fn is_element<N: Node>(node: &N) -> bool {
+    node.node_kind() == NodeKind::Element
+}
+
+fn matches_any<N: Node>(node: &N, selectors: &[Complex], ctx: &Context<'_, N>) -> bool {
+    if !is_element(node) {
+        return false;
+    }
+    selectors
+        .iter()
+        .any(|complex| matches_complex(node, complex, complex.compounds.len() - 1, ctx))
+}
+
+fn matches_complex<N: Node>(node: &N, complex: &Complex, idx: usize, ctx: &Context<'_, N>) -> bool {
+    if !matches_compound(node, &complex.compounds[idx], ctx) {
+        return false;
+    }
+    if idx == 0 {
+        return true;
+    }
+    match complex.combinators[idx - 1] {
+        Combinator::Descendant => {
+            let mut current = parent_element(node);
+            while let Some(parent) = current {
+                if matches_complex(&parent, complex, idx - 1, ctx) {
+                    return true;
+                }
+                current = parent_element(&parent);
+            }
+            false
+        },
+        Combinator::Child => parent_element(node)
+            .is_some_and(|parent| matches_complex(&parent, complex, idx - 1, ctx)),
+        Combinator::NextSibling => {
+            let (siblings, i) = element_siblings(node);
+            i > 0 && matches_complex(&siblings[i - 1], complex, idx - 1, ctx)
+        },
+        Combinator::SubsequentSibling => {
+            let (siblings, i) = element_siblings(node);
+            siblings[..i]
+                .iter()
+                .any(|sibling| matches_complex(sibling, complex, idx - 1, ctx))
+        },
+    }
+}
+
+fn matches_relative<N: Node>(
+    anchor: &N,
+    steps: &[(Combinator, Compound)],
+    ctx: &Context<'_, N>,
+) -> bool {
+    let (combinator, ref compound) = steps[0];
+    let rest = &steps[1..];
+    let check = |candidate: &N| {
+        matches_compound(candidate, compound, ctx)
+            && (rest.is_empty() || matches_relative(candidate, rest, ctx))
+    };
+    match combinator {
+        Combinator::Descendant => any_descendant(anchor, &check),
+        Combinator::Child => anchor
+            .child_nodes()
+            .filter(is_element)
+            .any(|child| check(&child)),
+        Combinator::NextSibling => {
+            let (siblings, i) = element_siblings(anchor);
+            siblings.get(i + 1).is_some_and(check)
+        },
+        Combinator::SubsequentSibling => {
+            let (siblings, i) = element_siblings(anchor);
+            siblings.iter().skip(i + 1).any(check)
+        },
+    }
+}
+
+fn any_descendant<N: Node, F: Fn(&N) -> bool>(node: &N, check: &F) -> bool {
+    node.child_nodes()
+        .filter(is_element)
+        .any(|child| check(&child) || any_descendant(&child, check))
+}
+
+fn matches_compound<N: Node>(node: &N, compound: &Compound, ctx: &Context<'_, N>) -> bool {
+    let name = match node.element_name() {
+        Some(name) => name,
+        None => return false,
+    };
+    let is_html = &*name.ns == HTML_NAMESPACE;
+    compound.simple.iter().all(|simple| match *simple {
+        Simple::Universal => true,
+        Simple::Type {
+            name: ref selector_name,
+            ref lower,
+        } => {
+            if is_html {
+                &*name.local == lower.as_str()
+            } else {
+                &*name.local == selector_name.as_str()
+            }
+        },
+        Simple::Id(ref id) => node.any_attribute(&mut |name, value| &*name.local == "id" && value == id),
+        Simple::Class(ref class) => node.any_attribute(&mut |name, value| {
+            &*name.local == "class" && value.split_whitespace().any(|c| c == class)
+        }),
+        Simple::Attribute(ref selector) => node.any_attribute(&mut |name, value| {
+            let attr_name = &*name.local;
+            let name_matches = if is_html {
+                attr_name.eq_ignore_ascii_case(&selector.name)
+            } else {
+                attr_name == selector.name
+            };
+            name_matches && matches_attr_value(selector, value)
+        }),
+        Simple::Pseudo(ref pseudo) => matches_pseudo(node, pseudo, ctx),
+    })
+}
+
+fn matches_attr_value(selector: &AttrSelector, value: &str) -> bool {
+    let (operator, expected) = match selector.operation {
+        Some((operator, ref expected)) => (operator, expected),
+        None => return true,
+    };
+    let (value, expected) = if selector.case_insensitive {
+        (value.to_lowercase(), expected.to_lowercase())
+    } else {
+        (value.to_string(), expected.clone())
+    };
+    match operator {
+        AttrOperator::Equals => value == expected,
+        AttrOperator::Includes => {
+            !expected.is_empty()
+                && !expected.contains(char::is_whitespace)
+                && value.split_whitespace().any(|part| part == expected)
+        },
+        AttrOperator::DashMatch => {
+            value == expected || value.starts_with(&format!("{}-", expected))
+        },
+        AttrOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+        AttrOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+        AttrOperator::Substring => !expected.is_empty() && value.contains(&expected),
+    }
+}
+
+fn matches_pseudo<N: Node>(node: &N, pseudo: &Pseudo, ctx: &Context<'_, N>) -> bool {
+    match *pseudo {
+        Pseudo::Root => node
+            .parent_node()
+            .is_some_and(|parent| parent.node_kind() == NodeKind::Document),
+        Pseudo::Scope => match ctx.scope {
+            Some(scope) if is_element(scope) => node.same_node(scope),
+            _ => matches_pseudo(node, &Pseudo::Root, ctx),
+        },
+        Pseudo::Empty => node.child_nodes().all(|child| match child.node_kind() {
+            NodeKind::Element => false,
+            NodeKind::Text => child.contents().is_none_or(|contents| contents.is_empty()),
+            _ => true,
+        }),
+        Pseudo::FirstChild => element_siblings(node).1 == 0,
+        Pseudo::LastChild => {
+            let (siblings, i) = element_siblings(node);
+            i + 1 == siblings.len()
+        },
+        Pseudo::OnlyChild => element_siblings(node).0.len() == 1,
+        Pseudo::FirstOfType => type_siblings(node).1 == 0,
+        Pseudo::LastOfType => {
+            let (siblings, i) = type_siblings(node);
+            i + 1 == siblings.len()
+        },
+        Pseudo::OnlyOfType => type_siblings(node).0.len() == 1,
+        Pseudo::NthChild(nth, ref of) => {
+            let (_, i) = filtered_siblings(node, of.as_ref().map(Vec::as_slice), ctx);
+            i.is_some_and(|i| nth.matches(i as i64 + 1))
+        },
+        Pseudo::NthLastChild(nth, ref of) => {
+            let (siblings, i) = filtered_siblings(node, of.as_ref().map(Vec::as_slice), ctx);
+            i.is_some_and(|i| nth.matches((siblings.len() - i) as i64))
+        },
+        Pseudo::NthOfType(nth) => nth.matches(type_siblings(node).1 as i64 + 1),
+        Pseudo::NthLastOfType(nth) => {
+            let (siblings, i) = type_siblings(node);
+            nth.matches((siblings.len() - i) as i64)
+        },
+        Pseudo::Not(ref selectors) => !matches_any(node, selectors, ctx),
+        Pseudo::Is(ref selectors) => matches_any(node, selectors, ctx),
+        Pseudo::Has(ref relatives) => relatives
+            .iter()
+            .any(|relative| matches_relative(node, &relative.steps, ctx)),
+        Pseudo::Contains(ref values) => {
+            let text = dom::text(node);
+            values.iter().any(|value| text.contains(value.as_str()))
+        },
+        Pseudo::Lang(ref langs) => {
+            let mut lang = attribute::get(node, "lang");
+            let mut current = node.parent_node();
+            while let (None, Some(parent)) = (&lang, current) {
+                lang = attribute::get(&parent, "lang");
+                current = parent.parent_node();
+            }
+            lang.is_some_and(|lang| {
+                let lang = lang.to_lowercase();
+                langs.iter().any(|expected| {
+                    let expected = expected.to_lowercase();
+                    lang == expected || lang.starts_with(&format!("{}-", expected))
+                })
+            })
+        },
+        Pseudo::Link => {
+            matches!(dom::name(node), "a" | "area" | "link") && attribute::get(node, "href").is_some()
+        },
+        Pseudo::Checked => match dom::name(node) {
+            "input" => {
+                let kind = attribute::get(node, "type").unwrap_or_default().to_lowercase();
+                (kind == "checkbox" || kind == "radio") && attribute::get(node, "checked").is_some()
+            },
+            "option" => attribute::get(node, "selected").is_some(),
+            _ => false,
+        },
+        Pseudo::Disabled => is_form_control(node) && is_disabled(node),
+        Pseudo::Enabled => is_form_control(node) && !is_disabled(node),
+        Pseudo::Required => is_input(node) && attribute::get(node, "required").is_some(),
+        Pseudo::Optional => is_input(node) && attribute::get(node, "required").is_none(),
+        Pseudo::Never => false,
+    }
+}
+
+fn is_input<N: Node>(node: &N) -> bool {
+    matches!(dom::name(node), "input" | "select" | "textarea")
+}
+
+fn is_form_control<N: Node>(node: &N) -> bool {
+    matches!(
+        dom::name(node),
+        "button" | "input" | "select" | "textarea" | "optgroup" | "option" | "fieldset"
+    )
+}
+
+fn is_disabled<N: Node>(node: &N) -> bool {
+    if attribute::get(node, "disabled").is_some() {
+        return true;
+    }
+    // an option inside of a disabled optgroup is disabled too
+    dom::name(node) == "option"
+        && node.parent_node().is_some_and(|parent| {
+            dom::name(&parent) == "optgroup" && attribute::get(&parent, "disabled").is_some()
+        })
+}
+
+fn parent_element<N: Node>(node: &N) -> Option<N> {
+    node.parent_node().filter(is_element)
+}
+
+// Returns the element siblings of `node` (including itself), and the index of
+// `node` in that list
+fn element_siblings<N: Node>(node: &N) -> (Vec<N>, usize) {
+    let parent = match node.parent_node() {
+        Some(parent) => parent,
+        None => return (vec![], 0),
+    };
+    let siblings = parent.child_nodes().filter(is_element).collect::<Vec<_>>();
+    let idx = siblings
+        .iter()
+        .position(|sibling| sibling.same_node(node))
+        .unwrap_or(0);
+    (siblings, idx)
+}
+
+fn type_siblings<N: Node>(node: &N) -> (Vec<N>, usize) {
+    let (siblings, _) = element_siblings(node);
+    let siblings = siblings
+        .into_iter()
+        .filter(|sibling| same_type(sibling, node))
+        .collect::<Vec<_>>();
+    let idx = siblings
+        .iter()
+        .position(|sibling| sibling.same_node(node))
+        .unwrap_or(0);
+    (siblings, idx)
+}
+
+// Siblings that match the `of S` clause of `:nth-child`, if there is one. The
+// index is `None` if `node` itself doesn't match
+fn filtered_siblings<N: Node>(
+    node: &N,
+    of: Option<&[Complex]>,
+    ctx: &Context<'_, N>,
+) -> (Vec<N>, Option<usize>) {
+    let (siblings, _) = element_siblings(node);
+    let siblings = match of {
+        Some(selectors) => siblings
+            .into_iter()
+            .filter(|sibling| matches_any(sibling, selectors, ctx))
+            .collect::<Vec<_>>(),
+        None => siblings,
+    };
+    let idx = siblings
+        .iter()
+        .position(|sibling| sibling.same_node(node));
+    (siblings, idx)
+}
+
+fn same_type<N: Node>(a: &N, b: &N) -> bool {
+    match (a.element_name(), b.element_name()) {
+        (Some(a), Some(b)) => a.ns == b.ns && a.local == b.local,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Selector;
+    use crate::prelude::*;
+
+    const HTML: &str = r#"
+<html><body>
+<div id="main" class="story wide">
+  <h1 lang="en-US">Title</h1>
+  <p class="intro">First</p>
+  <p>Second <a href="http://example.com/one">one</a></p>
+  <p>Third <a href="/two">two</a></p>
+  <ul>
+    <li>1</li><li class="odd">2</li><li>3</li><li class="odd">4</li><li>5</li>
+  </ul>
+  <span></span>
+  <input type="checkbox" checked>
+  <input type="text" disabled>
+</div>
+</body></html>
+"#;
+
+    fn select(selector: &str) -> Vec<String> {
+        let soup = Soup::new(HTML);
+        soup.select(selector)
+            .expect("Couldn't parse selector")
+            .map(|node| node.text())
+            .collect()
+    }
+
+    fn count(selector: &str) -> usize {
+        let soup = Soup::new(HTML);
+        soup.select(selector).expect("Couldn't parse selector").count()
+    }
+
+    #[test]
+    fn type_id_class() {
+        assert_eq!(select("h1"), vec!["Title"]);
+        assert_eq!(select("P.intro"), vec!["First"]);
+        assert_eq!(count("#main"), 1);
+        assert_eq!(count("div.story.wide"), 1);
+        assert_eq!(count("div.story.narrow"), 0);
+        assert_eq!(count("*"), 19);
+    }
+
+    #[test]
+    fn combinators() {
+        assert_eq!(select("div > p > a"), vec!["one", "two"]);
+        assert_eq!(select("body a"), vec!["one", "two"]);
+        assert_eq!(select("h1 + p"), vec!["First"]);
+        assert_eq!(count("h1 ~ p"), 3);
+        assert_eq!(count("body > p"), 0);
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(select("a[href^=http]"), vec!["one"]);
+        assert_eq!(select("a[href$='two']"), vec!["two"]);
+        assert_eq!(select("a[href*=example]"), vec!["one"]);
+        assert_eq!(count("div[class~=wide]"), 1);
+        assert_eq!(count("h1[lang|=en]"), 1);
+        assert_eq!(count("[class=INTRO i]"), 1);
+        assert_eq!(count("[class=INTRO]"), 0);
+        assert_eq!(count("input[disabled]"), 1);
+    }
+
+    #[test]
+    fn structural_pseudo_classes() {
+        assert_eq!(select("li:first-child"), vec!["1"]);
+        assert_eq!(select("li:last-child"), vec!["5"]);
+        assert_eq!(select("li:nth-child(2n+1)"), vec!["1", "3", "5"]);
+        assert_eq!(select("li:nth-child(even)"), vec!["2", "4"]);
+        assert_eq!(select("li:nth-last-child(-n+2)"), vec!["4", "5"]);
+        assert_eq!(select("li:nth-child(2 of .odd)"), vec!["4"]);
+        assert_eq!(select("li:nth-child(n-9223372036854775807)"), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(count("li:nth-child(-9223372036854775807n+9223372036854775807)"), 0);
+        assert_eq!(count("li:nth-last-child(9223372036854775807n-9223372036854775807)"), 0);
+        assert_eq!(select("p:nth-of-type(2)"), vec!["Second one"]);
+        assert_eq!(select("p:first-of-type"), vec!["First"]);
+        assert_eq!(count("span:empty"), 1);
+        assert_eq!(count("a:only-child"), 2);
+        assert_eq!(count(":root"), 1);
+    }
+
+    #[test]
+    fn logical_pseudo_classes() {
+        assert_eq!(select("p:not(.intro)"), vec!["Second one", "Third two"]);
+        assert_eq!(select("p:has(> a[href^=http])"), vec!["Second one"]);
+        assert_eq!(select(":is(h1, .intro)"), vec!["Title", "First"]);
+        assert_eq!(select("li:where(.odd):not(:nth-child(4))"), vec!["2"]);
+        assert_eq!(select("p:contains('Third')"), vec!["Third two"]);
+        assert_eq!(select(":lang(en)"), vec!["Title"]);
+    }
+
+    #[test]
+    fn form_pseudo_classes() {
+        assert_eq!(count("input:checked"), 1);
+        assert_eq!(count("input:disabled"), 1);
+        assert_eq!(count("input:enabled"), 1);
+        assert_eq!(count("a:link"), 2);
+        assert_eq!(count("a:hover"), 0);
+    }
+
+    #[test]
+    fn scoped() {
+        let soup = Soup::new(HTML);
+        let ul = soup.select_one("ul").expect("Couldn't parse").expect("Couldn't find ul");
+        assert_eq!(ul.select(":scope > li").expect("Couldn't parse").count(), 5);
+        assert_eq!(ul.select("ul").expect("Couldn't parse").count(), 0);
+        assert_eq!(ul.select("div li").expect("Couldn't parse").count(), 5);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("div >").is_err());
+        assert!(Selector::parse("p::before").is_err());
+        assert!(Selector::parse("p:unknown").is_err());
+        assert!(Selector::parse("a[href").is_err());
+        assert!(Selector::parse("li:nth-child(foo)").is_err());
+        assert!(Selector::parse("li:nth-child(99999999999999999999999n+2)").is_err());
+        assert!(Selector::parse("li:nth-child(n+99999999999999999999999)").is_err());
+        let err = Selector::parse("div $").expect_err("Parsed invalid selector");
+        assert_eq!(err.position(), 4);
+    }
+}
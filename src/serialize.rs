@@ -0,0 +1,608 @@
+//! HTML serialization
+//!
+//! Serializes nodes back to markup following the HTML fragment serialization algorithm, so
+//! that text & attribute values are escaped, void elements have no end tag, and the contents
+//! of raw text elements like `script` & `style` are written out untouched.
+//!
+//! Use `NodeExt::outer_html` & `NodeExt::inner_html` for the default options, or the
+//! `outer_html_with` & `inner_html_with` variants to pass a `SerializeOptions`. For indented
+//! output meant for people to read, use `NodeExt::prettify` or `Soup::prettify`.
+//!
+//! ```rust
+//! # extern crate soup;
+//! # use soup::prelude::*;
+//! # use std::error::Error;
+//! use soup::serialize::{AttributeOrder, Quote, SerializeOptions};
+//!
+//! # fn main() -> Result<(), Box<Error>> {
+//! let soup = Soup::new(r#"<p title="a &quot;quote&quot;" class=x>1 &lt; 2<br></p>"#);
+//! let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+//! assert_eq!(p.outer_html(), r#"<p title="a &quot;quote&quot;" class="x">1 &lt; 2<br></p>"#);
+//! assert_eq!(p.inner_html(), "1 &lt; 2<br>");
+//! let options = SerializeOptions::new()
+//!     .attribute_order(AttributeOrder::Sorted)
+//!     .quote(Quote::Single);
+//! assert_eq!(p.outer_html_with(&options), r#"<p class='x' title='a "quote"'>1 &lt; 2<br></p>"#);
+//! #   Ok(())
+//! # }
+//! ```
+use html5ever::{
+    rcdom::{Handle, NodeData},
+    Attribute,
+    QualName,
+};
+
+use crate::node_ext::NodeExt;
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+const MATHML_NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+/// The order that attributes are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeOrder {
+    /// The order the attributes appear in the source (or were added in)
+    Source,
+    /// Sorted by name
+    Sorted,
+}
+
+/// The quote character used around attribute values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// `name="value"`
+    Double,
+    /// `name='value'`
+    Single,
+}
+
+/// Options for serializing nodes to HTML
+///
+/// The defaults follow the HTML fragment serialization algorithm: attributes are written in
+/// source order, in double quotes, and `noscript` is treated as a raw text element, the same
+/// as when it was parsed with scripting enabled.
+///
+/// The same options are used by `NodeExt::prettify_with`, which also uses the `indent` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerializeOptions {
+    attribute_order: AttributeOrder,
+    quote: Quote,
+    scripting: bool,
+    indent: usize,
+}
+
+impl Default for SerializeOptions {
+    fn default() -> SerializeOptions {
+        SerializeOptions {
+            attribute_order: AttributeOrder::Source,
+            quote: Quote::Double,
+            scripting: true,
+            indent: 2,
+        }
+    }
+}
+
+impl SerializeOptions {
+    /// Creates the default options
+    pub fn new() -> SerializeOptions {
+        SerializeOptions::default()
+    }
+
+    /// Sets the order that attributes are written in
+    pub fn attribute_order(mut self, order: AttributeOrder) -> SerializeOptions {
+        self.attribute_order = order;
+        self
+    }
+
+    /// Sets the quote character used around attribute values
+    pub fn quote(mut self, quote: Quote) -> SerializeOptions {
+        self.quote = quote;
+        self
+    }
+
+    /// Sets whether the contents of `noscript` are written out untouched, which should match
+    /// the scripting flag the document was parsed with
+    pub fn scripting(mut self, scripting: bool) -> SerializeOptions {
+        self.scripting = scripting;
+        self
+    }
+
+    /// Sets the number of spaces each level is indented by when prettifying, 2 by default
+    pub fn indent(mut self, indent: usize) -> SerializeOptions {
+        self.indent = indent;
+        self
+    }
+}
+
+pub(crate) fn outer_html(node: &Handle, options: &SerializeOptions) -> String {
+    let mut out = String::new();
+    match node.data {
+        NodeData::Document => serialize_children(node, options, &mut out),
+        NodeData::Text {
+            ref contents,
+        } => {
+            let raw = node
+                .parent()
+                .is_some_and(|parent| is_raw_text(&parent, options));
+            write_text(&contents.borrow(), raw, &mut out);
+        },
+        _ => serialize_node(node, options, &mut out),
+    }
+    out
+}
+
+pub(crate) fn inner_html(node: &Handle, options: &SerializeOptions) -> String {
+    let mut out = String::new();
+    serialize_children(node, options, &mut out);
+    out
+}
+
+pub(crate) fn prettify(node: &Handle, options: &SerializeOptions) -> String {
+    let mut out = String::new();
+    match node.data {
+        NodeData::Document => pretty_children(node, 0, options, &mut out),
+        _ => pretty_node(node, 0, options, &mut out),
+    }
+    out
+}
+
+fn write_line(line: &str, depth: usize, options: &SerializeOptions, out: &mut String) {
+    for _ in 0..depth * options.indent {
+        out.push(' ');
+    }
+    out.push_str(line);
+    out.push('\n');
+}
+
+// Writes a block-level node, starting on a new line
+fn pretty_node(node: &Handle, depth: usize, options: &SerializeOptions, out: &mut String) {
+    match node.data {
+        NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } => {
+            if is_preformatted(name) || is_raw_text(node, options) {
+                write_line(&outer_html(node, options), depth, options, out);
+            } else if is_inline_content(node, options) {
+                let mut line = String::new();
+                write_inline(node, options, &mut line);
+                write_line(line.trim(), depth, options, out);
+            } else {
+                let mut tag = String::new();
+                write_start_tag(name, &attrs.borrow(), options, &mut tag);
+                write_line(&tag, depth, options, out);
+                pretty_children(node, depth + 1, options, out);
+                let mut tag = String::new();
+                write_end_tag(name, &mut tag);
+                write_line(&tag, depth, options, out);
+            }
+        },
+        NodeData::Text {
+            ..
+        } => {
+            let mut line = String::new();
+            write_inline(node, options, &mut line);
+            if !line.trim().is_empty() {
+                write_line(line.trim(), depth, options, out);
+            }
+        },
+        NodeData::Document => pretty_children(node, depth, options, out),
+        _ => {
+            let mut line = String::new();
+            serialize_node(node, options, &mut line);
+            write_line(&line, depth, options, out);
+        },
+    }
+}
+
+// Writes the children of a node, putting each run of inline content on a line of its own
+fn pretty_children(node: &Handle, depth: usize, options: &SerializeOptions, out: &mut String) {
+    if let NodeData::Element {
+        template_contents: Some(ref contents),
+        ..
+    } = node.data
+    {
+        return pretty_children(contents, depth, options, out);
+    }
+    let mut line = String::new();
+    for child in node.children.borrow().iter() {
+        if is_inline(child, options) {
+            write_inline(child, options, &mut line);
+            continue;
+        }
+        if !line.trim().is_empty() {
+            write_line(line.trim(), depth, options, out);
+        }
+        line.clear();
+        pretty_node(child, depth, options, out);
+    }
+    if !line.trim().is_empty() {
+        write_line(line.trim(), depth, options, out);
+    }
+}
+
+// Writes a node on the current line, collapsing runs of whitespace in its text
+fn write_inline(node: &Handle, options: &SerializeOptions, out: &mut String) {
+    match node.data {
+        NodeData::Text {
+            ref contents,
+        } => {
+            let mut escaped = String::new();
+            write_text(&contents.borrow(), false, &mut escaped);
+            for c in escaped.chars() {
+                if c.is_whitespace() && c != '\u{a0}' {
+                    if !out.ends_with(' ') {
+                        out.push(' ');
+                    }
+                } else {
+                    out.push(c);
+                }
+            }
+        },
+        NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } => {
+            write_start_tag(name, &attrs.borrow(), options, out);
+            if is_void(name) {
+                return;
+            }
+            for child in node.children.borrow().iter() {
+                write_inline(child, options, out);
+            }
+            write_end_tag(name, out);
+        },
+        _ => serialize_node(node, options, out),
+    }
+}
+
+fn is_preformatted(name: &QualName) -> bool {
+    is_html(name) && matches!(&*name.local, "pre" | "textarea" | "listing")
+}
+
+// Whether a node can be written as part of a line of text
+fn is_inline(node: &Handle, options: &SerializeOptions) -> bool {
+    match node.data {
+        NodeData::Text {
+            ..
+        }
+        | NodeData::Comment {
+            ..
+        } => true,
+        NodeData::Element {
+            ref name, ..
+        } => {
+            is_html(name)
+                && is_inline_element(&name.local)
+                && !is_preformatted(name)
+                && !is_raw_text(node, options)
+                && is_inline_content(node, options)
+        },
+        _ => false,
+    }
+}
+
+fn is_inline_content(node: &Handle, options: &SerializeOptions) -> bool {
+    node.children
+        .borrow()
+        .iter()
+        .all(|child| is_inline(child, options))
+}
+
+fn is_inline_element(name: &str) -> bool {
+    matches!(
+        name,
+        "a" | "abbr"
+            | "b"
+            | "bdi"
+            | "bdo"
+            | "big"
+            | "br"
+            | "button"
+            | "cite"
+            | "code"
+            | "data"
+            | "del"
+            | "dfn"
+            | "em"
+            | "font"
+            | "i"
+            | "img"
+            | "input"
+            | "ins"
+            | "kbd"
+            | "label"
+            | "mark"
+            | "nobr"
+            | "q"
+            | "s"
+            | "samp"
+            | "small"
+            | "span"
+            | "strike"
+            | "strong"
+            | "sub"
+            | "sup"
+            | "time"
+            | "tt"
+            | "u"
+            | "var"
+            | "wbr"
+    )
+}
+
+fn serialize_children(node: &Handle, options: &SerializeOptions, out: &mut String) {
+    if let NodeData::Element {
+        template_contents: Some(ref contents),
+        ..
+    } = node.data
+    {
+        return serialize_children(contents, options, out);
+    }
+    let raw = is_raw_text(node, options);
+    for child in node.children.borrow().iter() {
+        match child.data {
+            NodeData::Text {
+                ref contents,
+            } => write_text(&contents.borrow(), raw, out),
+            _ => serialize_node(child, options, out),
+        }
+    }
+}
+
+fn serialize_node(node: &Handle, options: &SerializeOptions, out: &mut String) {
+    match node.data {
+        NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } => {
+            write_start_tag(name, &attrs.borrow(), options, out);
+            if is_void(name) {
+                return;
+            }
+            serialize_children(node, options, out);
+            write_end_tag(name, out);
+        },
+        NodeData::Text {
+            ref contents,
+        } => write_text(&contents.borrow(), false, out),
+        NodeData::Comment {
+            ref contents,
+        } => {
+            out.push_str("<!--");
+            out.push_str(contents);
+            out.push_str("-->");
+        },
+        NodeData::ProcessingInstruction {
+            ref target,
+            ref contents,
+        } => {
+            out.push_str("<?");
+            out.push_str(target);
+            out.push(' ');
+            out.push_str(contents);
+            out.push('>');
+        },
+        NodeData::Doctype {
+            ref name, ..
+        } => {
+            out.push_str("<!DOCTYPE ");
+            out.push_str(name);
+            out.push('>');
+        },
+        NodeData::Document => serialize_children(node, options, out),
+    }
+}
+
+fn write_start_tag(
+    name: &QualName,
+    attrs: &[Attribute],
+    options: &SerializeOptions,
+    out: &mut String,
+) {
+    out.push('<');
+    out.push_str(&element_name(name));
+    let mut attrs = attrs.iter().collect::<Vec<_>>();
+    if options.attribute_order == AttributeOrder::Sorted {
+        attrs.sort_by(|a, b| (attribute_name(a), &*a.value).cmp(&(attribute_name(b), &*b.value)));
+    }
+    for attr in attrs {
+        write_attribute(attr, options.quote, out);
+    }
+    out.push('>');
+}
+
+fn write_end_tag(name: &QualName, out: &mut String) {
+    out.push_str("</");
+    out.push_str(&element_name(name));
+    out.push('>');
+}
+
+fn element_name(name: &QualName) -> String {
+    match &*name.ns {
+        HTML_NAMESPACE | MATHML_NAMESPACE | SVG_NAMESPACE => name.local.to_string(),
+        _ => match name.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, name.local),
+            None => name.local.to_string(),
+        },
+    }
+}
+
+fn attribute_name(attr: &Attribute) -> String {
+    let name = &attr.name;
+    match &*name.ns {
+        "" => name.local.to_string(),
+        XML_NAMESPACE => format!("xml:{}", name.local),
+        XMLNS_NAMESPACE if &*name.local == "xmlns" => "xmlns".to_string(),
+        XMLNS_NAMESPACE => format!("xmlns:{}", name.local),
+        XLINK_NAMESPACE => format!("xlink:{}", name.local),
+        _ => match name.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, name.local),
+            None => name.local.to_string(),
+        },
+    }
+}
+
+fn write_attribute(attr: &Attribute, quote: Quote, out: &mut String) {
+    let quote_char = match quote {
+        Quote::Double => '"',
+        Quote::Single => '\'',
+    };
+    out.push(' ');
+    out.push_str(&attribute_name(attr));
+    out.push('=');
+    out.push(quote_char);
+    for c in attr.value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '"' if quote == Quote::Double => out.push_str("&quot;"),
+            '\'' if quote == Quote::Single => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out.push(quote_char);
+}
+
+fn write_text(text: &str, raw: bool, out: &mut String) {
+    if raw {
+        out.push_str(text);
+        return;
+    }
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn is_html(name: &QualName) -> bool {
+    &*name.ns == HTML_NAMESPACE
+}
+
+fn is_void(name: &QualName) -> bool {
+    is_html(name)
+        && matches!(
+            &*name.local,
+            "area"
+                | "base"
+                | "basefont"
+                | "bgsound"
+                | "br"
+                | "col"
+                | "embed"
+                | "frame"
+                | "hr"
+                | "img"
+                | "input"
+                | "keygen"
+                | "link"
+                | "meta"
+                | "param"
+                | "source"
+                | "track"
+                | "wbr"
+        )
+}
+
+// Whether the text children of `node` are written out without escaping
+fn is_raw_text(node: &Handle, options: &SerializeOptions) -> bool {
+    match node.data {
+        NodeData::Element {
+            ref name, ..
+        } if is_html(name) => match &*name.local {
+            "style" | "script" | "xmp" | "iframe" | "noembed" | "noframes" | "plaintext" => true,
+            "noscript" => options.scripting,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributeOrder, Quote, SerializeOptions};
+    use crate::prelude::*;
+
+    #[test]
+    fn escaping() {
+        let soup = Soup::new(r#"<p data-x='a"b&amp;c'>Tom &amp; Jerry &lt;3&nbsp;</p>"#);
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        assert_eq!(p.outer_html(), r#"<p data-x="a&quot;b&amp;c">Tom &amp; Jerry &lt;3&nbsp;</p>"#);
+        let single = SerializeOptions::new().quote(Quote::Single);
+        assert_eq!(p.outer_html_with(&single), r#"<p data-x='a"b&amp;c'>Tom &amp; Jerry &lt;3&nbsp;</p>"#);
+        let text = p.children().next().expect("p has no children");
+        assert_eq!(text.outer_html(), "Tom &amp; Jerry &lt;3&nbsp;");
+    }
+
+    #[test]
+    fn special_elements() {
+        let html = "<!DOCTYPE html><html><head><script>if (a < b && c) {}</script>\
+                    <style>p > b { color: red }</style></head>\
+                    <body><br><img src=\"x.png\"><?php echo 1 ?><template><b>T</b></template>\
+                    <svg viewBox=\"0 0 1 1\"><use xlink:href=\"#a\"></use></svg></body></html>";
+        let soup = Soup::new(html);
+        let expected = "<!DOCTYPE html><html><head><script>if (a < b && c) {}</script>\
+                        <style>p > b { color: red }</style></head>\
+                        <body><br><img src=\"x.png\"><!--?php echo 1 ?--><template><b>T</b></template>\
+                        <svg viewBox=\"0 0 1 1\"><use xlink:href=\"#a\"></use></svg></body></html>";
+        assert_eq!(soup.get_handle().outer_html(), expected);
+        let reparsed = Soup::new(&soup.get_handle().outer_html());
+        assert_eq!(reparsed.get_handle().outer_html(), expected);
+    }
+
+    #[test]
+    fn attribute_order() {
+        let soup = Soup::new(r#"<a id="x" href="/" class="c">Link</a>"#);
+        let a = soup.tag("a").find().expect("Couldn't find tag 'a'");
+        assert_eq!(a.outer_html(), r#"<a id="x" href="/" class="c">Link</a>"#);
+        let sorted = SerializeOptions::new().attribute_order(AttributeOrder::Sorted);
+        assert_eq!(a.outer_html_with(&sorted), r#"<a class="c" href="/" id="x">Link</a>"#);
+        assert_eq!(a.display(), r#"<a class="c" href="/" id="x">Link</a>"#);
+        let body = a.parent().expect("a has no parent");
+        assert_eq!(body.inner_html(), r#"<a id="x" href="/" class="c">Link</a>"#);
+    }
+
+    #[test]
+    fn prettify() {
+        let soup = Soup::new(
+            "<!DOCTYPE html><html><head><title>T</title></head><body>\n\
+             <div id=\"main\"><p>Some   <b>bold</b>\n text</p>\n<ul><li>One</li><li>Two</li></ul>\
+             Loose <i>text</i><!-- note --><pre>  keep\n    this</pre><br></div></body></html>",
+        );
+        let expected = "<!DOCTYPE html>
+<html>
+  <head>
+    <title>T</title>
+  </head>
+  <body>
+    <div id=\"main\">
+      <p>Some <b>bold</b> text</p>
+      <ul>
+        <li>One</li>
+        <li>Two</li>
+      </ul>
+      Loose <i>text</i><!-- note -->
+      <pre>  keep
+    this</pre>
+      <br>
+    </div>
+  </body>
+</html>
+";
+        assert_eq!(soup.prettify(), expected);
+        let ul = soup.tag("ul").find().expect("Couldn't find tag 'ul'");
+        let options = SerializeOptions::new().indent(4);
+        assert_eq!(ul.prettify_with(&options), "<ul>\n    <li>One</li>\n    <li>Two</li>\n</ul>\n");
+    }
+}
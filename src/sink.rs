@@ -0,0 +1,200 @@
+use html5ever::{
+    rcdom::{Handle, RcDom},
+    tendril::StrTendril,
+    tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeSink},
+    Attribute,
+    ExpandedName,
+    LocalName,
+    QualName,
+};
+use std::{borrow::Cow, error::Error, fmt};
+
+use crate::position::{Positions, SourcePosition};
+
+/// An error encountered while parsing HTML
+///
+/// HTML parsing never fails outright, the parser recovers from every error the same way a
+/// browser would, but the errors are still recorded & available from `Soup::errors`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    message: String,
+    line: Option<u64>,
+}
+
+impl ParseError {
+    pub(crate) fn new<S: Into<String>>(message: S, line: Option<u64>) -> ParseError {
+        ParseError {
+            message: message.into(),
+            line,
+        }
+    }
+
+    /// The error message from the parser
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The line in the source the error was found on, starting at 1
+    ///
+    /// This is the line the parser had reached when it found the error, which for an error in
+    /// a tag that spans lines is the line the tag ends on. It's `None` for a `Soup` that was
+    /// created from an `RcDom`, which doesn't record lines.
+    pub fn line(&self) -> Option<u64> {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} on line {}", self.message, line),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+// The token the tree builder is working on, when source positions are being recorded
+pub(crate) enum Pending {
+    // A start tag, whose element hasn't been created yet
+    Element(LocalName, SourcePosition),
+    // Some text
+    Text(SourcePosition),
+}
+
+// Wraps an `RcDom`, keeping track of the line the parser is on so parse errors can be
+// recorded along with it, and recording where nodes start if asked to
+pub(crate) struct Sink {
+    pub(crate) dom: RcDom,
+    pub(crate) errors: Vec<ParseError>,
+    pub(crate) pending: Option<Pending>,
+    pub(crate) positions: Positions,
+    line: u64,
+}
+
+impl Default for Sink {
+    fn default() -> Sink {
+        Sink {
+            dom: RcDom::default(),
+            errors: vec![],
+            pending: None,
+            positions: Positions::default(),
+            line: 1,
+        }
+    }
+}
+
+impl TreeSink for Sink {
+    type Handle = Handle;
+    type Output = Self;
+
+    fn finish(self) -> Self {
+        self
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        self.errors.push(ParseError::new(msg, Some(self.line)));
+    }
+
+    fn set_current_line(&mut self, line_number: u64) {
+        self.line = line_number;
+    }
+
+    fn get_document(&mut self) -> Handle {
+        self.dom.get_document()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a Handle) -> ExpandedName<'a> {
+        self.dom.elem_name(target)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> Handle {
+        // Only the element for the start tag itself gets its position, not any that the tree
+        // builder creates along the way
+        let start = match self.pending {
+            Some(Pending::Element(ref tag, start)) if *tag == name.local => Some(start),
+            _ => None,
+        };
+        let node = self.dom.create_element(name, attrs, flags);
+        if let Some(start) = start {
+            self.pending = None;
+            self.positions.record(&node, start);
+        }
+        node
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> Handle {
+        self.dom.create_comment(text)
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> Handle {
+        self.dom.create_pi(target, data)
+    }
+
+    fn append(&mut self, parent: &Handle, child: NodeOrText<Handle>) {
+        let start = match (&self.pending, &child) {
+            (&Some(Pending::Text(start)), &NodeOrText::AppendText(_)) => Some(start),
+            _ => None,
+        };
+        let count = parent.children.borrow().len();
+        self.dom.append(parent, child);
+        // Text that was merged into the previous text node doesn't start a node of its own
+        if let Some(start) = start {
+            let children = parent.children.borrow();
+            if children.len() > count {
+                self.positions.record(&children[count], start);
+            }
+        }
+    }
+
+    fn append_based_on_parent_node(
+        &mut self,
+        element: &Handle,
+        prev_element: &Handle,
+        child: NodeOrText<Handle>,
+    ) {
+        self.dom.append_based_on_parent_node(element, prev_element, child)
+    }
+
+    fn append_doctype_to_document(
+        &mut self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        self.dom.append_doctype_to_document(name, public_id, system_id)
+    }
+
+    fn get_template_contents(&mut self, target: &Handle) -> Handle {
+        self.dom.get_template_contents(target)
+    }
+
+    fn same_node(&self, x: &Handle, y: &Handle) -> bool {
+        self.dom.same_node(x, y)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.dom.set_quirks_mode(mode)
+    }
+
+    fn append_before_sibling(&mut self, sibling: &Handle, new_node: NodeOrText<Handle>) {
+        self.dom.append_before_sibling(sibling, new_node)
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &Handle, attrs: Vec<Attribute>) {
+        self.dom.add_attrs_if_missing(target, attrs)
+    }
+
+    fn remove_from_parent(&mut self, target: &Handle) {
+        self.dom.remove_from_parent(target)
+    }
+
+    fn reparent_children(&mut self, node: &Handle, new_parent: &Handle) {
+        self.dom.reparent_children(node, new_parent)
+    }
+
+    fn is_mathml_annotation_xml_integration_point(&self, target: &Handle) -> bool {
+        self.dom.is_mathml_annotation_xml_integration_point(target)
+    }
+}
@@ -0,0 +1,445 @@
+use encoding_rs::Encoding;
+use html5ever::{
+    parse_document,
+    rcdom::RcDom,
+    tendril::TendrilSink,
+    QualName,
+};
+use std::{borrow::Cow, fmt, rc::Rc, sync::Arc};
+
+use crate::{
+    arena::{ArenaSink, Data, Entry, NodeId, Tree},
+    dom::{self, Node, NodeKind},
+    position::Positions,
+    qb_ext::QueryBuilderExt,
+    sink::ParseError,
+    QuirksMode,
+    Soup,
+};
+
+/// A read-only document that can be shared between threads
+///
+/// A `Soup` is made of `Rc`s, so it can't leave the thread it was parsed on. A `SyncSoup` holds
+/// the same tree in a single `Arc`, so it is `Send` & `Sync`, and cloning it is cheap. It can
+/// be searched with the `QueryBuilderExt` methods, CSS selectors & XPath included, and its
+/// nodes have the reading & navigation methods of `NodeExt` through `dom::Node`. To change the
+/// document, turn it back into a `Soup` with `to_soup`.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate soup;
+/// # use soup::prelude::*;
+/// # use std::error::Error;
+/// use soup::{dom::Node, SyncSoup};
+/// use std::thread;
+///
+/// # fn main() -> Result<(), Box<Error>> {
+/// let pages = vec![r#"<a href="/one">One</a>"#, r#"<a href="/two">Two</a>"#];
+/// let threads = pages
+///     .into_iter()
+///     .map(|page| thread::spawn(move || SyncSoup::new(page)))
+///     .collect::<Vec<_>>();
+/// let links = threads
+///     .into_iter()
+///     .map(|thread| thread.join().expect("Couldn't parse page"))
+///     .filter_map(|soup| soup.tag("a").find().and_then(|a| a.get("href")))
+///     .collect::<Vec<_>>();
+/// assert_eq!(links, vec!["/one".to_string(), "/two".to_string()]);
+/// #   Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SyncSoup {
+    root: SyncNode,
+    quirks_mode: QuirksMode,
+    original_encoding: Option<&'static Encoding>,
+    errors: Vec<ParseError>,
+}
+
+impl SyncSoup {
+    /// Create a new `SyncSoup` from a string slice
+    pub fn new(html: &str) -> SyncSoup {
+        parse_document(ArenaSink::default(), Default::default())
+            .from_utf8()
+            .one(html.as_bytes())
+    }
+
+    pub(crate) fn from_tree(tree: Tree, quirks_mode: QuirksMode, errors: Vec<ParseError>) -> SyncSoup {
+        SyncSoup {
+            root: SyncNode {
+                tree: Arc::new(tree),
+                id: Tree::document(),
+            },
+            quirks_mode,
+            original_encoding: None,
+            errors,
+        }
+    }
+
+    /// Looks up the node with the id `id`, if the document has one
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// use soup::{dom::Node, SyncSoup};
+    ///
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = SyncSoup::new(r#"<p>One</p><p>Two</p>"#);
+    /// let ids = soup.tag("p").find_all().map(|p| p.id()).collect::<Vec<_>>();
+    /// let second = soup.node(ids[1]).expect("Couldn't find node");
+    /// assert_eq!(second.text(), "Two");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn node(&self, id: NodeId) -> Option<SyncNode> {
+        if id.index() < self.root.tree.len() {
+            self.root.relative(Some(id))
+        } else {
+            None
+        }
+    }
+
+    /// Copies the document into a `Soup`, which can be changed
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use soup::prelude::*;
+    /// # use std::error::Error;
+    /// use soup::SyncSoup;
+    ///
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let sync = SyncSoup::new(r#"<p>Hello</p>"#);
+    /// let soup = sync.to_soup();
+    /// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+    /// p.set_attr("id", "greeting");
+    /// assert_eq!(p.display(), r#"<p id="greeting">Hello</p>"#);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    pub fn to_soup(&self) -> Soup {
+        Soup {
+            handle: RcDom {
+                document: self.root.tree.to_handle(),
+                errors: vec![],
+                quirks_mode: self.quirks_mode,
+            },
+            original_encoding: self.original_encoding,
+            errors: self.errors.clone(),
+            positions: Positions::default(),
+            index: Rc::default(),
+        }
+    }
+
+    /// The name of the encoding the document was decoded from, like `Soup::original_encoding`
+    pub fn original_encoding(&self) -> Option<&'static str> {
+        self.original_encoding.map(|encoding| encoding.name())
+    }
+
+    /// The errors the parser recovered from while parsing the document, like `Soup::errors`
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// The quirks mode the document was parsed in, like `Soup::quirks_mode`
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    /// Extracts all text from the document
+    pub fn text(&self) -> String {
+        self.root.text()
+    }
+}
+
+impl<'a> From<&'a Soup> for SyncSoup {
+    fn from(soup: &'a Soup) -> SyncSoup {
+        SyncSoup {
+            root: SyncNode {
+                tree: Arc::new(Tree::from_handle(&soup.handle.document)),
+                id: Tree::document(),
+            },
+            quirks_mode: soup.handle.quirks_mode,
+            original_encoding: soup.original_encoding,
+            errors: soup.errors.clone(),
+        }
+    }
+}
+
+impl From<Soup> for SyncSoup {
+    fn from(soup: Soup) -> SyncSoup {
+        SyncSoup::from(&soup)
+    }
+}
+
+impl fmt::Debug for SyncSoup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text())
+    }
+}
+
+impl QueryBuilderExt<SyncNode> for SyncSoup {
+    fn get_handle(&self) -> SyncNode {
+        self.root.clone()
+    }
+}
+
+/// A node of a `SyncSoup`
+///
+/// Like a `Handle`, this keeps the whole document alive.
+#[derive(Clone)]
+pub struct SyncNode {
+    tree: Arc<Tree>,
+    id: NodeId,
+}
+
+impl SyncNode {
+    fn entry(&self) -> &Entry {
+        &self.tree[self.id]
+    }
+
+    fn relative(&self, id: Option<NodeId>) -> Option<SyncNode> {
+        id.map(|id| SyncNode {
+            tree: self.tree.clone(),
+            id,
+        })
+    }
+
+    /// The id of the node, which `SyncSoup::node` turns back into the node
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns an iterator over the node's children
+    pub fn children(&self) -> SyncChildIter {
+        SyncChildIter {
+            tree: self.tree.clone(),
+            next: self.entry().first_child,
+        }
+    }
+}
+
+impl fmt::Debug for SyncNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncNode")
+            .field("id", &self.id)
+            .field("name", &dom::name(self))
+            .finish()
+    }
+}
+
+impl Node for SyncNode {
+    type Children = SyncChildIter;
+
+    fn node_kind(&self) -> NodeKind {
+        match self.entry().data {
+            Data::Document => NodeKind::Document,
+            Data::Doctype {
+                ..
+            } => NodeKind::Doctype,
+            Data::Text(_) => NodeKind::Text,
+            Data::Comment(_) => NodeKind::Comment,
+            Data::ProcessingInstruction {
+                ..
+            } => NodeKind::ProcessingInstruction,
+            Data::Element {
+                ..
+            } => NodeKind::Element,
+        }
+    }
+
+    fn element_name(&self) -> Option<&QualName> {
+        match self.entry().data {
+            Data::Element {
+                ref name, ..
+            } => Some(name),
+            _ => None,
+        }
+    }
+
+    fn any_attribute(&self, f: &mut dyn FnMut(&QualName, &str) -> bool) -> bool {
+        match self.entry().data {
+            Data::Element {
+                ref attrs, ..
+            } => attrs.iter().any(|(name, value)| f(name, value)),
+            _ => false,
+        }
+    }
+
+    fn contents(&self) -> Option<Cow<'_, str>> {
+        match self.entry().data {
+            Data::Text(ref contents) | Data::Comment(ref contents) => Some(Cow::Borrowed(contents)),
+            Data::ProcessingInstruction {
+                ref contents, ..
+            } => Some(Cow::Borrowed(contents)),
+            _ => None,
+        }
+    }
+
+    fn child_nodes(&self) -> SyncChildIter {
+        self.children()
+    }
+
+    fn parent_node(&self) -> Option<SyncNode> {
+        self.relative(self.entry().parent)
+    }
+
+    fn same_node(&self, other: &SyncNode) -> bool {
+        Arc::ptr_eq(&self.tree, &other.tree) && self.id == other.id
+    }
+
+    fn next_sibling_node(&self) -> Option<SyncNode> {
+        self.relative(self.entry().next_sibling)
+    }
+
+    fn previous_sibling_node(&self) -> Option<SyncNode> {
+        self.relative(self.entry().previous_sibling)
+    }
+
+    fn last_child_node(&self) -> Option<SyncNode> {
+        self.relative(self.entry().last_child)
+    }
+
+    fn target(&self) -> Option<&str> {
+        match self.entry().data {
+            Data::ProcessingInstruction {
+                ref target, ..
+            } => Some(target),
+            _ => None,
+        }
+    }
+
+    fn node_id(&self) -> Option<usize> {
+        Some(self.id.index())
+    }
+}
+
+impl QueryBuilderExt<SyncNode> for SyncNode {
+    fn get_handle(&self) -> SyncNode {
+        self.clone()
+    }
+}
+
+/// Iterator over the children of a `SyncNode`
+pub struct SyncChildIter {
+    tree: Arc<Tree>,
+    next: Option<NodeId>,
+}
+
+impl Iterator for SyncChildIter {
+    type Item = SyncNode;
+
+    fn next(&mut self) -> Option<SyncNode> {
+        let id = self.next?;
+        self.next = self.tree[id].next_sibling;
+        Some(SyncNode {
+            tree: self.tree.clone(),
+            id,
+        })
+    }
+}
+
+impl fmt::Debug for SyncChildIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncChildIter")
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyncNode, SyncSoup};
+    use crate::{dom::Node, QueryBuilderExt, Soup};
+    use std::thread;
+
+    const HTML: &str = r#"<!DOCTYPE html><p class="a b">One <b>two</b></p><!--c--><template><i>t</i></template>"#;
+
+    fn is_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn queries() {
+        is_send_sync::<SyncSoup>();
+        is_send_sync::<SyncNode>();
+        let soup = SyncSoup::new(HTML);
+        let soup = thread::spawn(move || soup).join().expect("Thread panicked");
+        let p = soup.class("b").find().expect("Couldn't find class 'b'");
+        assert_eq!(p.name(), "p");
+        assert!(p.has_class("a"));
+        assert_eq!(p.text(), "One two");
+        let b = p.tag("b").find().expect("Couldn't find tag 'b'");
+        assert_eq!(b.parent().map(|parent| parent.name().to_string()), Some("p".to_string()));
+        assert_eq!(b.previous_sibling().map(|text| text.text()), Some("One ".to_string()));
+        assert_eq!(b.tag("template").find_next().map(|t| t.children().count()), Some(0));
+        assert_eq!(b.tag("p").find_previous().map(|p| p.get("class")), Some(Some("a b".to_string())));
+        assert_eq!(soup.string("two").find().map(|text| text.name().to_string()), Some("[text]".to_string()));
+        assert!(soup.tag("i").find().is_none());
+    }
+
+    #[test]
+    fn selectors_and_xpath() {
+        let html = r#"<ul lang="en"><li id="one">ONE</li><li class="x">TWO</li><!--c--></ul>"#;
+        let soup = Soup::new(html);
+        let sync = SyncSoup::new(html);
+        for selector in &["ul > li:not(.x)", "li:nth-child(2)", ":lang(en)", ":scope li", "li:has(+ li)"] {
+            assert_eq!(
+                soup.select(selector).expect("Invalid selector").map(|node| node.text()).collect::<Vec<_>>(),
+                sync.select(selector).expect("Invalid selector").map(|node| node.text()).collect::<Vec<_>>()
+            );
+        }
+        let ul = sync.select_one("ul").expect("Invalid selector").expect("Couldn't find ul");
+        assert_eq!(ul.select("ul").expect("Invalid selector").count(), 0);
+        let two = sync.xpath("//li[@class='x']").expect("Invalid XPath").into_handles();
+        assert_eq!(two.iter().map(|node| node.text()).collect::<Vec<_>>(), vec!["TWO".to_string()]);
+        assert_eq!(ul.xpath("count(li)").expect("Invalid XPath").number(), 2.0);
+        for expr in &["//li/@id | //li", "//li[2]/preceding::node()", "//comment()"] {
+            assert_eq!(
+                soup.xpath(expr).expect("Invalid XPath").nodes().map(|nodes| nodes.iter().map(|node| node.string_value()).collect::<Vec<_>>()),
+                sync.xpath(expr).expect("Invalid XPath").nodes().map(|nodes| nodes.iter().map(|node| node.string_value()).collect::<Vec<_>>())
+            );
+        }
+        let xml = SyncSoup::from(Soup::xml("<a><?pi data?></a>"));
+        assert_eq!(xml.xpath("//processing-instruction('pi')").expect("Invalid XPath").string(), "data");
+    }
+
+    #[test]
+    fn navigation() {
+        let html = r#"<ul><li id="one">ONE</li> <li id="two">TWO <b>!</b></li> <li>THREE</li></ul>"#;
+        let soup = Soup::new(html);
+        let sync = SyncSoup::new(html);
+        let two = soup.attr("id", "two").find().expect("Couldn't find id 'two'");
+        let sync_two = sync.attr("id", "two").find().expect("Couldn't find id 'two'");
+        assert_eq!(
+            two.next_elements().map(|node| node.name().to_string()).collect::<Vec<_>>(),
+            sync_two.next_elements().map(|node| node.name().to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            two.previous_siblings().map(|node| node.text()).collect::<Vec<_>>(),
+            sync_two.previous_siblings().map(|node| node.text()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            two.next_element_siblings().map(|node| node.text()).collect::<Vec<_>>(),
+            sync_two.next_element_siblings().map(|node| node.text()).collect::<Vec<_>>()
+        );
+        assert_eq!(two.attrs(), sync_two.attrs());
+        assert_eq!(sync_two.previous_element().map(|node| node.text()), Some(" ".to_string()));
+    }
+
+    #[test]
+    fn round_trip() {
+        use crate::node_ext::NodeExt;
+        let soup = Soup::new(HTML);
+        let sync = SyncSoup::from(&soup);
+        assert_eq!(sync.quirks_mode(), soup.quirks_mode());
+        assert_eq!(sync.errors(), soup.errors());
+        let back = sync.to_soup();
+        assert_eq!(back.get_handle().display(), soup.get_handle().display());
+        let template = back.tag("template").find().expect("Couldn't find tag 'template'");
+        assert_eq!(template.display(), "<template><i>t</i></template>");
+    }
+}
@@ -0,0 +1,484 @@
+//! Text extraction
+//!
+//! `NodeExt::text` joins every text node under a node, just as they appear in the source,
+//! including the contents of `script` & `style` elements. The methods modeled on
+//! BeautifulSoup's are better suited to text meant for people or search indexes:
+//! `NodeExt::strings` iterates over the text under a node one string at a time,
+//! `NodeExt::stripped_strings` does the same with surrounding whitespace removed & blank strings
+//! left out, and `NodeExt::get_text` joins the strings with a separator. By default they skip
+//! the contents of `script`, `style` & `template` elements and comments, which can be changed by
+//! passing a `TextOptions` to the `_with` variants.
+//!
+//! `NodeExt::rendered_text` & `Soup::to_plain_text` go further, laying the text out roughly
+//! the way a browser displays it: hidden elements like `head` & `script` are left out, block
+//! elements like `div`, `p` & `li` start new lines, with a blank line around paragraphs, `br`
+//! breaks the line, table rows go on lines of their own with their cells separated by tabs, and
+//! whitespace collapses everywhere but inside `pre`.
+//!
+//! ```rust
+//! # extern crate soup;
+//! # use soup::prelude::*;
+//! # use std::error::Error;
+//! use soup::text::TextOptions;
+//!
+//! # fn main() -> Result<(), Box<Error>> {
+//! let soup = Soup::new("<div><p>Some\n   <b>bold</b> text</p><script>var x;</script><p>More</p></div>");
+//! let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+//! assert_eq!(div.text(), "Some\n   bold textvar x;More");
+//! assert_eq!(div.get_text("|", true), "Some|bold|text|More");
+//! let options = TextOptions::new().collapse_whitespace(true).skip_scripts(false);
+//! assert_eq!(div.get_text_with("", &options), "Some bold textvar x;More");
+//! assert_eq!(div.rendered_text(0), "Some bold text\n\nMore");
+//! #   Ok(())
+//! # }
+//! ```
+use html5ever::rcdom::Handle;
+use std::fmt;
+
+use crate::dom::{Node, NodeKind};
+
+/// Options for extracting text with `NodeExt::get_text_with` & `NodeExt::strings_with`
+///
+/// By default the contents of `script`, `style` & `template` elements and comments are left
+/// out, and strings are returned as they are in the source. The elements are only skipped
+/// inside the node the text is extracted from, so the text of a `script` element itself can
+/// still be had.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextOptions {
+    skip_scripts: bool,
+    skip_styles: bool,
+    skip_templates: bool,
+    skip_comments: bool,
+    collapse_whitespace: bool,
+    strip: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> TextOptions {
+        TextOptions {
+            skip_scripts: true,
+            skip_styles: true,
+            skip_templates: true,
+            skip_comments: true,
+            collapse_whitespace: false,
+            strip: false,
+        }
+    }
+}
+
+impl TextOptions {
+    /// Creates the default options
+    pub fn new() -> TextOptions {
+        TextOptions::default()
+    }
+
+    /// Sets whether the contents of `script` elements are left out
+    pub fn skip_scripts(mut self, skip_scripts: bool) -> TextOptions {
+        self.skip_scripts = skip_scripts;
+        self
+    }
+
+    /// Sets whether the contents of `style` elements are left out
+    pub fn skip_styles(mut self, skip_styles: bool) -> TextOptions {
+        self.skip_styles = skip_styles;
+        self
+    }
+
+    /// Sets whether the contents of `template` elements are left out
+    ///
+    /// The parser keeps the contents of HTML templates out of the tree, so this only makes a
+    /// difference for documents parsed as XML or built by hand.
+    pub fn skip_templates(mut self, skip_templates: bool) -> TextOptions {
+        self.skip_templates = skip_templates;
+        self
+    }
+
+    /// Sets whether comments are left out, or included like text
+    pub fn skip_comments(mut self, skip_comments: bool) -> TextOptions {
+        self.skip_comments = skip_comments;
+        self
+    }
+
+    /// Sets whether each run of whitespace inside a string is replaced with a single space
+    pub fn collapse_whitespace(mut self, collapse_whitespace: bool) -> TextOptions {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    /// Sets whether whitespace is stripped from the start & end of each string, leaving out the
+    /// strings that were only whitespace
+    pub fn strip(mut self, strip: bool) -> TextOptions {
+        self.strip = strip;
+        self
+    }
+
+    fn skips<N: Node>(&self, element: &N) -> bool {
+        match element.element_name().map(|name| name.local.as_ref()) {
+            Some("script") => self.skip_scripts,
+            Some("style") => self.skip_styles,
+            Some("template") => self.skip_templates,
+            _ => false,
+        }
+    }
+
+    // Applies the whitespace options to a string, returning `None` if it should be left out
+    fn process(&self, text: &str) -> Option<String> {
+        let text = if self.collapse_whitespace {
+            collapse(text)
+        } else {
+            text.to_string()
+        };
+        if !self.strip {
+            return Some(text);
+        }
+        let stripped = text.trim();
+        if stripped.is_empty() {
+            None
+        } else {
+            Some(stripped.to_string())
+        }
+    }
+}
+
+// Replaces each run of whitespace with a single space
+pub(crate) fn collapse(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+// Elements that aren't displayed at all
+const HIDDEN: &[&str] = &[
+    "area", "base", "basefont", "datalist", "head", "link", "meta", "noembed", "noframes",
+    "noscript", "param", "rp", "script", "style", "template", "title",
+];
+
+// Elements displayed as blocks, which go on lines of their own
+const BLOCKS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "caption", "center", "dd", "details",
+    "dialog", "dir", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form",
+    "h1", "h2", "h3", "h4", "h5", "h6", "header", "hgroup", "hr", "html", "legend", "li",
+    "listing", "main", "menu", "nav", "ol", "p", "plaintext", "pre", "section", "summary",
+    "table", "tr", "ul", "xmp",
+];
+
+// Elements whose whitespace is kept as it is
+const PREFORMATTED: &[&str] = &["listing", "plaintext", "pre", "textarea", "xmp"];
+
+// What's left to do while rendering, kept on a stack so deep trees don't recurse
+enum Task<N> {
+    Node(N, bool),
+    Break(usize),
+    Tab,
+}
+
+// Lays the text of a node out like a browser would, wrapping lines longer than `width`
+// characters unless it is 0
+pub(crate) fn render<N: Node>(node: &N, width: usize) -> String {
+    let mut writer = Writer {
+        out: String::new(),
+        width,
+        column: 0,
+        pending_break: 0,
+        pending_space: false,
+    };
+    let mut tasks = vec![Task::Node(node.clone(), false)];
+    while let Some(task) = tasks.pop() {
+        let (node, preformatted) = match task {
+            Task::Node(node, preformatted) => (node, preformatted),
+            Task::Break(lines) => {
+                writer.request_break(lines);
+                continue;
+            },
+            Task::Tab => {
+                writer.tab();
+                continue;
+            },
+        };
+        let name = match node.node_kind() {
+            NodeKind::Text => {
+                if let Some(contents) = node.contents() {
+                    if preformatted {
+                        writer.preformatted(&contents);
+                    } else {
+                        writer.text(&contents);
+                    }
+                }
+                continue;
+            },
+            NodeKind::Document => "",
+            NodeKind::Element => node.element_name().map_or("", |name| name.local.as_ref()),
+            _ => continue,
+        };
+        if HIDDEN.contains(&name) {
+            continue;
+        }
+        if name == "br" {
+            writer.newline();
+            continue;
+        }
+        let lines = match name {
+            "p" => 2,
+            _ if BLOCKS.contains(&name) => 1,
+            _ => 0,
+        };
+        writer.request_break(lines);
+        tasks.push(Task::Break(lines));
+        let preformatted = preformatted || PREFORMATTED.contains(&name);
+        let mut children = vec![];
+        let mut cells = 0;
+        for child in node.child_nodes() {
+            let is_cell = name == "tr"
+                && child
+                    .element_name()
+                    .is_some_and(|name| matches!(name.local.as_ref(), "td" | "th"));
+            if is_cell {
+                if cells > 0 {
+                    children.push(Task::Tab);
+                }
+                cells += 1;
+            }
+            children.push(Task::Node(child, preformatted));
+        }
+        tasks.extend(children.into_iter().rev());
+    }
+    writer.out.truncate(writer.out.trim_end().len());
+    writer.out
+}
+
+fn is_html_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\x0C' | '\r')
+}
+
+// Writes out rendered text, collapsing whitespace & the line breaks that blocks ask for
+struct Writer {
+    out: String,
+    width: usize,
+    // The number of characters on the current line
+    column: usize,
+    // The number of line breaks that have to come before any more text
+    pending_break: usize,
+    // Whether collapsed whitespace has to come before any more text on this line
+    pending_space: bool,
+}
+
+impl Writer {
+    fn request_break(&mut self, lines: usize) {
+        if lines > 0 {
+            self.pending_break = self.pending_break.max(lines);
+            self.pending_space = false;
+        }
+    }
+
+    // Starts the lines that were asked for, counting the ones the output already ends with
+    fn flush_break(&mut self) {
+        if self.pending_break == 0 {
+            return;
+        }
+        if !self.out.is_empty() {
+            let trailing = self.out.len() - self.out.trim_end_matches('\n').len();
+            for _ in trailing..self.pending_break {
+                self.out.push('\n');
+            }
+            self.column = 0;
+        }
+        self.pending_break = 0;
+        self.pending_space = false;
+    }
+
+    fn text(&mut self, text: &str) {
+        for (idx, word) in text.split(is_html_whitespace).enumerate() {
+            if idx > 0 && self.column > 0 {
+                self.pending_space = true;
+            }
+            if !word.is_empty() {
+                self.word(word);
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str) {
+        self.flush_break();
+        let len = word.chars().count();
+        if self.pending_space && self.column > 0 {
+            if self.width > 0 && self.column + 1 + len > self.width {
+                self.out.push('\n');
+                self.column = 0;
+            } else {
+                self.out.push(' ');
+                self.column += 1;
+            }
+        }
+        self.out.push_str(word);
+        self.column += len;
+        self.pending_space = false;
+    }
+
+    fn preformatted(&mut self, text: &str) {
+        self.flush_break();
+        if self.pending_space && self.column > 0 {
+            self.out.push(' ');
+            self.column += 1;
+        }
+        for c in text.chars() {
+            self.out.push(c);
+            self.column = if c == '\n' { 0 } else { self.column + 1 };
+        }
+        self.pending_space = false;
+    }
+
+    fn newline(&mut self) {
+        self.flush_break();
+        self.out.push('\n');
+        self.column = 0;
+        self.pending_space = false;
+    }
+
+    fn tab(&mut self) {
+        self.flush_break();
+        self.out.push('\t');
+        self.column += 1;
+        self.pending_space = false;
+    }
+}
+
+/// Iterator over the strings under a node, in document order
+///
+/// See `NodeExt::strings`
+pub struct Strings<N: Node = Handle> {
+    // The node the strings are taken from, when it is a string itself
+    string: Option<N>,
+    stack: Vec<N::Children>,
+    options: TextOptions,
+}
+
+impl<N: Node> Strings<N> {
+    pub(crate) fn new(node: &N, options: &TextOptions) -> Strings<N> {
+        let string = match node.node_kind() {
+            NodeKind::Text | NodeKind::Comment => Some(node.clone()),
+            _ => None,
+        };
+        Strings {
+            string,
+            stack: vec![node.child_nodes()],
+            options: *options,
+        }
+    }
+
+    fn next_node(&mut self) -> Option<N> {
+        if let Some(string) = self.string.take() {
+            return Some(string);
+        }
+        loop {
+            let child = self.stack.last_mut()?.next();
+            match child {
+                Some(child) => return Some(child),
+                None => {
+                    self.stack.pop();
+                },
+            }
+        }
+    }
+}
+
+impl<N: Node> Iterator for Strings<N> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            let node = self.next_node()?;
+            let contents = match node.node_kind() {
+                NodeKind::Text => node.contents(),
+                NodeKind::Comment if !self.options.skip_comments => node.contents(),
+                NodeKind::Element if !self.options.skips(&node) => {
+                    self.stack.push(node.child_nodes());
+                    None
+                },
+                _ => None,
+            };
+            if let Some(string) = contents.and_then(|contents| self.options.process(&contents)) {
+                return Some(string);
+            }
+        }
+    }
+}
+
+impl<N: Node> fmt::Debug for Strings<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Strings")
+            .field("depth", &self.stack.len())
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextOptions;
+    use crate::prelude::*;
+
+    const HTML: &str = "<div>\n  <h1> Title </h1>\n  <!-- note -->\n  <style>p { }</style>\n  <p>One\n\ttwo</p>\n</div>";
+
+    #[test]
+    fn strings() {
+        let soup = Soup::new(HTML);
+        let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+        assert_eq!(
+            div.strings().collect::<Vec<_>>(),
+            vec!["\n  ", " Title ", "\n  ", "\n  ", "\n  ", "One\n\ttwo", "\n"]
+        );
+        assert_eq!(div.stripped_strings().collect::<Vec<_>>(), vec!["Title", "One\n\ttwo"]);
+        let options = TextOptions::new()
+            .skip_comments(false)
+            .skip_styles(false)
+            .collapse_whitespace(true)
+            .strip(true);
+        assert_eq!(
+            div.strings_with(&options).collect::<Vec<_>>(),
+            vec!["Title", "note", "p { }", "One two"]
+        );
+        let style = soup.tag("style").find().expect("Couldn't find tag 'style'");
+        assert_eq!(style.get_text("", false), "p { }");
+    }
+
+    #[test]
+    fn get_text() {
+        let soup = Soup::new(HTML);
+        assert_eq!(soup.get_text(" ", true), "Title One\n\ttwo");
+        let options = TextOptions::new().collapse_whitespace(true);
+        let h1 = soup.tag("h1").find().expect("Couldn't find tag 'h1'");
+        assert_eq!(h1.get_text_with("", &options), " Title ");
+        let text = h1.children().next().expect("Couldn't find text");
+        assert_eq!(text.get_text("", true), "Title");
+    }
+
+    #[test]
+    fn rendered_text() {
+        let soup = Soup::new(concat!(
+            "<head><title>T</title></head><body>\n",
+            "<h1>  Title </h1>\n<p>Some <b>bold</b>\n  text.<br>Next line</p>",
+            "<ul><li>One</li><li>Two <span>parts</span></li></ul>",
+            "<table><tr><th>A</th> <th>B</th></tr><tr><td>1</td><td>2</td></tr></table>",
+            "<pre>  keep\n    this</pre><div>after<script>x()</script></div></body>",
+        ));
+        assert_eq!(
+            soup.to_plain_text(),
+            "Title\n\nSome bold text.\nNext line\n\nOne\nTwo parts\nA\tB\n1\t2\n  keep\n    this\nafter"
+        );
+        let soup = Soup::new("<p>one two three four five</p><p>a<br><br>b</p>");
+        assert_eq!(soup.get_handle().rendered_text(9), "one two\nthree\nfour five\n\na\n\nb");
+        let sync = crate::SyncSoup::new("<div>inline <i>text</i></div>");
+        assert_eq!(sync.tag("div").find().map(|div| crate::dom::Node::rendered_text(&div, 0)), Some("inline text".to_string()));
+    }
+}
@@ -0,0 +1,340 @@
+use html5ever::{
+    rcdom::{Handle, Node, NodeData},
+    tendril::StrTendril,
+    LocalName,
+    Namespace,
+    QualName,
+};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use crate::{index, node_ext::NodeExt};
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+/// Adds methods for restructuring the tree to the `Handle` type
+///
+/// These keep each node's `parent` and its parent's `children` in sync, the same way
+/// `html5ever`'s `RcDom` does while parsing. A node that is inserted somewhere is first removed
+/// from wherever it was before, so nodes can be moved around freely, including between
+/// documents.
+pub trait TreeExt {
+    /// Creates a new element named `name`, that isn't part of any document yet
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate html5ever;
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// use html5ever::rcdom::Handle;
+    ///
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<ul><li>ONE</li></ul>"#);
+    /// let ul = soup.tag("ul").find().expect("Couldn't find tag 'ul'");
+    /// let li = Handle::new_element("li");
+    /// li.append(Handle::new_text("TWO"));
+    /// ul.append(li);
+    /// assert_eq!(ul.display(), "<ul><li>ONE</li><li>TWO</li></ul>");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn new_element(name: &str) -> Handle;
+
+    /// Creates a new text node, that isn't part of any document yet
+    fn new_text(text: &str) -> Handle;
+
+    /// Creates a new comment node, that isn't part of any document yet
+    fn new_comment(text: &str) -> Handle;
+
+    /// Removes the node from its parent, leaving it and its descendants intact
+    ///
+    /// Does nothing if the node has no parent
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<div><p>Text</p><div class="ad"><b>BUY</b></div></div>"#);
+    /// let ad = soup.class("ad").find().expect("Couldn't find ad");
+    /// ad.extract();
+    /// assert!(soup.class("ad").find().is_none());
+    /// assert!(ad.parent().is_none());
+    /// assert_eq!(ad.display(), r#"<div class="ad"><b>BUY</b></div>"#);
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn extract(&self);
+
+    /// Removes the node from its parent and tears down the subtree below it
+    ///
+    /// Afterwards the node has no children, and none of its former descendants have a parent
+    /// or children
+    fn decompose(&self);
+
+    /// Puts `node` in this node's place, and removes this node from its parent
+    ///
+    /// # Panics
+    ///
+    /// Panics if this node has no parent, or if `node` is an ancestor of this node
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate html5ever;
+    /// # extern crate soup;
+    /// # use std::error::Error;
+    /// # use soup::prelude::*;
+    /// use html5ever::rcdom::Handle;
+    ///
+    /// # fn main() -> Result<(), Box<Error>> {
+    /// let soup = Soup::new(r#"<p>Some <b>bold</b> text</p>"#);
+    /// let b = soup.tag("b").find().expect("Couldn't find tag 'b'");
+    /// let strong = Handle::new_element("strong");
+    /// b.replace_with(strong.clone());
+    /// for child in b.children().collect::<Vec<_>>() {
+    ///     strong.append(child);
+    /// }
+    /// let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+    /// assert_eq!(p.display(), "<p>Some <strong>bold</strong> text</p>");
+    /// #   Ok(())
+    /// # }
+    /// ```
+    fn replace_with(&self, node: Handle);
+
+    /// Inserts `node` right before this node, as a sibling
+    ///
+    /// # Panics
+    ///
+    /// Panics if this node has no parent, or if `node` is an ancestor of this node
+    fn insert_before(&self, node: Handle);
+
+    /// Inserts `node` right after this node, as a sibling
+    ///
+    /// # Panics
+    ///
+    /// Panics if this node has no parent, or if `node` is an ancestor of this node
+    fn insert_after(&self, node: Handle);
+
+    /// Adds `node` as the last child of this node
+    ///
+    /// # Panics
+    ///
+    /// Panics if `node` is this node or one of its ancestors
+    fn append(&self, node: Handle);
+
+    /// Adds `node` as the first child of this node
+    ///
+    /// # Panics
+    ///
+    /// Panics if `node` is this node or one of its ancestors
+    fn prepend(&self, node: Handle);
+}
+
+impl TreeExt for Handle {
+    fn new_element(name: &str) -> Handle {
+        let template_contents = if name == "template" {
+            Some(new_node(NodeData::Document))
+        } else {
+            None
+        };
+        new_node(NodeData::Element {
+            name: QualName::new(None, Namespace::from(HTML_NAMESPACE), LocalName::from(name)),
+            attrs: RefCell::new(vec![]),
+            template_contents,
+            mathml_annotation_xml_integration_point: false,
+        })
+    }
+
+    fn new_text(text: &str) -> Handle {
+        new_node(NodeData::Text {
+            contents: RefCell::new(StrTendril::from_slice(text)),
+        })
+    }
+
+    fn new_comment(text: &str) -> Handle {
+        new_node(NodeData::Comment {
+            contents: StrTendril::from_slice(text),
+        })
+    }
+
+    fn extract(&self) {
+        detach(self);
+    }
+
+    fn decompose(&self) {
+        detach(self);
+        let mut stack = vec![self.clone()];
+        while let Some(node) = stack.pop() {
+            let children = node.children.replace(vec![]);
+            for child in children {
+                child.parent.set(None);
+                stack.push(child);
+            }
+        }
+    }
+
+    fn replace_with(&self, node: Handle) {
+        if Rc::ptr_eq(self, &node) {
+            return;
+        }
+        self.insert_before(node);
+        detach(self);
+    }
+
+    fn insert_before(&self, node: Handle) {
+        insert_sibling(self, node, 0);
+    }
+
+    fn insert_after(&self, node: Handle) {
+        insert_sibling(self, node, 1);
+    }
+
+    fn append(&self, node: Handle) {
+        check_insert(self, &node);
+        detach(&node);
+        let idx = self.children.borrow().len();
+        insert_child(self, idx, node);
+    }
+
+    fn prepend(&self, node: Handle) {
+        check_insert(self, &node);
+        detach(&node);
+        insert_child(self, 0, node);
+    }
+}
+
+fn new_node(data: NodeData) -> Handle {
+    Rc::new(Node {
+        parent: Cell::new(None),
+        children: RefCell::new(vec![]),
+        data,
+    })
+}
+
+// The position of `node` in its parent's children
+fn position(parent: &Handle, node: &Handle) -> Option<usize> {
+    parent
+        .children
+        .borrow()
+        .iter()
+        .position(|child| Rc::ptr_eq(child, node))
+}
+
+// Removes `node` from its parent's children and clears its parent
+fn detach(node: &Handle) {
+    if let Some(parent) = node.parent() {
+        index::changed();
+        if let Some(idx) = position(&parent, node) {
+            parent.children.borrow_mut().remove(idx);
+        }
+    }
+    node.parent.set(None);
+}
+
+fn insert_child(parent: &Handle, idx: usize, node: Handle) {
+    index::changed();
+    node.parent.set(Some(Rc::downgrade(parent)));
+    parent.children.borrow_mut().insert(idx, node);
+}
+
+// Panics if inserting `node` under `parent` would create a cycle
+fn check_insert(parent: &Handle, node: &Handle) {
+    let mut ancestor = Some(parent.clone());
+    while let Some(current) = ancestor {
+        if Rc::ptr_eq(&current, node) {
+            panic!("Cannot insert a node into itself or one of its descendants");
+        }
+        ancestor = current.parent();
+    }
+}
+
+// Inserts `node` next to `sibling`, `offset` places after it
+fn insert_sibling(sibling: &Handle, node: Handle, offset: usize) {
+    if Rc::ptr_eq(sibling, &node) {
+        return;
+    }
+    let parent = sibling
+        .parent()
+        .expect("Cannot insert a sibling next to a node without a parent");
+    check_insert(&parent, &node);
+    detach(&node);
+    let idx = position(&parent, sibling).expect("Node is missing from its parent's children");
+    insert_child(&parent, idx + offset, node);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use html5ever::rcdom::Handle;
+    use std::rc::Rc;
+
+    fn check_parents(node: &Handle) {
+        for child in node.children.borrow().iter() {
+            let parent = child.parent().expect("Child has no parent");
+            assert!(Rc::ptr_eq(&parent, node));
+            check_parents(child);
+        }
+    }
+
+    #[test]
+    fn restructure() {
+        let soup = Soup::new(r#"<ul><li id="a">A</li><li id="b">B</li><li id="c">C</li></ul>"#);
+        let ul = soup.tag("ul").find().expect("Couldn't find tag 'ul'");
+        let a = soup.attr("id", "a").find().expect("Couldn't find a");
+        let c = soup.attr("id", "c").find().expect("Couldn't find c");
+        a.insert_after(c.clone());
+        assert_eq!(ul.text(), "ACB");
+        c.insert_before(Handle::new_comment("x"));
+        assert_eq!(ul.children().map(|li| li.name().to_string()).collect::<Vec<_>>(), vec!["li", "[comment]", "li", "li"]);
+        ul.prepend(c.clone());
+        ul.append(a.clone());
+        assert_eq!(ul.text(), "CBA");
+        a.replace_with(Handle::new_text("D"));
+        assert_eq!(ul.display(), r#"<ul><li id="c">C</li><!--x--><li id="b">B</li>D</ul>"#);
+        assert!(a.parent().is_none());
+        c.append(a.clone());
+        assert_eq!(ul.text(), "CABD");
+        check_parents(&soup.get_handle());
+    }
+
+    #[test]
+    fn extract_and_decompose() {
+        let soup = Soup::new(r#"<div><p>Keep</p><aside><b>Ad</b></aside></div>"#);
+        let aside = soup.tag("aside").find().expect("Couldn't find tag 'aside'");
+        let b = soup.tag("b").find().expect("Couldn't find tag 'b'");
+        aside.extract();
+        assert!(aside.parent().is_none());
+        assert_eq!(aside.display(), "<aside><b>Ad</b></aside>");
+        assert!(soup.tag("aside").find().is_none());
+        aside.decompose();
+        assert!(b.parent().is_none());
+        assert_eq!(aside.children().count(), 0);
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        p.decompose();
+        assert_eq!(soup.tag("div").find().expect("Couldn't find tag 'div'").display(), "<div></div>");
+        check_parents(&soup.get_handle());
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_into_descendant() {
+        let soup = Soup::new(r#"<div><p>Text</p></div>"#);
+        let div = soup.tag("div").find().expect("Couldn't find tag 'div'");
+        let p = soup.tag("p").find().expect("Couldn't find tag 'p'");
+        p.append(div);
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_next_to_orphan() {
+        let orphan = Handle::new_element("div");
+        orphan.insert_after(Handle::new_element("p"));
+    }
+}
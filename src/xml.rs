@@ -0,0 +1,578 @@
+use html5ever::{
+    rcdom::Handle,
+    tendril::StrTendril,
+    tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeSink as HtmlTreeSink},
+    Attribute,
+    LocalName,
+    Namespace,
+    Prefix,
+    QualName,
+};
+use std::{borrow::Cow, collections::HashMap, mem, rc::Rc};
+use xml5ever::{
+    buffer_queue::BufferQueue,
+    tokenizer::{
+        states::XmlState,
+        CharacterTokens,
+        EmptyTag,
+        PIToken,
+        ParseError,
+        StartTag,
+        Tag,
+        TagToken,
+        Token,
+        TokenSink,
+        XmlTokenizer,
+        XmlTokenizerOpts,
+    },
+    tree_builder::{self, TreeSink, XmlTreeBuilder, XmlTreeBuilderOpts},
+    ExpandedName,
+};
+
+use crate::{
+    position::{self, Columns, SourcePosition},
+    sink::{Pending, Sink},
+};
+
+const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+// Entities declared in the document are left out if they'd expand to more than this, so that
+// entities built out of other entities can't blow up in size
+const MAX_ENTITY_LEN: usize = 1 << 16;
+
+// Parses an XML document with xml5ever
+//
+// The parser is forgiving, the same way the HTML one is: problems are reported as parse errors
+// & worked around instead of stopping the parse. xml5ever doesn't understand the internal
+// subset of a doctype, so the general entities declared there are picked out beforehand &
+// expanded as the document is fed to the tokenizer.
+pub(crate) fn parse(xml: &str, positions: bool) -> Sink {
+    let xml = xml.trim_start_matches('\u{feff}');
+    let (source, entities) = match internal_subset(xml) {
+        Some((start, end)) => (blank(xml, start, end), declared_entities(&xml[start + 1..end - 1])),
+        None => (Cow::Borrowed(xml), HashMap::new()),
+    };
+    let tracker = Tracker {
+        inner: XmlTreeBuilder::new(XmlSink::default(), XmlTreeBuilderOpts::default()),
+        positions,
+        start: SourcePosition::start(),
+        emitted: false,
+    };
+    let opts = XmlTokenizerOpts {
+        discard_bom: false,
+        ..XmlTokenizerOpts::default()
+    };
+    let mut tokenizer = XmlTokenizer::new(tracker, opts);
+    let mut input = BufferQueue::new();
+    let mut columns = Columns::new(&source);
+    let mut offset = 0;
+    let mut line = 1;
+    let mut after_cr = false;
+    for (text, replacement) in pieces(&source, &entities) {
+        // xml5ever doesn't keep track of lines, so the input is fed a line at a time to know
+        // the line each error is on, & split at tags as well to know where each token starts
+        // when positions are being recorded. Entities are fed all at once, but count as the
+        // text of their reference.
+        let chunks = match replacement {
+            Some(_) => vec![text],
+            None if positions => position::chunks(text).flat_map(|chunk| chunk.split_inclusive(['\n', '\r'])).collect(),
+            None => text.split_inclusive(['\n', '\r']).collect(),
+        };
+        for chunk in chunks {
+            tokenizer.sink.sink().set_current_line(line);
+            if positions && tokenizer.sink.emitted {
+                tokenizer.sink.start = SourcePosition::new(line, columns.at(offset));
+                tokenizer.sink.emitted = false;
+            }
+            if chunk.ends_with('\r') || (chunk.ends_with('\n') && !(after_cr && chunk == "\n")) {
+                line += 1;
+            }
+            after_cr = chunk.ends_with('\r');
+            offset += chunk.len();
+            feed(&mut tokenizer, &mut input, replacement.unwrap_or(chunk));
+        }
+    }
+    tokenizer.end();
+    tokenizer.sink.inner.sink.sink
+}
+
+fn feed(tokenizer: &mut XmlTokenizer<Tracker>, input: &mut BufferQueue, text: &str) {
+    input.push_back(StrTendril::from_slice(text));
+    tokenizer.feed(input);
+}
+
+// Finds the internal subset of the doctype, if the document has one, returning where its
+// opening `[` starts & where its closing `]` ends
+fn internal_subset(xml: &str) -> Option<(usize, usize)> {
+    // The doctype can only come after the XML declaration, comments & processing instructions
+    let mut pos = 0;
+    loop {
+        let rest = xml[pos..].trim_start();
+        pos = xml.len() - rest.len();
+        if rest.starts_with("<?") {
+            pos += rest.find("?>")? + 2;
+        } else if rest.starts_with("<!--") {
+            pos += rest.find("-->")? + 3;
+        } else if rest.starts_with("<!DOCTYPE") {
+            break;
+        } else {
+            return None;
+        }
+    }
+    let mut quote = None;
+    let mut start = None;
+    for (idx, c) in xml[pos..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => {},
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '[') => {
+                start = Some(pos + idx);
+                break;
+            },
+            (None, '>') => return None,
+            _ => {},
+        }
+    }
+    let start = start?;
+    let mut pos = start + 1;
+    loop {
+        let rest = xml[pos..].trim_start();
+        pos = xml.len() - rest.len();
+        if rest.starts_with(']') {
+            return Some((start, pos + 1));
+        } else if rest.starts_with("<!--") {
+            pos += rest.find("-->")? + 3;
+        } else if rest.starts_with('<') {
+            pos += declaration_len(rest)?;
+        } else {
+            // Parameter entity references, or anything else that isn't a declaration
+            pos += rest.find(|c: char| c.is_whitespace() || c == ']' || c == '<')?.max(1);
+        }
+    }
+}
+
+// How long the markup declaration at the start of `decl` is, including its closing `>`
+fn declaration_len(decl: &str) -> Option<usize> {
+    let mut quote = None;
+    for (idx, c) in decl.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => {},
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(idx + 1),
+            _ => {},
+        }
+    }
+    None
+}
+
+// Replaces the internal subset between `start` & `end` with spaces, keeping its line breaks so
+// that lines & columns after it stay the same
+fn blank(xml: &str, start: usize, end: usize) -> Cow<'_, str> {
+    let subset = xml[start..end]
+        .chars()
+        .map(|c| if c == '\r' || c == '\n' { c } else { ' ' })
+        .collect::<String>();
+    Cow::Owned(format!("{}{}{}", &xml[..start], subset, &xml[end..]))
+}
+
+// The general entities declared in an internal subset, with any references to entities
+// declared before them expanded. Like any XML parser, the first declaration of an entity is
+// the one that counts, & the predefined entities can't be changed.
+fn declared_entities(subset: &str) -> HashMap<String, String> {
+    let mut entities = HashMap::new();
+    let mut rest = subset;
+    while let Some(idx) = rest.find('<') {
+        rest = &rest[idx..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let len = match declaration_len(rest) {
+            Some(len) => len,
+            None => break,
+        };
+        let decl = &rest[..len - 1];
+        rest = &rest[len..];
+        if !decl.starts_with("<!ENTITY") {
+            continue;
+        }
+        let decl = decl["<!ENTITY".len()..].trim_start();
+        let name_len = decl.find(char::is_whitespace).unwrap_or(decl.len());
+        let (name, value) = (&decl[..name_len], decl[name_len..].trim());
+        // Parameter entities start with `%`, & external entities have an ID instead of a value
+        let quote = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => continue,
+        };
+        if name == "%" || matches!(name, "lt" | "gt" | "amp" | "quot" | "apos") || entities.contains_key(name) {
+            continue;
+        }
+        let value = match value[1..].find(quote) {
+            Some(end) => &value[1..=end],
+            None => continue,
+        };
+        let value = pieces(value, &entities)
+            .map(|(text, replacement)| replacement.unwrap_or(text))
+            .collect::<String>();
+        if value.len() <= MAX_ENTITY_LEN {
+            entities.insert(name.to_string(), value);
+        }
+    }
+    entities
+}
+
+// Splits `xml` up around references to `entities`, pairing each piece with what it's replaced
+// by. References in comments, CDATA sections & processing instructions are left alone.
+fn pieces<'a>(xml: &'a str, entities: &'a HashMap<String, String>) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+    let mut rest = xml;
+    let mut pending = None;
+    ::std::iter::from_fn(move || {
+        if let Some(piece) = pending.take() {
+            return Some(piece);
+        }
+        if rest.is_empty() {
+            return None;
+        }
+        if entities.is_empty() {
+            return Some((mem::take(&mut rest), None));
+        }
+        let mut pos = 0;
+        while let Some(idx) = rest[pos..].find(['<', '&']) {
+            let at = pos + idx;
+            let tail = &rest[at..];
+            let skip = [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>")]
+                .iter()
+                .find(|(open, _)| tail.starts_with(open))
+                .map(|(open, close)| tail[open.len()..].find(close).map_or(tail.len(), |end| open.len() + end + close.len()));
+            if let Some(skip) = skip {
+                pos = at + skip;
+                continue;
+            }
+            let reference = tail[1..]
+                .find(';')
+                .filter(|_| tail.starts_with('&'))
+                .and_then(|end| entities.get(&tail[1..=end]).map(|value| (end + 2, value)));
+            if let Some((len, value)) = reference {
+                let (text, after) = rest.split_at(at);
+                pending = Some((&after[..len], Some(value.as_str())));
+                rest = &after[len..];
+                return Some((text, None));
+            }
+            pos = at + 1;
+        }
+        Some((mem::take(&mut rest), None))
+    })
+}
+
+// A node, along with its name in xml5ever's own types if it's an element, so the tree builder
+// can borrow it
+#[derive(Clone)]
+struct XmlHandle {
+    node: Handle,
+    name: Option<xml5ever::QualName>,
+}
+
+impl XmlHandle {
+    fn new(node: Handle) -> XmlHandle {
+        XmlHandle {
+            node,
+            name: None,
+        }
+    }
+}
+
+// xml5ever builds on a different version of markup5ever than html5ever, so this converts
+// between the two & hands everything on to the `Sink` the HTML parser uses
+#[derive(Default)]
+struct XmlSink {
+    sink: Sink,
+    // The namespace declarations of the tag being processed, which the tree builder drops
+    declarations: Vec<Attribute>,
+}
+
+fn qual_name(name: &xml5ever::QualName) -> QualName {
+    QualName::new(
+        name.prefix.as_ref().map(|prefix| Prefix::from(&**prefix)),
+        Namespace::from(&*name.ns),
+        LocalName::from(&*name.local),
+    )
+}
+
+fn attribute(attr: xml5ever::Attribute) -> Attribute {
+    Attribute {
+        name: qual_name(&attr.name),
+        value: attr.value,
+    }
+}
+
+fn node_or_text(child: tree_builder::NodeOrText<XmlHandle>) -> NodeOrText<Handle> {
+    match child {
+        tree_builder::NodeOrText::AppendNode(handle) => NodeOrText::AppendNode(handle.node),
+        tree_builder::NodeOrText::AppendText(text) => NodeOrText::AppendText(text),
+    }
+}
+
+// The `xmlns` attributes of `tag`, named the way the HTML parser names them
+fn declarations(tag: &Tag) -> Vec<Attribute> {
+    tag.attrs
+        .iter()
+        .filter_map(|attr| {
+            let name = match attr.name.prefix {
+                Some(ref prefix) if &**prefix == "xmlns" => QualName::new(
+                    Some(Prefix::from("xmlns")),
+                    Namespace::from(XMLNS_NAMESPACE),
+                    LocalName::from(&*attr.name.local),
+                ),
+                None if &*attr.name.local == "xmlns" => {
+                    QualName::new(None, Namespace::from(XMLNS_NAMESPACE), LocalName::from("xmlns"))
+                },
+                _ => return None,
+            };
+            Some(Attribute {
+                name,
+                value: attr.value.clone(),
+            })
+        })
+        .collect()
+}
+
+impl TreeSink for XmlSink {
+    type Handle = XmlHandle;
+    type Output = Self;
+
+    fn finish(self) -> Self {
+        self
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        self.sink.parse_error(msg)
+    }
+
+    fn get_document(&mut self) -> XmlHandle {
+        XmlHandle::new(self.sink.get_document())
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a XmlHandle) -> ExpandedName<'a> {
+        target.name.as_ref().expect("not an element").expanded()
+    }
+
+    fn create_element(
+        &mut self,
+        name: xml5ever::QualName,
+        attrs: Vec<xml5ever::Attribute>,
+        flags: xml5ever::interface::ElementFlags,
+    ) -> XmlHandle {
+        let mut element_flags = ElementFlags::default();
+        element_flags.template = flags.template;
+        element_flags.mathml_annotation_xml_integration_point = flags.mathml_annotation_xml_integration_point;
+        let mut all_attrs = mem::take(&mut self.declarations);
+        all_attrs.extend(attrs.into_iter().map(attribute));
+        let node = self.sink.create_element(qual_name(&name), all_attrs, element_flags);
+        XmlHandle {
+            node,
+            name: Some(name),
+        }
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> XmlHandle {
+        XmlHandle::new(self.sink.create_comment(text))
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> XmlHandle {
+        XmlHandle::new(self.sink.create_pi(target, data))
+    }
+
+    fn append(&mut self, parent: &XmlHandle, child: tree_builder::NodeOrText<XmlHandle>) {
+        self.sink.append(&parent.node, node_or_text(child))
+    }
+
+    fn append_based_on_parent_node(
+        &mut self,
+        element: &XmlHandle,
+        prev_element: &XmlHandle,
+        child: tree_builder::NodeOrText<XmlHandle>,
+    ) {
+        self.sink.append_based_on_parent_node(&element.node, &prev_element.node, node_or_text(child))
+    }
+
+    fn append_doctype_to_document(
+        &mut self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        self.sink.append_doctype_to_document(name, public_id, system_id)
+    }
+
+    fn get_template_contents(&mut self, target: &XmlHandle) -> XmlHandle {
+        XmlHandle::new(self.sink.get_template_contents(&target.node))
+    }
+
+    fn same_node(&self, x: &XmlHandle, y: &XmlHandle) -> bool {
+        Rc::ptr_eq(&x.node, &y.node)
+    }
+
+    // XML documents are never in quirks mode
+    fn set_quirks_mode(&mut self, _mode: xml5ever::interface::QuirksMode) {
+        self.sink.set_quirks_mode(QuirksMode::NoQuirks)
+    }
+
+    fn append_before_sibling(&mut self, sibling: &XmlHandle, new_node: tree_builder::NodeOrText<XmlHandle>) {
+        self.sink.append_before_sibling(&sibling.node, node_or_text(new_node))
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &XmlHandle, attrs: Vec<xml5ever::Attribute>) {
+        self.sink.add_attrs_if_missing(&target.node, attrs.into_iter().map(attribute).collect())
+    }
+
+    fn remove_from_parent(&mut self, target: &XmlHandle) {
+        self.sink.remove_from_parent(&target.node)
+    }
+
+    fn reparent_children(&mut self, node: &XmlHandle, new_parent: &XmlHandle) {
+        self.sink.reparent_children(&node.node, &new_parent.node)
+    }
+}
+
+// Sits between the tokenizer & the tree builder, dropping the XML declaration & telling the
+// sink where each token started when source positions are being recorded
+struct Tracker {
+    inner: XmlTreeBuilder<XmlHandle, XmlSink>,
+    positions: bool,
+    // Where the token being read started
+    start: SourcePosition,
+    // Whether any tokens have been emitted since `start` was set
+    emitted: bool,
+}
+
+impl Tracker {
+    fn sink(&mut self) -> &mut Sink {
+        &mut self.inner.sink.sink
+    }
+}
+
+impl TokenSink for Tracker {
+    fn process_token(&mut self, token: Token) {
+        // Errors are reported in the middle of tokens, so they don't count as the end of one
+        if let ParseError(_) = token {
+            return self.inner.process_token(token);
+        }
+        self.emitted = true;
+        let start = self.start;
+        match token {
+            // The XML declaration looks like a processing instruction, but isn't one
+            PIToken(ref pi) if &*pi.target == "xml" => return,
+            TagToken(ref tag) if tag.kind == StartTag || tag.kind == EmptyTag => {
+                self.inner.sink.declarations = declarations(tag);
+                if self.positions {
+                    self.sink().pending = Some(Pending::Element(LocalName::from(&*tag.name.local), start));
+                }
+            },
+            CharacterTokens(_) if self.positions => self.sink().pending = Some(Pending::Text(start)),
+            _ => {},
+        }
+        self.inner.process_token(token);
+        self.sink().pending = None;
+        self.inner.sink.declarations.clear();
+    }
+
+    fn end(&mut self) {
+        self.inner.end()
+    }
+
+    fn query_state_change(&mut self) -> Option<XmlState> {
+        self.inner.query_state_change()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE feed>
+<?xml-stylesheet href="feed.xsl"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+  <title type="text">Tom &amp; Jerry&#x27;s &lt;Feed&gt;</title>
+  <entry xml:lang="en">
+    <Link href="/a"/>
+    <summary><![CDATA[<b>not markup</b>]]></summary>
+    <media:thumbnail url="a.jpg" />
+    <!-- note -->
+  </entry>
+</feed>"#;
+
+    #[test]
+    fn parse_xml() {
+        let soup = Soup::xml(ATOM);
+        assert!(soup.errors().is_empty(), "{:?}", soup.errors());
+        let names = soup.children().map(|node| node.name().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["[doctype]", "[processing-instruction]", "feed"]);
+        let title = soup.tag("title").find().expect("Couldn't find tag 'title'");
+        assert_eq!(title.text(), "Tom & Jerry's <Feed>");
+        let link = soup.tag("Link").find().expect("Couldn't find tag 'Link'");
+        assert_eq!(link.get("href"), Some("/a".to_string()));
+        assert!(soup.tag("link").find().is_none());
+        let summary = soup.tag("summary").find().expect("Couldn't find tag 'summary'");
+        assert_eq!(summary.text(), "<b>not markup</b>");
+        assert_eq!(summary.children().count(), 1);
+        let thumbnail = soup.tag("media:thumbnail").find().expect("Couldn't find thumbnail");
+        assert_eq!(thumbnail.get("url"), Some("a.jpg".to_string()));
+        assert!(soup.tag("thumbnail").find().is_some());
+        let entry = soup.attr("xml:lang", "en").find().expect("Couldn't find entry");
+        assert_eq!(entry.get("xml:lang"), Some("en".to_string()));
+        assert_eq!(soup.attr_name("lang").find().map(|node| node.name().to_string()), Some("entry".to_string()));
+        let feed = soup.tag("feed").find().expect("Couldn't find tag 'feed'");
+        match feed.data {
+            html5ever::rcdom::NodeData::Element { ref name, .. } => {
+                assert_eq!(&*name.ns, "http://www.w3.org/2005/Atom");
+            },
+            _ => unreachable!(),
+        }
+        assert_eq!(feed.get("xmlns:media"), Some("http://search.yahoo.com/mrss/".to_string()));
+    }
+
+    #[test]
+    fn malformed_xml() {
+        let soup = Soup::builder()
+            .drop_whitespace(true)
+            .parse_xml("<a>\n  <b>one &bogus; &#0;</b>\n  <d:e/>\n</a><a/>");
+        let lines = soup.errors().iter().map(|err| err.line()).collect::<Vec<_>>();
+        assert_eq!(lines, vec![Some(2), Some(2), Some(3), Some(4)]);
+        let a = soup.tag("a").find().expect("Couldn't find tag 'a'");
+        assert_eq!(a.display(), "<a><b>one &amp;bogus; \u{fffd}</b><d:e></d:e></a>");
+    }
+
+    #[test]
+    fn internal_entities() {
+        let soup = Soup::xml(
+            r#"<!DOCTYPE doc [
+  <!-- "quoted" ] -->
+  <!ENTITY who "world">
+  <!ENTITY greeting "hello &who;">
+  <!ENTITY who "ignored">
+  <!ENTITY ext SYSTEM "ext.xml">
+]>
+<doc title="&greeting;">&greeting;<!-- &who; --><![CDATA[&who;]]></doc>"#,
+        );
+        assert!(soup.errors().is_empty(), "{:?}", soup.errors());
+        let names = soup.children().map(|node| node.name().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["[doctype]", "doc"]);
+        let doc = soup.tag("doc").find().expect("Couldn't find tag 'doc'");
+        assert_eq!(doc.get("title"), Some("hello world".to_string()));
+        assert_eq!(doc.text(), "hello world&who;");
+    }
+
+    #[test]
+    fn xml_positions() {
+        let soup = Soup::builder().source_positions(true).parse_xml("<a>\r\n  <b>text</b></a>");
+        let b = soup.tag("b").find().expect("Couldn't find tag 'b'");
+        let position = b.source_position(&soup).expect("No position recorded");
+        assert_eq!((position.line(), position.column()), (2, 3));
+        let text = b.children().next().expect("Couldn't find text");
+        let position = text.source_position(&soup).expect("No position recorded");
+        assert_eq!((position.line(), position.column()), (2, 6));
+    }
+}
@@ -0,0 +1,1803 @@
+//! XPath 1.0 evaluation
+//!
+//! Evaluates XPath 1.0 expressions against a parsed document, using any node (or the `Soup`
+//! itself) as the context node.
+//!
+//! ```rust
+//! # extern crate soup;
+//! # use soup::prelude::*;
+//! # use std::error::Error;
+//! # fn main() -> Result<(), Box<Error>> {
+//! let soup = Soup::new(r#"
+//! <table id="prices">
+//!   <tr><th>Item</th><th>Price</th></tr>
+//!   <tr><td>Apple</td><td>1.25</td></tr>
+//!   <tr><td>Pear</td><td>2.50</td></tr>
+//! </table>"#);
+//! let prices = soup.xpath("//table[@id='prices']//tr[position()>1]/td[2]/text()")?
+//!                  .into_handles()
+//!                  .iter()
+//!                  .map(|text| text.text())
+//!                  .collect::<Vec<_>>();
+//! assert_eq!(prices, vec!["1.25".to_string(), "2.50".to_string()]);
+//!
+//! let total = soup.xpath("sum(//table[@id='prices']//td[2])")?;
+//! assert_eq!(total.number(), 3.75);
+//! #   Ok(())
+//! # }
+//! ```
+//!
+//! Element names are matched against the local name of the element, so `//div` matches HTML
+//! `div` elements even though `html5ever` places them in the XHTML namespace. A name test with
+//! a prefix, like `svg:rect`, matches elements & attributes with that prefix.
+//!
+//! The `namespace` axis is not supported.
+use html5ever::{rcdom::Handle, QualName};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error,
+    f64,
+    fmt,
+};
+
+use crate::{
+    attribute,
+    dom::{self, NodeKind},
+};
+
+/// An error encountered while parsing or evaluating an XPath expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    message: String,
+}
+
+impl Error {
+    fn new<S: Into<String>>(message: S) -> Error {
+        Error {
+            message: message.into(),
+        }
+    }
+
+    fn at<S: AsRef<str>>(message: S, position: usize) -> Error {
+        Error::new(format!("{} at position {}", message.as_ref(), position))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+/// An attribute node, as returned by the `attribute` axis
+#[derive(Clone)]
+pub struct Attribute<N = Handle> {
+    element: N,
+    name: QualName,
+    value: String,
+}
+
+impl<N> Attribute<N> {
+    /// The element this attribute belongs to
+    pub fn element(&self) -> &N {
+        &self.element
+    }
+
+    /// The name of the attribute, including its prefix if it has one
+    pub fn name(&self) -> String {
+        qualified_name(&self.name)
+    }
+
+    /// The value of the attribute
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl<N> fmt::Debug for Attribute<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attribute")
+            .field("name", &self.name())
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+/// A node in an XPath node-set
+///
+/// `N` is the type of node in the tree being searched, which is a `Handle` for a `Soup`.
+#[derive(Clone)]
+pub enum Node<N = Handle> {
+    /// A document, element, text, comment or processing instruction node
+    Handle(N),
+    /// An attribute of an element
+    Attribute(Attribute<N>),
+}
+
+impl<N: dom::Node> Node<N> {
+    /// Returns the handle of this node, or `None` if it is an attribute
+    pub fn as_handle(&self) -> Option<&N> {
+        match *self {
+            Node::Handle(ref handle) => Some(handle),
+            Node::Attribute(_) => None,
+        }
+    }
+
+    /// The XPath string-value of the node
+    ///
+    /// For elements & documents this is the text of all descendant text nodes, for attributes
+    /// it is the attribute value
+    pub fn string_value(&self) -> String {
+        match *self {
+            Node::Handle(ref handle) => match handle.node_kind() {
+                NodeKind::Comment | NodeKind::ProcessingInstruction => {
+                    handle.contents().map(|contents| contents.into_owned()).unwrap_or_default()
+                },
+                NodeKind::Doctype => String::new(),
+                _ => dom::text(handle),
+            },
+            Node::Attribute(ref attr) => attr.value.clone(),
+        }
+    }
+
+    fn same_node(&self, other: &Node<N>) -> bool {
+        match (self, other) {
+            (Node::Handle(a), Node::Handle(b)) => a.same_node(b),
+            (Node::Attribute(a), Node::Attribute(b)) => {
+                a.element.same_node(&b.element) && a.name == b.name
+            },
+            _ => false,
+        }
+    }
+}
+
+impl<N: dom::Node> PartialEq for Node<N> {
+    fn eq(&self, other: &Node<N>) -> bool {
+        self.same_node(other)
+    }
+}
+
+impl<N: dom::Node> fmt::Debug for Node<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Node::Handle(ref handle) => {
+                let mut tuple = f.debug_tuple("Handle");
+                tuple.field(&dom::name(handle));
+                if let Some(contents) = handle.contents() {
+                    tuple.field(&contents);
+                }
+                tuple.finish()
+            },
+            Node::Attribute(ref attr) => f.debug_tuple("Attribute").field(attr).finish(),
+        }
+    }
+}
+
+/// The result of evaluating an XPath expression
+#[derive(Clone)]
+pub enum Value<N = Handle> {
+    /// A set of nodes, in document order
+    Nodes(Vec<Node<N>>),
+    /// A string
+    String(String),
+    /// A number
+    Number(f64),
+    /// A boolean
+    Boolean(bool),
+}
+
+impl<N: dom::Node> Value<N> {
+    /// Converts the value to a boolean, as done by the XPath `boolean()` function
+    pub fn boolean(&self) -> bool {
+        match *self {
+            Value::Nodes(ref nodes) => !nodes.is_empty(),
+            Value::String(ref s) => !s.is_empty(),
+            Value::Number(n) => n != 0.0 && !n.is_nan(),
+            Value::Boolean(b) => b,
+        }
+    }
+
+    /// Converts the value to a number, as done by the XPath `number()` function
+    pub fn number(&self) -> f64 {
+        match *self {
+            Value::Nodes(_) | Value::String(_) => string_to_number(&self.string()),
+            Value::Number(n) => n,
+            Value::Boolean(b) => {
+                if b {
+                    1.0
+                } else {
+                    0.0
+                }
+            },
+        }
+    }
+
+    /// Converts the value to a string, as done by the XPath `string()` function
+    ///
+    /// The string-value of a node-set is the string-value of its first node
+    pub fn string(&self) -> String {
+        match *self {
+            Value::Nodes(ref nodes) => nodes.first().map(Node::string_value).unwrap_or_default(),
+            Value::String(ref s) => s.clone(),
+            Value::Number(n) => number_to_string(n),
+            Value::Boolean(b) => b.to_string(),
+        }
+    }
+
+    /// Returns the nodes if this value is a node-set
+    pub fn nodes(&self) -> Option<&[Node<N>]> {
+        match *self {
+            Value::Nodes(ref nodes) => Some(nodes),
+            _ => None,
+        }
+    }
+
+    /// Returns the handles of all non-attribute nodes in the node-set
+    ///
+    /// If this value is not a node-set, an empty `Vec` is returned
+    pub fn into_handles(self) -> Vec<N> {
+        match self {
+            Value::Nodes(nodes) => nodes
+                .into_iter()
+                .filter_map(|node| match node {
+                    Node::Handle(handle) => Some(handle),
+                    Node::Attribute(_) => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl<N: dom::Node> PartialEq for Value<N> {
+    fn eq(&self, other: &Value<N>) -> bool {
+        match (self, other) {
+            (Value::Nodes(a), Value::Nodes(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<N: dom::Node> fmt::Debug for Value<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Nodes(ref nodes) => f.debug_tuple("Nodes").field(nodes).finish(),
+            Value::String(ref s) => f.debug_tuple("String").field(s).finish(),
+            Value::Number(n) => f.debug_tuple("Number").field(&n).finish(),
+            Value::Boolean(b) => f.debug_tuple("Boolean").field(&b).finish(),
+        }
+    }
+}
+
+/// A compiled XPath expression
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate soup;
+/// # use soup::prelude::*;
+/// # use std::error::Error;
+/// use soup::xpath::XPath;
+///
+/// # fn main() -> Result<(), Box<Error>> {
+/// let soup = Soup::new(r#"<ul><li>ONE</li><li>TWO</li></ul>"#);
+/// let count = XPath::parse("count(//li)")?;
+/// let ul = soup.tag("ul").find().expect("Couldn't find ul");
+/// assert_eq!(count.evaluate(&ul)?.number(), 2.0);
+/// #   Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct XPath {
+    expr: Expr,
+}
+
+impl XPath {
+    /// Parses an XPath 1.0 expression
+    pub fn parse(expr: &str) -> Result<XPath, Error> {
+        let tokens = Lexer::new(expr).tokenize()?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: expr.chars().count(),
+        };
+        let expr = parser.parse_expr()?;
+        if let Some(&(ref token, position)) = parser.tokens.get(parser.pos) {
+            return Err(Error::at(format!("Unexpected {:?}", token), position));
+        }
+        Ok(XPath {
+            expr,
+        })
+    }
+
+    /// Evaluates the expression with `node` as the context node
+    ///
+    /// `node` can be from any tree that implements `dom::Node`, like a `SyncSoup`.
+    pub fn evaluate<N: dom::Node>(&self, node: &N) -> Result<Value<N>, Error> {
+        self.evaluate_with(node, &HashMap::new())
+    }
+
+    /// Evaluates the expression with `node` as the context node, resolving variable
+    /// references like `$name` from `variables`
+    pub fn evaluate_with<N: dom::Node>(
+        &self,
+        node: &N,
+        variables: &HashMap<String, Value<N>>,
+    ) -> Result<Value<N>, Error> {
+        let evaluator = Evaluator {
+            variables,
+            order: RefCell::new(None),
+            root: root_of(node),
+        };
+        let ctx = Context {
+            node: &Node::Handle(node.clone()),
+            position: 1,
+            size: 1,
+        };
+        evaluator.eval(&self.expr, &ctx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    SelfNode,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Axis> {
+        let axis = match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::SelfNode,
+            _ => return None,
+        };
+        Some(axis)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NodeTest {
+    Any,
+    Prefixed(String),
+    Name(Option<String>, String),
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction(Option<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Debug, Clone)]
+enum PathStart {
+    Context,
+    Root,
+    Expr(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Union,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    Negate(Box<Expr>),
+    Path(PathStart, Vec<Step>),
+    Filter(Box<Expr>, Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    Function(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    NameTest(NodeTest),
+    NodeType(String),
+    FunctionName(String),
+    AxisName(String),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+}
+
+impl Token {
+    fn is_operator(&self) -> bool {
+        matches!(
+            *self,
+            Token::And
+                | Token::Or
+                | Token::Mod
+                | Token::Div
+                | Token::Multiply
+                | Token::Slash
+                | Token::DoubleSlash
+                | Token::Pipe
+                | Token::Plus
+                | Token::Minus
+                | Token::Equal
+                | Token::NotEqual
+                | Token::Less
+                | Token::LessEqual
+                | Token::Greater
+                | Token::GreaterEqual
+        )
+    }
+
+    // Whether a `*` or name following this token must be read as an operator
+    fn precedes_operator(&self) -> bool {
+        !(self.is_operator()
+            || matches!(
+                *self,
+                Token::At | Token::ColonColon | Token::LeftParen | Token::LeftBracket | Token::Comma
+            ))
+    }
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn new(input: &str) -> Lexer {
+        Lexer {
+            chars: input.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    // the next non-whitespace character, without consuming anything
+    fn next_significant(&self) -> (Option<char>, Option<char>) {
+        let mut i = self.pos;
+        while self.chars.get(i).is_some_and(|c| c.is_whitespace()) {
+            i += 1;
+        }
+        (self.chars.get(i).cloned(), self.chars.get(i + 1).cloned())
+    }
+
+    fn is_name_start(c: char) -> bool {
+        c.is_alphabetic() || c == '_'
+    }
+
+    fn is_name_char(c: char) -> bool {
+        Lexer::is_name_start(c) || c.is_numeric() || c == '-' || c == '.' || c == '\u{B7}'
+    }
+
+    fn ncname(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if Lexer::is_name_char(c) {
+                name.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        name
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, usize)>, Error> {
+        let mut tokens: Vec<(Token, usize)> = vec![];
+        loop {
+            self.skip_whitespace();
+            let start = self.pos;
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Ok(tokens),
+            };
+            let operator_context = tokens
+                .last()
+                .is_some_and(|(token, _)| token.precedes_operator());
+            let token = match c {
+                '(' => self.single(Token::LeftParen),
+                ')' => self.single(Token::RightParen),
+                '[' => self.single(Token::LeftBracket),
+                ']' => self.single(Token::RightBracket),
+                '@' => self.single(Token::At),
+                ',' => self.single(Token::Comma),
+                '|' => self.single(Token::Pipe),
+                '+' => self.single(Token::Plus),
+                '-' => self.single(Token::Minus),
+                '=' => self.single(Token::Equal),
+                '!' if self.peek_at(1) == Some('=') => self.double(Token::NotEqual),
+                '<' if self.peek_at(1) == Some('=') => self.double(Token::LessEqual),
+                '<' => self.single(Token::Less),
+                '>' if self.peek_at(1) == Some('=') => self.double(Token::GreaterEqual),
+                '>' => self.single(Token::Greater),
+                ':' if self.peek_at(1) == Some(':') => self.double(Token::ColonColon),
+                '/' if self.peek_at(1) == Some('/') => self.double(Token::DoubleSlash),
+                '/' => self.single(Token::Slash),
+                '.' if self.peek_at(1) == Some('.') => self.double(Token::DotDot),
+                '.' if !self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => {
+                    self.single(Token::Dot)
+                },
+                '.' | '0'..='9' => self.number(),
+                '"' | '\'' => self.literal(c)?,
+                '$' => {
+                    self.pos += 1;
+                    let name = self.qname()?;
+                    Token::Variable(name)
+                },
+                '*' if operator_context => self.single(Token::Multiply),
+                '*' => self.single(Token::NameTest(NodeTest::Any)),
+                c if Lexer::is_name_start(c) => self.name(operator_context)?,
+                c => return Err(Error::at(format!("Unexpected character '{}'", c), start)),
+            };
+            tokens.push((token, start));
+        }
+    }
+
+    fn single(&mut self, token: Token) -> Token {
+        self.pos += 1;
+        token
+    }
+
+    fn double(&mut self, token: Token) -> Token {
+        self.pos += 2;
+        token
+    }
+
+    fn number(&mut self) -> Token {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+        let number = self.chars[start..self.pos].iter().collect::<String>();
+        Token::Number(string_to_number(&number))
+    }
+
+    fn literal(&mut self, quote: char) -> Result<Token, Error> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(Token::Literal(value));
+                },
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                },
+                None => return Err(Error::at("Unterminated string literal", start)),
+            }
+        }
+    }
+
+    fn qname(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+        if !self.peek().is_some_and(Lexer::is_name_start) {
+            return Err(Error::at("Expected name", start));
+        }
+        let mut name = self.ncname();
+        if self.peek() == Some(':') && self.peek_at(1).is_some_and(Lexer::is_name_start) {
+            self.pos += 1;
+            name.push(':');
+            name.push_str(&self.ncname());
+        }
+        Ok(name)
+    }
+
+    fn name(&mut self, operator_context: bool) -> Result<Token, Error> {
+        let start = self.pos;
+        let name = self.ncname();
+        if operator_context {
+            return match name.as_str() {
+                "and" => Ok(Token::And),
+                "or" => Ok(Token::Or),
+                "mod" => Ok(Token::Mod),
+                "div" => Ok(Token::Div),
+                _ => Err(Error::at(format!("Expected operator, found '{}'", name), start)),
+            };
+        }
+        // prefix:* or prefix:local
+        if self.peek() == Some(':') && self.peek_at(1) != Some(':') {
+            self.pos += 1;
+            if self.peek() == Some('*') {
+                self.pos += 1;
+                return Ok(Token::NameTest(NodeTest::Prefixed(name)));
+            }
+            if !self.peek().is_some_and(Lexer::is_name_start) {
+                return Err(Error::at("Expected name after ':'", self.pos));
+            }
+            let local = self.ncname();
+            return Ok(match self.next_significant() {
+                (Some('('), _) => Token::FunctionName(format!("{}:{}", name, local)),
+                _ => Token::NameTest(NodeTest::Name(Some(name), local)),
+            });
+        }
+        Ok(match self.next_significant() {
+            (Some('('), _) => match name.as_str() {
+                "node" | "text" | "comment" | "processing-instruction" => Token::NodeType(name),
+                _ => Token::FunctionName(name),
+            },
+            (Some(':'), Some(':')) => Token::AxisName(name),
+            _ => Token::NameTest(NodeTest::Name(None, name)),
+        })
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|&(_, position)| position)
+            .unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), Error> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("{:?}", token)))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        match self.peek() {
+            Some(found) => Error::at(
+                format!("Expected {}, found {:?}", expected, found),
+                self.position(),
+            ),
+            None => Error::at(
+                format!("Expected {}, found end of expression", expected),
+                self.position(),
+            ),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, Error> {
+        self.parse_binary(0)
+    }
+
+    // Operators by precedence level, from loosest to tightest binding
+    fn operator(token: &Token, level: usize) -> Option<BinaryOp> {
+        let op = match (level, token) {
+            (0, Token::Or) => BinaryOp::Or,
+            (1, Token::And) => BinaryOp::And,
+            (2, Token::Equal) => BinaryOp::Equal,
+            (2, Token::NotEqual) => BinaryOp::NotEqual,
+            (3, Token::Less) => BinaryOp::Less,
+            (3, Token::LessEqual) => BinaryOp::LessEqual,
+            (3, Token::Greater) => BinaryOp::Greater,
+            (3, Token::GreaterEqual) => BinaryOp::GreaterEqual,
+            (4, Token::Plus) => BinaryOp::Add,
+            (4, Token::Minus) => BinaryOp::Subtract,
+            (5, Token::Multiply) => BinaryOp::Multiply,
+            (5, Token::Div) => BinaryOp::Divide,
+            (5, Token::Mod) => BinaryOp::Modulo,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, Error> {
+        if level > 5 {
+            return self.parse_unary();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(op) = self.peek().and_then(|token| Parser::operator(token, level)) {
+            self.pos += 1;
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        if self.eat(&Token::Minus) {
+            let expr = self.parse_unary()?;
+            return Ok(Expr::Negate(Box::new(expr)));
+        }
+        let mut left = self.parse_path()?;
+        while self.eat(&Token::Pipe) {
+            let right = self.parse_path()?;
+            left = Expr::Binary(Box::new(left), BinaryOp::Union, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn starts_step(token: Option<&Token>) -> bool {
+        matches!(
+            token,
+            Some(Token::Dot)
+                | Some(Token::DotDot)
+                | Some(Token::At)
+                | Some(Token::AxisName(_))
+                | Some(Token::NameTest(_))
+                | Some(Token::NodeType(_))
+        )
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, Error> {
+        match self.peek() {
+            Some(Token::Slash) => {
+                self.pos += 1;
+                let steps = if Parser::starts_step(self.peek()) {
+                    self.parse_relative_path()?
+                } else {
+                    vec![]
+                };
+                Ok(Expr::Path(PathStart::Root, steps))
+            },
+            Some(Token::DoubleSlash) => {
+                self.pos += 1;
+                let mut steps = vec![descendant_or_self()];
+                steps.extend(self.parse_relative_path()?);
+                Ok(Expr::Path(PathStart::Root, steps))
+            },
+            token if Parser::starts_step(token) => {
+                let steps = self.parse_relative_path()?;
+                Ok(Expr::Path(PathStart::Context, steps))
+            },
+            _ => {
+                let primary = self.parse_primary()?;
+                let mut predicates = vec![];
+                while self.peek() == Some(&Token::LeftBracket) {
+                    predicates.push(self.parse_predicate()?);
+                }
+                let filter = if predicates.is_empty() {
+                    primary
+                } else {
+                    Expr::Filter(Box::new(primary), predicates)
+                };
+                let mut steps = vec![];
+                match self.peek() {
+                    Some(Token::Slash) => {
+                        self.pos += 1;
+                    },
+                    Some(Token::DoubleSlash) => {
+                        self.pos += 1;
+                        steps.push(descendant_or_self());
+                    },
+                    _ => return Ok(filter),
+                }
+                steps.extend(self.parse_relative_path()?);
+                Ok(Expr::Path(PathStart::Expr(Box::new(filter)), steps))
+            },
+        }
+    }
+
+    fn parse_relative_path(&mut self) -> Result<Vec<Step>, Error> {
+        let mut steps = vec![self.parse_step()?];
+        loop {
+            match self.peek() {
+                Some(Token::Slash) => {
+                    self.pos += 1;
+                },
+                Some(Token::DoubleSlash) => {
+                    self.pos += 1;
+                    steps.push(descendant_or_self());
+                },
+                _ => return Ok(steps),
+            }
+            steps.push(self.parse_step()?);
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, Error> {
+        let axis = match self.peek() {
+            Some(Token::Dot) => {
+                self.pos += 1;
+                return Ok(Step {
+                    axis: Axis::SelfNode,
+                    test: NodeTest::Node,
+                    predicates: vec![],
+                });
+            },
+            Some(Token::DotDot) => {
+                self.pos += 1;
+                return Ok(Step {
+                    axis: Axis::Parent,
+                    test: NodeTest::Node,
+                    predicates: vec![],
+                });
+            },
+            Some(Token::At) => {
+                self.pos += 1;
+                Axis::Attribute
+            },
+            Some(Token::AxisName(_)) => {
+                let position = self.position();
+                let name = match self.next() {
+                    Some(Token::AxisName(name)) => name,
+                    _ => unreachable!(),
+                };
+                self.expect(&Token::ColonColon)?;
+                match Axis::from_name(&name) {
+                    Some(axis) => axis,
+                    None if name == "namespace" => {
+                        return Err(Error::at("The namespace axis is not supported", position))
+                    },
+                    None => return Err(Error::at(format!("Unknown axis '{}'", name), position)),
+                }
+            },
+            _ => Axis::Child,
+        };
+        let test = match self.next() {
+            Some(Token::NameTest(test)) => test,
+            Some(Token::NodeType(kind)) => {
+                self.expect(&Token::LeftParen)?;
+                let test = match kind.as_str() {
+                    "node" => NodeTest::Node,
+                    "text" => NodeTest::Text,
+                    "comment" => NodeTest::Comment,
+                    _ => match self.peek() {
+                        Some(Token::Literal(_)) => match self.next() {
+                            Some(Token::Literal(target)) => {
+                                NodeTest::ProcessingInstruction(Some(target))
+                            },
+                            _ => unreachable!(),
+                        },
+                        _ => NodeTest::ProcessingInstruction(None),
+                    },
+                };
+                self.expect(&Token::RightParen)?;
+                test
+            },
+            _ => {
+                self.pos -= 1;
+                return Err(self.unexpected("node test"));
+            },
+        };
+        let mut predicates = vec![];
+        while self.peek() == Some(&Token::LeftBracket) {
+            predicates.push(self.parse_predicate()?);
+        }
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn parse_predicate(&mut self) -> Result<Expr, Error> {
+        self.expect(&Token::LeftBracket)?;
+        let expr = self.parse_expr()?;
+        self.expect(&Token::RightBracket)?;
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::LeftParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RightParen)?;
+                Ok(expr)
+            },
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Variable(name)) => Ok(Expr::Variable(name)),
+            Some(Token::FunctionName(name)) => {
+                self.expect(&Token::LeftParen)?;
+                let mut args = vec![];
+                if !self.eat(&Token::RightParen) {
+                    loop {
+                        args.push(self.parse_expr()?);
+                        if self.eat(&Token::Comma) {
+                            continue;
+                        }
+                        self.expect(&Token::RightParen)?;
+                        break;
+                    }
+                }
+                check_arity(&name, args.len(), position)?;
+                Ok(Expr::Function(name, args))
+            },
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("expression"))
+            },
+        }
+    }
+}
+
+fn descendant_or_self() -> Step {
+    Step {
+        axis: Axis::DescendantOrSelf,
+        test: NodeTest::Node,
+        predicates: vec![],
+    }
+}
+
+fn check_arity(name: &str, count: usize, position: usize) -> Result<(), Error> {
+    let (min, max) = match name {
+        "last" | "position" | "true" | "false" => (0, Some(0)),
+        "count" | "id" | "boolean" | "not" | "lang" | "sum" | "floor" | "ceiling" | "round" => {
+            (1, Some(1))
+        },
+        "local-name" | "namespace-uri" | "name" | "string" | "string-length"
+        | "normalize-space" | "number" => (0, Some(1)),
+        "starts-with" | "contains" | "substring-before" | "substring-after" => (2, Some(2)),
+        "substring" => (2, Some(3)),
+        "translate" => (3, Some(3)),
+        "concat" => (2, None),
+        _ => return Err(Error::at(format!("Unknown function '{}'", name), position)),
+    };
+    if count < min || max.is_some_and(|max| count > max) {
+        return Err(Error::at(
+            format!("Wrong number of arguments to '{}'", name),
+            position,
+        ));
+    }
+    Ok(())
+}
+
+struct Context<'c, N> {
+    node: &'c Node<N>,
+    position: usize,
+    size: usize,
+}
+
+// Every node of the document in preorder, along with where each node with an id is
+struct Order<N> {
+    nodes: Vec<N>,
+    ids: HashMap<usize, usize>,
+}
+
+struct Evaluator<'v, N> {
+    variables: &'v HashMap<String, Value<N>>,
+    // built the first time it is needed
+    order: RefCell<Option<Order<N>>>,
+    root: N,
+}
+
+impl<'v, N: dom::Node> Evaluator<'v, N> {
+    fn eval(&self, expr: &Expr, ctx: &Context<'_, N>) -> Result<Value<N>, Error> {
+        match *expr {
+            Expr::Literal(ref value) => Ok(Value::String(value.clone())),
+            Expr::Number(value) => Ok(Value::Number(value)),
+            Expr::Variable(ref name) => self
+                .variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::new(format!("Unknown variable '${}'", name))),
+            Expr::Negate(ref expr) => Ok(Value::Number(-self.eval(expr, ctx)?.number())),
+            Expr::Binary(ref left, op, ref right) => self.eval_binary(left, op, right, ctx),
+            Expr::Function(ref name, ref args) => self.eval_function(name, args, ctx),
+            Expr::Filter(ref primary, ref predicates) => {
+                let nodes = self.eval_nodes(primary, ctx)?;
+                let mut nodes = self.apply_predicates(nodes, predicates)?;
+                self.sort(&mut nodes);
+                Ok(Value::Nodes(nodes))
+            },
+            Expr::Path(ref start, ref steps) => {
+                let mut nodes = match *start {
+                    PathStart::Context => vec![ctx.node.clone()],
+                    PathStart::Root => vec![Node::Handle(self.root.clone())],
+                    PathStart::Expr(ref expr) => self.eval_nodes(expr, ctx)?,
+                };
+                for step in steps {
+                    nodes = self.eval_step(&nodes, step)?;
+                }
+                Ok(Value::Nodes(nodes))
+            },
+        }
+    }
+
+    fn eval_nodes(&self, expr: &Expr, ctx: &Context<'_, N>) -> Result<Vec<Node<N>>, Error> {
+        match self.eval(expr, ctx)? {
+            Value::Nodes(nodes) => Ok(nodes),
+            _ => Err(Error::new("Expression does not evaluate to a node-set")),
+        }
+    }
+
+    fn eval_step(&self, context: &[Node<N>], step: &Step) -> Result<Vec<Node<N>>, Error> {
+        let mut result = vec![];
+        for node in context {
+            let candidates = axis_nodes(node, step.axis)
+                .into_iter()
+                .filter(|candidate| node_test(candidate, step.axis, &step.test))
+                .collect();
+            result.extend(self.apply_predicates(candidates, &step.predicates)?);
+        }
+        self.sort(&mut result);
+        Ok(result)
+    }
+
+    fn apply_predicates(&self, mut nodes: Vec<Node<N>>, predicates: &[Expr]) -> Result<Vec<Node<N>>, Error> {
+        for predicate in predicates {
+            let size = nodes.len();
+            let mut filtered = vec![];
+            for (i, node) in nodes.into_iter().enumerate() {
+                let ctx = Context {
+                    node: &node,
+                    position: i + 1,
+                    size,
+                };
+                let keep = match self.eval(predicate, &ctx)? {
+                    Value::Number(n) => n == (i + 1) as f64,
+                    value => value.boolean(),
+                };
+                if keep {
+                    filtered.push(node);
+                }
+            }
+            nodes = filtered;
+        }
+        Ok(nodes)
+    }
+
+    fn eval_binary(
+        &self,
+        left: &Expr,
+        op: BinaryOp,
+        right: &Expr,
+        ctx: &Context<'_, N>,
+    ) -> Result<Value<N>, Error> {
+        match op {
+            BinaryOp::Or => {
+                Ok(Value::Boolean(
+                    self.eval(left, ctx)?.boolean() || self.eval(right, ctx)?.boolean(),
+                ))
+            },
+            BinaryOp::And => {
+                Ok(Value::Boolean(
+                    self.eval(left, ctx)?.boolean() && self.eval(right, ctx)?.boolean(),
+                ))
+            },
+            BinaryOp::Union => {
+                let mut nodes = self.eval_nodes(left, ctx)?;
+                nodes.extend(self.eval_nodes(right, ctx)?);
+                self.sort(&mut nodes);
+                Ok(Value::Nodes(nodes))
+            },
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Less
+            | BinaryOp::LessEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual => {
+                let left = self.eval(left, ctx)?;
+                let right = self.eval(right, ctx)?;
+                Ok(Value::Boolean(compare(&left, op, &right)))
+            },
+            _ => {
+                let left = self.eval(left, ctx)?.number();
+                let right = self.eval(right, ctx)?.number();
+                Ok(Value::Number(match op {
+                    BinaryOp::Add => left + right,
+                    BinaryOp::Subtract => left - right,
+                    BinaryOp::Multiply => left * right,
+                    BinaryOp::Divide => left / right,
+                    _ => left % right,
+                }))
+            },
+        }
+    }
+
+    fn eval_function(&self, name: &str, args: &[Expr], ctx: &Context<'_, N>) -> Result<Value<N>, Error> {
+        let arg = |i: usize| -> Result<Value<N>, Error> { self.eval(&args[i], ctx) };
+        // string argument that defaults to the context node
+        let string_arg = |i: usize| -> Result<String, Error> {
+            if args.len() > i {
+                Ok(arg(i)?.string())
+            } else {
+                Ok(ctx.node.string_value())
+            }
+        };
+        // node-set argument that defaults to the context node
+        let first_node = |i: usize| -> Result<Option<Node<N>>, Error> {
+            if args.len() > i {
+                let mut nodes = self.eval_nodes(&args[i], ctx)?;
+                self.sort(&mut nodes);
+                Ok(nodes.into_iter().next())
+            } else {
+                Ok(Some(ctx.node.clone()))
+            }
+        };
+        let value = match name {
+            "last" => Value::Number(ctx.size as f64),
+            "position" => Value::Number(ctx.position as f64),
+            "count" => Value::Number(self.eval_nodes(&args[0], ctx)?.len() as f64),
+            "id" => {
+                let ids = match arg(0)? {
+                    Value::Nodes(nodes) => nodes
+                        .iter()
+                        .map(Node::string_value)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    value => value.string(),
+                };
+                let mut nodes = vec![];
+                for id in ids.split_whitespace() {
+                    find_by_id(&self.root, id, &mut nodes);
+                }
+                self.sort(&mut nodes);
+                Value::Nodes(nodes)
+            },
+            "local-name" => Value::String(
+                first_node(0)?
+                    .map(|node| node_name(&node).map(|name| name.local.to_string()))
+                    .unwrap_or_default()
+                    .unwrap_or_default(),
+            ),
+            "namespace-uri" => Value::String(
+                first_node(0)?
+                    .map(|node| node_name(&node).map(|name| name.ns.to_string()))
+                    .unwrap_or_default()
+                    .unwrap_or_default(),
+            ),
+            "name" => Value::String(
+                first_node(0)?
+                    .map(|node| node_name(&node).map(|name| qualified_name(&name)))
+                    .unwrap_or_default()
+                    .unwrap_or_default(),
+            ),
+            "string" => Value::String(string_arg(0)?),
+            "concat" => {
+                let mut result = String::new();
+                for i in 0..args.len() {
+                    result.push_str(&arg(i)?.string());
+                }
+                Value::String(result)
+            },
+            "starts-with" => Value::Boolean(arg(0)?.string().starts_with(&arg(1)?.string())),
+            "contains" => Value::Boolean(arg(0)?.string().contains(&arg(1)?.string())),
+            "substring-before" => {
+                let s = arg(0)?.string();
+                let needle = arg(1)?.string();
+                Value::String(s.find(&needle).map(|i| s[..i].to_string()).unwrap_or_default())
+            },
+            "substring-after" => {
+                let s = arg(0)?.string();
+                let needle = arg(1)?.string();
+                Value::String(
+                    s.find(&needle)
+                        .map(|i| s[i + needle.len()..].to_string())
+                        .unwrap_or_default(),
+                )
+            },
+            "substring" => {
+                let s = arg(0)?.string();
+                let start = round(arg(1)?.number());
+                let end = if args.len() > 2 {
+                    start + round(arg(2)?.number())
+                } else {
+                    f64::INFINITY
+                };
+                Value::String(
+                    s.chars()
+                        .enumerate()
+                        .filter(|&(i, _)| {
+                            let position = (i + 1) as f64;
+                            position >= start && position < end
+                        })
+                        .map(|(_, c)| c)
+                        .collect(),
+                )
+            },
+            "string-length" => Value::Number(string_arg(0)?.chars().count() as f64),
+            "normalize-space" => Value::String(
+                string_arg(0)?
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "translate" => {
+                let s = arg(0)?.string();
+                let from = arg(1)?.string().chars().collect::<Vec<_>>();
+                let to = arg(2)?.string().chars().collect::<Vec<_>>();
+                Value::String(
+                    s.chars()
+                        .filter_map(|c| match from.iter().position(|&f| f == c) {
+                            Some(i) => to.get(i).cloned(),
+                            None => Some(c),
+                        })
+                        .collect(),
+                )
+            },
+            "boolean" => Value::Boolean(arg(0)?.boolean()),
+            "not" => Value::Boolean(!arg(0)?.boolean()),
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            "lang" => {
+                let expected = arg(0)?.string().to_lowercase();
+                let lang = lang_of(ctx.node).map(|lang| lang.to_lowercase());
+                Value::Boolean(lang.is_some_and(|lang| {
+                    lang == expected || lang.starts_with(&format!("{}-", expected))
+                }))
+            },
+            "number" => {
+                if args.is_empty() {
+                    Value::Number(string_to_number(&ctx.node.string_value()))
+                } else {
+                    Value::Number(arg(0)?.number())
+                }
+            },
+            "sum" => Value::Number(
+                self.eval_nodes(&args[0], ctx)?
+                    .iter()
+                    .map(|node| string_to_number(&node.string_value()))
+                    .sum(),
+            ),
+            "floor" => Value::Number(arg(0)?.number().floor()),
+            "ceiling" => Value::Number(arg(0)?.number().ceil()),
+            "round" => Value::Number(round(arg(0)?.number())),
+            _ => return Err(Error::new(format!("Unknown function '{}'", name))),
+        };
+        Ok(value)
+    }
+
+    fn order_of(&self, node: &Node<N>) -> (usize, usize) {
+        let mut order = self.order.borrow_mut();
+        let order = order.get_or_insert_with(|| {
+            let mut order = Order {
+                nodes: vec![],
+                ids: HashMap::new(),
+            };
+            let mut stack = vec![self.root.clone()];
+            while let Some(handle) = stack.pop() {
+                if let Some(id) = handle.node_id() {
+                    order.ids.insert(id, order.nodes.len());
+                }
+                stack.extend(handle.child_nodes().collect::<Vec<_>>().into_iter().rev());
+                order.nodes.push(handle);
+            }
+            order
+        });
+        let index_of = |handle: &N| {
+            let idx = match handle.node_id() {
+                Some(id) => order.ids.get(&id).cloned(),
+                None => order.nodes.iter().position(|node| node.same_node(handle)),
+            };
+            // Nodes from other documents, like those in variables, go last
+            idx.filter(|&idx| order.nodes[idx].same_node(handle)).unwrap_or(usize::MAX)
+        };
+        match *node {
+            Node::Handle(ref handle) => (index_of(handle), 0),
+            Node::Attribute(ref attr) => {
+                let mut idx = 0;
+                attr.element.any_attribute(&mut |name, _| {
+                    idx += 1;
+                    *name == attr.name
+                });
+                (index_of(&attr.element), idx)
+            },
+        }
+    }
+
+    // Sorts a node-set into document order, removing duplicates
+    fn sort(&self, nodes: &mut Vec<Node<N>>) {
+        if nodes.len() < 2 {
+            return;
+        }
+        nodes.sort_by_cached_key(|node| self.order_of(node));
+        nodes.dedup_by(|a, b| a.same_node(b));
+    }
+}
+
+fn root_of<N: dom::Node>(node: &N) -> N {
+    let mut root = node.clone();
+    while let Some(parent) = root.parent_node() {
+        root = parent;
+    }
+    root
+}
+
+fn qualified_name(name: &QualName) -> String {
+    match name.prefix {
+        Some(ref prefix) => format!("{}:{}", prefix, name.local),
+        None => name.local.to_string(),
+    }
+}
+
+fn node_name<N: dom::Node>(node: &Node<N>) -> Option<QualName> {
+    match *node {
+        Node::Handle(ref handle) => handle.element_name().cloned(),
+        Node::Attribute(ref attr) => Some(attr.name.clone()),
+    }
+}
+
+fn lang_of<N: dom::Node>(node: &Node<N>) -> Option<String> {
+    let mut current = match *node {
+        Node::Handle(ref handle) => Some(handle.clone()),
+        Node::Attribute(ref attr) => Some(attr.element.clone()),
+    };
+    while let Some(handle) = current {
+        if let Some(lang) = attribute::get(&handle, "xml:lang").or_else(|| attribute::get(&handle, "lang")) {
+            return Some(lang);
+        }
+        current = handle.parent_node();
+    }
+    None
+}
+
+fn find_by_id<N: dom::Node>(node: &N, id: &str, result: &mut Vec<Node<N>>) {
+    if attribute::get(node, "id").is_some_and(|value| value == id) {
+        result.push(Node::Handle(node.clone()));
+    }
+    for child in node.child_nodes() {
+        find_by_id(&child, id, result);
+    }
+}
+
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else if (-0.5..0.0).contains(&n) {
+        -0.0
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n');
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let valid = !digits.is_empty()
+        && digits != "."
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.matches('.').count() <= 1;
+    if valid {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 {
+            "Infinity".to_string()
+        } else {
+            "-Infinity".to_string()
+        }
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+fn compare<N: dom::Node>(left: &Value<N>, op: BinaryOp, right: &Value<N>) -> bool {
+    match (left, right) {
+        (Value::Nodes(left), Value::Nodes(right)) => {
+            let right = right.iter().map(Node::string_value).collect::<Vec<_>>();
+            left.iter().any(|l| {
+                let l = Value::<N>::String(l.string_value());
+                right
+                    .iter()
+                    .any(|r| compare_atomic(&l, op, &Value::String(r.clone())))
+            })
+        },
+        (Value::Nodes(nodes), Value::Boolean(_)) => {
+            compare_atomic(&Value::Boolean(!nodes.is_empty()), op, right)
+        },
+        (Value::Boolean(_), Value::Nodes(nodes)) => {
+            compare_atomic(left, op, &Value::Boolean(!nodes.is_empty()))
+        },
+        (Value::Nodes(nodes), _) => nodes.iter().any(|node| {
+            let value = match *right {
+                Value::Number(_) => Value::Number(string_to_number(&node.string_value())),
+                _ => Value::String(node.string_value()),
+            };
+            compare_atomic(&value, op, right)
+        }),
+        (_, Value::Nodes(nodes)) => nodes.iter().any(|node| {
+            let value = match *left {
+                Value::Number(_) => Value::Number(string_to_number(&node.string_value())),
+                _ => Value::String(node.string_value()),
+            };
+            compare_atomic(left, op, &value)
+        }),
+        _ => compare_atomic(left, op, right),
+    }
+}
+
+fn compare_atomic<N: dom::Node>(left: &Value<N>, op: BinaryOp, right: &Value<N>) -> bool {
+    match op {
+        BinaryOp::Equal | BinaryOp::NotEqual => {
+            let equal = match (left, right) {
+                (Value::Boolean(_), _) | (_, Value::Boolean(_)) => left.boolean() == right.boolean(),
+                (Value::Number(_), _) | (_, Value::Number(_)) => left.number() == right.number(),
+                _ => left.string() == right.string(),
+            };
+            (op == BinaryOp::Equal) == equal
+        },
+        _ => {
+            let (left, right) = (left.number(), right.number());
+            match op {
+                BinaryOp::Less => left < right,
+                BinaryOp::LessEqual => left <= right,
+                BinaryOp::Greater => left > right,
+                _ => left >= right,
+            }
+        },
+    }
+}
+
+fn handles<N: dom::Node>(handles: &[N]) -> Vec<Node<N>> {
+    handles.iter().cloned().map(Node::Handle).collect()
+}
+
+fn descendants<N: dom::Node>(handle: &N, result: &mut Vec<Node<N>>) {
+    for child in handle.child_nodes() {
+        result.push(Node::Handle(child.clone()));
+        descendants(&child, result);
+    }
+}
+
+// The siblings of `handle`, and its index amongst them
+fn siblings<N: dom::Node>(handle: &N) -> Option<(Vec<N>, usize)> {
+    let parent = handle.parent_node()?;
+    let siblings = parent.child_nodes().collect::<Vec<_>>();
+    let idx = siblings.iter().position(|sibling| sibling.same_node(handle))?;
+    Some((siblings, idx))
+}
+
+// Returns the nodes on the axis, in axis order (reverse document order for reverse axes)
+fn axis_nodes<N: dom::Node>(node: &Node<N>, axis: Axis) -> Vec<Node<N>> {
+    let handle = match *node {
+        Node::Handle(ref handle) => handle,
+        Node::Attribute(ref attr) => {
+            return match axis {
+                Axis::SelfNode => vec![node.clone()],
+                Axis::Parent => vec![Node::Handle(attr.element.clone())],
+                Axis::Ancestor | Axis::AncestorOrSelf => {
+                    let mut result = if axis == Axis::AncestorOrSelf {
+                        vec![node.clone()]
+                    } else {
+                        vec![]
+                    };
+                    result.push(Node::Handle(attr.element.clone()));
+                    result.extend(axis_nodes(&Node::Handle(attr.element.clone()), Axis::Ancestor));
+                    result
+                },
+                Axis::Following => {
+                    let element = Node::Handle(attr.element.clone());
+                    let mut result = vec![];
+                    descendants(&attr.element, &mut result);
+                    result.extend(axis_nodes(&element, Axis::Following));
+                    result
+                },
+                Axis::Preceding => {
+                    let element = Node::Handle(attr.element.clone());
+                    axis_nodes(&element, Axis::Preceding)
+                },
+                _ => vec![],
+            };
+        },
+    };
+    match axis {
+        Axis::SelfNode => vec![node.clone()],
+        Axis::Child => handle.child_nodes().map(Node::Handle).collect(),
+        Axis::Descendant | Axis::DescendantOrSelf => {
+            let mut result = if axis == Axis::DescendantOrSelf {
+                vec![node.clone()]
+            } else {
+                vec![]
+            };
+            descendants(handle, &mut result);
+            result
+        },
+        Axis::Parent => handle.parent_node().map(Node::Handle).into_iter().collect(),
+        Axis::Ancestor | Axis::AncestorOrSelf => {
+            let mut result = if axis == Axis::AncestorOrSelf {
+                vec![node.clone()]
+            } else {
+                vec![]
+            };
+            let mut current = handle.parent_node();
+            while let Some(parent) = current {
+                current = parent.parent_node();
+                result.push(Node::Handle(parent));
+            }
+            result
+        },
+        Axis::Attribute => {
+            let mut result = vec![];
+            handle.any_attribute(&mut |name, value| {
+                result.push(Node::Attribute(Attribute {
+                    element: handle.clone(),
+                    name: name.clone(),
+                    value: value.to_string(),
+                }));
+                false
+            });
+            result
+        },
+        Axis::FollowingSibling => siblings(handle)
+            .map(|(siblings, i)| handles(&siblings[i + 1..]))
+            .unwrap_or_default(),
+        Axis::PrecedingSibling => siblings(handle)
+            .map(|(siblings, i)| {
+                let mut preceding = handles(&siblings[..i]);
+                preceding.reverse();
+                preceding
+            })
+            .unwrap_or_default(),
+        Axis::Following => {
+            let mut result = vec![];
+            let mut current = Some(handle.clone());
+            while let Some(handle) = current {
+                if let Some((siblings, i)) = siblings(&handle) {
+                    for sibling in &siblings[i + 1..] {
+                        result.push(Node::Handle(sibling.clone()));
+                        descendants(sibling, &mut result);
+                    }
+                }
+                current = handle.parent_node();
+            }
+            result
+        },
+        Axis::Preceding => {
+            let mut result = vec![];
+            let mut current = Some(handle.clone());
+            while let Some(handle) = current {
+                if let Some((siblings, i)) = siblings(&handle) {
+                    for sibling in siblings[..i].iter().rev() {
+                        let mut subtree = vec![Node::Handle(sibling.clone())];
+                        descendants(sibling, &mut subtree);
+                        result.extend(subtree.into_iter().rev());
+                    }
+                }
+                current = handle.parent_node();
+            }
+            result
+        },
+    }
+}
+
+fn node_test<N: dom::Node>(node: &Node<N>, axis: Axis, test: &NodeTest) -> bool {
+    let principal_is_attribute = axis == Axis::Attribute;
+    match *test {
+        NodeTest::Node => true,
+        NodeTest::Text => node.as_handle().is_some_and(|handle| handle.node_kind() == NodeKind::Text),
+        NodeTest::Comment => node.as_handle().is_some_and(|handle| handle.node_kind() == NodeKind::Comment),
+        NodeTest::ProcessingInstruction(ref target) => match node.as_handle() {
+            Some(handle) if handle.node_kind() == NodeKind::ProcessingInstruction => {
+                target.as_ref().is_none_or(|target| handle.target() == Some(target.as_str()))
+            },
+            _ => false,
+        },
+        NodeTest::Any | NodeTest::Prefixed(_) | NodeTest::Name(..) => {
+            let name = match *node {
+                Node::Attribute(ref attr) if principal_is_attribute => &attr.name,
+                Node::Handle(ref handle) if !principal_is_attribute => match handle.element_name() {
+                    Some(name) => name,
+                    None => return false,
+                },
+                _ => return false,
+            };
+            let prefix = name.prefix.as_deref();
+            match *test {
+                NodeTest::Prefixed(ref expected) => prefix == Some(expected.as_str()),
+                NodeTest::Name(ref expected_prefix, ref local) => {
+                    prefix == expected_prefix.as_ref().map(String::as_str) && &*name.local == local
+                },
+                _ => true,
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Value, XPath};
+    use crate::prelude::*;
+
+    const HTML: &str = r#"
+<html><head><title>Prices</title></head>
+<body>
+<h1 id="top" lang="en">Fruit</h1>
+<table id="prices">
+  <tr><th>Item</th><th>Price</th></tr>
+  <tr class="row"><td>Apple</td><td>1.25</td></tr>
+  <tr class="row"><td>Pear</td><td>2.50</td></tr>
+  <tr class="row sale"><td>Plum</td><td>0.75</td></tr>
+</table>
+<p>After <!-- note --> table</p>
+</body></html>
+"#;
+
+    fn eval(expr: &str) -> Value {
+        let soup = Soup::new(HTML);
+        soup.xpath(expr).expect("Couldn't evaluate expression")
+    }
+
+    fn texts(expr: &str) -> Vec<String> {
+        eval(expr)
+            .nodes()
+            .expect("Not a node-set")
+            .iter()
+            .map(|node| node.string_value())
+            .collect()
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            texts("//table[@id='prices']//tr[position()>1]/td[2]/text()"),
+            vec!["1.25", "2.50", "0.75"]
+        );
+        assert_eq!(texts("/html/head/title"), vec!["Prices"]);
+        assert_eq!(texts("//td[.='Pear']/following-sibling::td"), vec!["2.50"]);
+        assert_eq!(texts("//td[.='Pear']/../preceding-sibling::tr[1]/td[1]"), vec!["Apple"]);
+        assert_eq!(texts("(//tr)[last()]/td[1]"), vec!["Plum"]);
+        assert_eq!(texts("//tr[contains(@class, 'sale')]/td[1]"), vec!["Plum"]);
+        assert_eq!(texts("//h1/@id"), vec!["top"]);
+        assert_eq!(texts("//th | //h1"), vec!["Fruit", "Item", "Price"]);
+        assert_eq!(texts("//p/comment()"), vec![" note "]);
+        assert_eq!(texts("id('top')"), vec!["Fruit"]);
+        assert_eq!(texts("//td[1][ancestor::table]")[0], "Apple");
+        assert_eq!(texts("//h1/following::th[1]"), vec!["Item"]);
+        assert_eq!(texts("//p/preceding::td[1]"), vec!["0.75"]);
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(eval("count(//tr)"), Value::Number(4.0));
+        assert_eq!(eval("sum(//tr/td[2])"), Value::Number(4.5));
+        assert_eq!(eval("string(//h1)"), Value::String("Fruit".to_string()));
+        assert_eq!(eval("name(//h1/@lang)"), Value::String("lang".to_string()));
+        assert_eq!(eval("boolean(//h2)"), Value::Boolean(false));
+        assert_eq!(eval("//td = 'Pear'"), Value::Boolean(true));
+        assert_eq!(eval("//td[2] > 2"), Value::Boolean(true));
+        assert_eq!(eval("1 + 2 * 3 - 4 div 2 mod 3"), Value::Number(5.0));
+        assert_eq!(eval("-(3)"), Value::Number(-3.0));
+        assert_eq!(eval("substring('12345', 1.5, 2.6)"), Value::String("234".to_string()));
+        assert_eq!(eval("normalize-space('  a   b ')"), Value::String("a b".to_string()));
+        assert_eq!(eval("translate('bar', 'abc', 'AB')"), Value::String("BAr".to_string()));
+        assert_eq!(eval("concat('a', 1, true())"), Value::String("a1true".to_string()));
+        assert_eq!(eval("substring-after('a=b', '=')"), Value::String("b".to_string()));
+        assert_eq!(eval("round(-0.5) = 0"), Value::Boolean(true));
+        assert_eq!(eval("string(1 div 0)"), Value::String("Infinity".to_string()));
+        assert!(eval("number('abc')").number().is_nan());
+        assert_eq!(eval("count(//*[lang('en')])"), Value::Number(1.0));
+    }
+
+    #[test]
+    fn relative_to_node() {
+        let soup = Soup::new(HTML);
+        let table = soup.tag("table").find().expect("Couldn't find table");
+        let rows = table.xpath("count(.//tr)").expect("Couldn't evaluate");
+        assert_eq!(rows.number(), 4.0);
+        let title = table.xpath("string(/html/head/title)").expect("Couldn't evaluate");
+        assert_eq!(title.string(), "Prices");
+    }
+
+    #[test]
+    fn variables() {
+        let soup = Soup::new(HTML);
+        let mut variables = ::std::collections::HashMap::new();
+        variables.insert("fruit".to_string(), Value::String("Plum".to_string()));
+        let xpath = XPath::parse("//td[. = $fruit]/following-sibling::td").expect("Couldn't parse");
+        let result = xpath
+            .evaluate_with(&soup.get_handle(), &variables)
+            .expect("Couldn't evaluate");
+        assert_eq!(result.string(), "0.75");
+        assert!(xpath.evaluate(&soup.get_handle()).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(XPath::parse("//").is_err());
+        assert!(XPath::parse("//a[").is_err());
+        assert!(XPath::parse("foo(1)").is_err());
+        assert!(XPath::parse("count()").is_err());
+        assert!(XPath::parse("namespace::*").is_err());
+        assert!(XPath::parse("'unterminated").is_err());
+        let soup = Soup::new(HTML);
+        assert!(soup.xpath("count(1)").is_err());
+    }
+}
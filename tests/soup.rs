@@ -1,11 +1,13 @@
 // TODO: any assertion commented out is a test that we won't pass yet
 #[cfg(feature = "regex")]
 extern crate regex;
+extern crate html5ever;
 extern crate soup;
 
 #[cfg(feature = "regex")]
 use regex::Regex;
-use soup::prelude::*;
+use html5ever::rcdom::Handle;
+use soup::{prelude::*, Query};
 
 const THREE_SISTERS: &'static str = include_str!("data/three_sisters.html");
 
@@ -185,3 +187,160 @@ fn parent_iterator() {
                              "div".to_string(), "body".to_string(), "html".to_string(),
                              "[document]".to_string()]);
 }
+
+#[test]
+fn boolean_combinators() {
+    let soup = Soup::new(r#"
+    <h1>ONE</h1>
+    <div class="ad">TWO</div>
+    <h2 class="ad" id="keep">THREE</h2>
+    <h3 class="ad">FOUR</h3>
+    "#);
+    let headings = soup.tag("h1").or(|q| q.tag("h2")).find_all().map(|node| node.text()).collect::<Vec<_>>();
+    assert_eq!(headings, vec!["ONE".to_string(), "THREE".to_string()]);
+    let ads = soup.class("ad").not(|q| q.attr("id", "keep")).find_all().map(|node| node.text()).collect::<Vec<_>>();
+    assert_eq!(ads, vec!["TWO".to_string(), "FOUR".to_string()]);
+    let nested = soup.any_of(|q| q.tag("h1").tag("h2").tag("h3"))
+                     .not(|q| q.class("ad").not(|q| q.attr("id", "keep")))
+                     .find_all()
+                     .map(|node| node.text())
+                     .collect::<Vec<_>>();
+    assert_eq!(nested, vec!["ONE".to_string(), "THREE".to_string()]);
+    let either = soup.or(|q| q.tag("div")).or(|q| q.tag("h3")).class("ad").find_all().map(|node| node.text()).collect::<Vec<_>>();
+    assert_eq!(either, vec!["TWO".to_string(), "FOUR".to_string()]);
+}
+
+#[test]
+fn custom_queries() {
+    struct ManyChildren(usize);
+
+    impl Query for ManyChildren {
+        fn matches(&self, node: &Handle) -> bool {
+            node.children.borrow().iter().filter(|child| child.is_element()).count() > self.0
+        }
+    }
+
+    let soup = Soup::new(r#"
+    <ul id="short"><li>1</li></ul>
+    <ul id="long"><li>1</li><li>2</li><li>3</li><li>4</li></ul>
+    <div><ul id="nested"><li>1</li><li>2</li><li>3</li><li>4</li></ul></div>
+    "#);
+    let long = soup.tag("ul")
+                   .query(ManyChildren(3))
+                   .find_all()
+                   .map(|ul| ul.get("id").expect("ul has no id"))
+                   .collect::<Vec<_>>();
+    assert_eq!(long, vec!["long".to_string(), "nested".to_string()]);
+    let body = soup.tag("body").find().expect("Couldn't find tag 'body'");
+    let top_level = body.filter(|node| node.children.borrow().len() > 3)
+                        .tag("ul")
+                        .recursive(false)
+                        .find_all()
+                        .count();
+    assert_eq!(top_level, 1);
+    let first_li = soup.filter(|node| node.text() == "2").tag("li").limit(1).find_all().count();
+    assert_eq!(first_li, 1);
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn find_by_text() {
+    let soup = soup();
+    let elsie = Regex::new("Elsie").expect("Couldn't create regex 'Elsie'");
+    let tags = soup.tag(true)
+                   .text(elsie.clone())
+                   .find_all()
+                   .map(|tag| tag.name().to_string())
+                   .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["html", "body", "p", "a"]);
+    let own = soup.own_text(elsie.clone())
+                  .find_all()
+                  .map(|tag| tag.name().to_string())
+                  .collect::<Vec<_>>();
+    assert_eq!(own, vec!["a"]);
+    let story = soup.own_text(Regex::new("^Once upon").expect("Couldn't create regex '^Once upon'"))
+                    .find()
+                    .expect("Couldn't find the story");
+    assert_eq!(story.get("class"), Some("story".to_string()));
+    let strings = soup.string(elsie).find_all().collect::<Vec<_>>();
+    assert_eq!(strings.len(), 1);
+    assert!(strings[0].is_text());
+    assert_eq!(strings[0].text(), "Elsie".to_string());
+    let parent = strings[0].parent().expect("Text node has no parent");
+    assert_eq!(parent.get("id"), Some("link1".to_string()));
+}
+
+#[test]
+fn sibling_navigation() {
+    let soup = Soup::new(r#"<dl><dt>Name</dt> <dd>Soup</dd><!-- c --><dt>Version</dt> <dd>0.5</dd></dl>"#);
+    let dts = soup.tag("dt").find_all().collect::<Vec<_>>();
+    let values = dts.iter()
+                    .map(|dt| dt.next_element_sibling().expect("Couldn't find dd").text())
+                    .collect::<Vec<_>>();
+    assert_eq!(values, vec!["Soup".to_string(), "0.5".to_string()]);
+    let after = dts[0].next_siblings().map(|node| node.name().to_string()).collect::<Vec<_>>();
+    assert_eq!(after, vec!["[text]", "dd", "[comment]", "dt", "[text]", "dd"]);
+    let before = dts[1].previous_siblings().map(|node| node.name().to_string()).collect::<Vec<_>>();
+    assert_eq!(before, vec!["[comment]", "dd", "[text]", "dt"]);
+    let before = dts[1].previous_element_siblings().map(|node| node.text()).collect::<Vec<_>>();
+    assert_eq!(before, vec!["Soup".to_string(), "Name".to_string()]);
+    assert!(dts[0].previous_sibling().is_none());
+    assert!(dts[0].previous_element_sibling().is_none());
+    let last = dts[1].next_element_siblings().last().expect("Couldn't find last dd");
+    assert!(last.next_sibling().is_none());
+    assert_eq!(dts[1].previous_sibling().map(|node| node.is_comment()), Some(true));
+    assert!(soup.get_handle().next_sibling().is_none());
+}
+
+#[test]
+fn document_order_navigation() {
+    let soup = soup();
+    let title = soup.tag("title").find().expect("Couldn't find tag 'title'");
+    let p = title.tag("p").find_next().expect("Couldn't find tag 'p'");
+    assert_eq!(p.get("class"), Some("title".to_string()));
+    let links = title.tag("a")
+                     .find_all_next()
+                     .map(|a| a.get("id").expect("link has no id"))
+                     .collect::<Vec<_>>();
+    assert_eq!(links, vec!["link1".to_string(), "link2".to_string(), "link3".to_string()]);
+    let tillie = soup.attr("id", "link3").find().expect("Couldn't find tag with id 'link3'");
+    let before = tillie.tag("a")
+                       .find_all_previous()
+                       .map(|a| a.get("id").expect("link has no id"))
+                       .collect::<Vec<_>>();
+    assert_eq!(before, vec!["link2".to_string(), "link1".to_string()]);
+    let story = tillie.class("story").find_previous().expect("Couldn't find story");
+    assert_eq!(story.name(), "p");
+    assert!(tillie.tag("title").limit(1).find_all_next().next().is_none());
+    let last = soup.get_handle().next_elements().last().expect("Document is empty");
+    assert!(last.next_element().is_none());
+    let back = last.previous_elements().collect::<Vec<_>>();
+    // every node but `last` itself, plus the document
+    assert_eq!(back.len(), soup.get_handle().next_elements().count());
+    assert!(back.last().expect("No previous elements").is_document());
+    let lacie = soup.string("Lacie").find().expect("Couldn't find 'Lacie'");
+    assert_eq!(lacie.previous_element().map(|node| node.name().to_string()), Some("a".to_string()));
+    assert_eq!(lacie.next_element().map(|node| node.text()), Some(" and\n".to_string()));
+}
+
+#[test]
+fn namespaced_queries() {
+    const SVG: &str = "http://www.w3.org/2000/svg";
+    const MATHML: &str = "http://www.w3.org/1998/Math/MathML";
+    let soup = Soup::new(r##"<a href="/home">Home</a>
+<svg><a xlink:href="#icon"><title>Icon</title></a></svg>
+<math><mi>x</mi></math>"##);
+    let links = soup.tag("a").find_all().collect::<Vec<_>>();
+    assert_eq!(links.len(), 2);
+    let svg_a = soup.tag_ns(SVG, "a").find().expect("Couldn't find svg 'a'");
+    assert_eq!(svg_a.get("xlink:href"), Some("#icon".to_string()));
+    assert_eq!(soup.tag_ns(SVG, true).find_all().count(), 3);
+    let mi = soup.tag_ns(MATHML, "mi").find().expect("Couldn't find tag 'mi'");
+    assert_eq!(mi.namespace(), Some(MATHML));
+    let html_a = soup.tag_ns("http://www.w3.org/1999/xhtml", "a").find().expect("Couldn't find tag 'a'");
+    assert_eq!(html_a.text(), "Home".to_string());
+    assert!(soup.attr_ns("", "href", true).find().is_some());
+    assert!(soup.attr_ns("", "href", "#icon").find().is_none());
+    let icon = soup.attr_ns("http://www.w3.org/1999/xlink", "href", "#icon").find();
+    assert_eq!(icon.and_then(|a| a.namespace().map(String::from)), Some(SVG.to_string()));
+}